@@ -4,6 +4,7 @@ mod store;
 use simulation_args::Error as SimulationArgsError;
 use snafu::{Backtrace, Snafu};
 use std::fmt;
+use std::path::PathBuf;
 
 /// Shorthand for result types returned from the Simulation simulation.
 pub type Result<V, E = Error> = std::result::Result<V, E>;
@@ -14,6 +15,10 @@ pub enum WhatBound {
     ExecutionEnvironmentState,
     ShardBlock(usize),
     Shard,
+    // (shard index, shard slot index) of the block containing the transaction
+    Transaction(usize, usize),
+    // Index within its block of the transaction referencing the EE
+    TransactionExecutionEnvironment(usize),
 }
 
 impl fmt::Display for WhatBound {
@@ -23,6 +28,30 @@ impl fmt::Display for WhatBound {
             WhatBound::ExecutionEnvironmentState => write!(f, "execution environment state"),
             WhatBound::Shard => write!(f, "shard"),
             WhatBound::ShardBlock(shard) => write!(f, "block on shard {}", shard),
+            WhatBound::Transaction(shard, slot) => {
+                write!(f, "transaction in block {} on shard {}", slot, shard)
+            }
+            WhatBound::TransactionExecutionEnvironment(transaction_index) => write!(
+                f,
+                "execution environment referenced by transaction {}",
+                transaction_index
+            ),
+        }
+    }
+}
+
+/// The configurable limits that can be reached in the simulation
+#[derive(Debug)]
+pub enum LimitKind {
+    AddressableIndices,
+    EeState,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitKind::AddressableIndices => write!(f, "addressable indices"),
+            LimitKind::EeState => write!(f, "execution environment states per shard"),
         }
     }
 }
@@ -30,20 +59,320 @@ impl fmt::Display for WhatBound {
 /// Errors arising from the simulation.
 #[derive(Debug, Snafu)]
 pub enum Error {
+    #[snafu(display("unable to convert arguments: {}", source))]
     ArgsError {
         backtrace: Backtrace,
         source: SimulationArgsError,
     },
+    #[snafu(display("shard block is too large: {}", reason))]
+    BlockTooLarge { reason: String },
+    #[snafu(display("execution environment code is {} bytes, max is {}", size, max))]
+    CodeTooLarge { size: usize, max: usize },
+    #[snafu(display("unable to deserialize simulation state: {}", reason))]
+    Deserialize { reason: String },
+    #[snafu(display("execution environment trapped: {}", reason))]
+    ExecutionTrap { reason: String },
+    #[snafu(display("execution environment {} is frozen", ee_index))]
+    Frozen { ee_index: u64 },
+    #[snafu(display("history needed to rebuild the state is unavailable: {}", reason))]
+    HistoryUnavailable { reason: String },
+    #[snafu(display("invalid arguments: {}", reason))]
+    InvalidArgs { reason: String },
+    #[snafu(display("value is not exactly 32 bytes long"))]
     InvalidBytes32,
-    #[snafu(display("{} exceeds max allowable length", what))]
-    MaxLengthExceeded {
-        what: String,
+    #[snafu(display("invalid wasm module: {}", reason))]
+    InvalidWasm { reason: String },
+    #[snafu(display("unable to read {}: {}", path.display(), source))]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
     },
+    #[snafu(display("limit of {} {} reached", limit, kind))]
+    Limit { kind: LimitKind, limit: usize },
+    #[snafu(display("{} exceeds max allowable length", what))]
+    MaxLengthExceeded { what: String },
+    #[snafu(display(
+        "execution environment code doesn't export a `{}` function taking and returning nothing",
+        expected
+    ))]
+    MissingEntrypoint { expected: String },
     #[snafu(display("no {} exists at index: {}", what, index))]
-    OutOfBounds {
-        what: WhatBound,
-        index: usize,
-    },
+    OutOfBounds { what: WhatBound, index: usize },
+    #[snafu(display("transaction rejected by policy: {}", reason))]
+    PolicyRejected { reason: String },
+    #[snafu(display(
+        "preprocessing transaction for execution environment {} failed: {}",
+        ee_index,
+        reason
+    ))]
+    PreprocessingFailed { ee_index: u64, reason: String },
+}
+
+impl Error {
+    /// A stable numeric code identifying the kind of error, for API consumers that shouldn't
+    /// depend on variant names or messages
+    ///
+    /// | Code | Variant               |
+    /// |------|-----------------------|
+    /// | 1    | `ArgsError`           |
+    /// | 2    | `ExecutionTrap`       |
+    /// | 3    | `InvalidBytes32`      |
+    /// | 4    | `Limit`               |
+    /// | 5    | `MaxLengthExceeded`   |
+    /// | 6    | `OutOfBounds`         |
+    /// | 7    | `PreprocessingFailed` |
+    /// | 8    | `InvalidArgs`         |
+    /// | 9    | `Io`                  |
+    /// | 10   | `PolicyRejected`      |
+    /// | 11   | `Frozen`              |
+    /// | 12   | `InvalidWasm`         |
+    /// | 13   | `Deserialize`         |
+    /// | 14   | `CodeTooLarge`        |
+    /// | 15   | `BlockTooLarge`       |
+    /// | 16   | `MissingEntrypoint`   |
+    /// | 17   | `HistoryUnavailable`  |
+    ///
+    /// Codes are never reused; new variants get the next unused code.
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::ArgsError { .. } => 1,
+            Error::BlockTooLarge { .. } => 15,
+            Error::CodeTooLarge { .. } => 14,
+            Error::Deserialize { .. } => 13,
+            Error::ExecutionTrap { .. } => 2,
+            Error::Frozen { .. } => 11,
+            Error::HistoryUnavailable { .. } => 17,
+            Error::InvalidArgs { .. } => 8,
+            Error::InvalidBytes32 => 3,
+            Error::InvalidWasm { .. } => 12,
+            Error::Io { .. } => 9,
+            Error::Limit { .. } => 4,
+            Error::MaxLengthExceeded { .. } => 5,
+            Error::MissingEntrypoint { .. } => 16,
+            Error::OutOfBounds { .. } => 6,
+            Error::PolicyRejected { .. } => 10,
+            Error::PreprocessingFailed { .. } => 7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snafu::ResultExt;
+
+    #[test]
+    fn error_codes_are_stable() {
+        let args_error = Err::<(), _>(SimulationArgsError::InvalidBytes32)
+            .context(ArgsError)
+            .unwrap_err();
+        let errors = vec![
+            (args_error, 1),
+            (
+                Error::ExecutionTrap {
+                    reason: "stack exhausted".to_string(),
+                },
+                2,
+            ),
+            (Error::InvalidBytes32, 3),
+            (
+                Error::Limit {
+                    kind: LimitKind::EeState,
+                    limit: 1,
+                },
+                4,
+            ),
+            (
+                Error::MaxLengthExceeded {
+                    what: "number of execution environments".to_string(),
+                },
+                5,
+            ),
+            (
+                Error::OutOfBounds {
+                    what: WhatBound::Shard,
+                    index: 64,
+                },
+                6,
+            ),
+            (
+                Error::PreprocessingFailed {
+                    ee_index: 0,
+                    reason: "empty transaction data".to_string(),
+                },
+                7,
+            ),
+            (
+                Error::InvalidArgs {
+                    reason: "exactly one of wasm_code or wasm_path must be set".to_string(),
+                },
+                8,
+            ),
+            (
+                Error::Io {
+                    source: std::io::Error::from(std::io::ErrorKind::NotFound),
+                    path: PathBuf::from("missing.wasm"),
+                },
+                9,
+            ),
+            (
+                Error::PolicyRejected {
+                    reason: "data is larger than 2 bytes".to_string(),
+                },
+                10,
+            ),
+            (Error::Frozen { ee_index: 0 }, 11),
+            (
+                Error::InvalidWasm {
+                    reason: "bad magic number".to_string(),
+                },
+                12,
+            ),
+            (
+                Error::Deserialize {
+                    reason: "InvalidByteLength".to_string(),
+                },
+                13,
+            ),
+            (Error::CodeTooLarge { size: 17, max: 16 }, 14),
+            (
+                Error::BlockTooLarge {
+                    reason: "3 transactions exceed the limit of 2".to_string(),
+                },
+                15,
+            ),
+            (
+                Error::MissingEntrypoint {
+                    expected: "main".to_string(),
+                },
+                16,
+            ),
+            (
+                Error::HistoryUnavailable {
+                    reason: "blocks on shard 0 were evicted".to_string(),
+                },
+                17,
+            ),
+        ];
+        for (error, code) in errors.iter() {
+            assert_eq!(error.code(), *code, "unexpected code for {:?}", error);
+        }
+    }
+
+    #[test]
+    fn error_messages_describe_the_error() {
+        let args_error = Err::<(), _>(SimulationArgsError::InvalidBytes32)
+            .context(ArgsError)
+            .unwrap_err();
+        let errors = vec![
+            (
+                args_error,
+                "unable to convert arguments: value is not exactly 32 bytes",
+            ),
+            (
+                Error::BlockTooLarge {
+                    reason: "3 transactions exceed the limit of 2".to_string(),
+                },
+                "shard block is too large: 3 transactions",
+            ),
+            (
+                Error::CodeTooLarge { size: 17, max: 16 },
+                "code is 17 bytes, max is 16",
+            ),
+            (
+                Error::Deserialize {
+                    reason: "InvalidByteLength".to_string(),
+                },
+                "unable to deserialize simulation state: InvalidByteLength",
+            ),
+            (
+                Error::ExecutionTrap {
+                    reason: "stack exhausted".to_string(),
+                },
+                "trapped: stack exhausted",
+            ),
+            (
+                Error::Frozen { ee_index: 3 },
+                "execution environment 3 is frozen",
+            ),
+            (
+                Error::HistoryUnavailable {
+                    reason: "blocks on shard 0 were evicted".to_string(),
+                },
+                "history needed to rebuild the state is unavailable: blocks on shard 0",
+            ),
+            (
+                Error::InvalidArgs {
+                    reason: "exactly one of wasm_code or wasm_path must be set".to_string(),
+                },
+                "invalid arguments: exactly one",
+            ),
+            (Error::InvalidBytes32, "not exactly 32 bytes"),
+            (
+                Error::InvalidWasm {
+                    reason: "bad magic number".to_string(),
+                },
+                "invalid wasm module: bad magic number",
+            ),
+            (
+                Error::Io {
+                    source: std::io::Error::from(std::io::ErrorKind::NotFound),
+                    path: PathBuf::from("missing.wasm"),
+                },
+                "unable to read missing.wasm",
+            ),
+            (
+                Error::Limit {
+                    kind: LimitKind::EeState,
+                    limit: 1,
+                },
+                "limit of 1 execution environment states per shard reached",
+            ),
+            (
+                Error::MaxLengthExceeded {
+                    what: "number of execution environments".to_string(),
+                },
+                "number of execution environments exceeds max allowable length",
+            ),
+            (
+                Error::MissingEntrypoint {
+                    expected: "main".to_string(),
+                },
+                "doesn't export a `main` function",
+            ),
+            (
+                Error::OutOfBounds {
+                    what: WhatBound::Shard,
+                    index: 64,
+                },
+                "no shard exists at index: 64",
+            ),
+            (
+                Error::PolicyRejected {
+                    reason: "data is larger than 2 bytes".to_string(),
+                },
+                "rejected by policy: data is larger",
+            ),
+            (
+                Error::PreprocessingFailed {
+                    ee_index: 0,
+                    reason: "empty transaction data".to_string(),
+                },
+                "execution environment 0 failed: empty transaction data",
+            ),
+        ];
+        for (error, message) in errors.iter() {
+            assert!(
+                error.to_string().contains(message),
+                "unexpected message for {:?}: {}",
+                error,
+                error
+            );
+        }
+    }
 }
 
-pub use crate::simulation::Simulation;
+pub use crate::simulation::{
+    ReferenceError, ReferenceLocation, Simulation, SimulationBuilder, Snapshot,
+    TransactionPreprocessor, TransactionValidator,
+};
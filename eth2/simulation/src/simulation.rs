@@ -1,13 +1,125 @@
 use crate::store::Store;
-use crate::{ArgsError, Error, Result, WhatBound};
+use crate::{ArgsError, Error, Io, LimitKind, Result, WhatBound};
+use eth2_hashing::hash;
 use ewasm::{Execute, RootRuntime};
 use simulation_args;
 use snafu::ResultExt;
+use ssz::Encode;
+use ssz_types::VariableList;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::convert::TryFrom;
+use std::fmt::{self, Write};
+use std::fs;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use typenum::Unsigned;
 use types::eth_spec::EthSpec;
 use types::execution_environment::ExecutionEnvironment;
 use types::shard_block::ShardBlock;
-use types::slot_epoch_root::{Root, Shard};
+use types::shard_state::{KeyedEeState, ShardState};
+use types::shard_transaction::{Condition, ShardTransaction};
+use types::slot_epoch_root::{EeIndex, Root, Shard};
+
+/// Transforms transaction data before it is passed to the EE code
+/// This allows scenarios to adapt to EE calling conventions, eg. by prepending a selector.
+/// Returning an error rejects the transaction, which fails the whole shard block.
+/// It must be `Sync` so that a simulation can be read from several threads at once.
+pub trait TransactionPreprocessor: fmt::Debug + Send + Sync {
+    fn preprocess(&self, ee_index: u64, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Decides whether a transaction may be executed, eg. to enforce blocklists or size policies
+/// Validation sees the transaction data before any preprocessing. Rejecting a transaction
+/// (typically with `Error::PolicyRejected`) fails the whole shard block.
+/// It must be `Sync` so that a simulation can be read from several threads at once.
+pub trait TransactionValidator: fmt::Debug + Send + Sync {
+    fn validate(&self, ee_index: u64, data: &[u8]) -> Result<()>;
+}
+
+/// Where a dangling EE reference was found by `Simulation::audit_ee_references`
+#[derive(Debug, PartialEq)]
+pub enum ReferenceLocation {
+    // A shard holds a state for an EE that doesn't exist
+    EeState,
+    // A shard holds a keyed state for an EE that doesn't exist
+    KeyedEeState {
+        state_key: u32,
+    },
+    // A stored transaction targets an EE that doesn't exist
+    Transaction {
+        shard_slot_index: u64,
+        transaction_index: u64,
+    },
+    // A stored transaction's condition reads the state of an EE that doesn't exist
+    TransactionCondition {
+        shard_slot_index: u64,
+        transaction_index: u64,
+    },
+}
+
+/// A reference to an EE index that doesn't exist
+#[derive(Debug, PartialEq)]
+pub struct ReferenceError {
+    pub shard_index: u64,
+    pub location: ReferenceLocation,
+    pub ee_index: u64,
+}
+
+/// Counts of the shard blocks evicted to stay within the memory budget
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EvictionStats {
+    pub blocks_evicted: u64,
+    pub bytes_evicted: u64,
+}
+
+/// Running totals of the work done by the simulation, for performance tuning
+#[derive(Debug, Default)]
+struct Metrics {
+    execution_environments_created: u64,
+    shard_blocks_created: u64,
+    transactions_executed: u64,
+    // Args that couldn't be converted into the simulation's internal types
+    decode_errors: u64,
+}
+
+/// The result of a create operation, kept so that retrying it with the same idempotency key
+/// doesn't repeat it
+#[derive(Clone, Debug)]
+enum IdempotentResult {
+    ExecutionEnvironment(u64),
+    ShardBlock(simulation_args::ShardBlockResult),
+}
+
+/// The results of the most recently used idempotency keys, least recently used first
+#[derive(Debug, Default)]
+struct IdempotentResults {
+    results: VecDeque<(String, IdempotentResult)>,
+}
+
+impl IdempotentResults {
+    /// Get the result of an earlier operation with `key`, marking the key as recently used
+    fn get(&mut self, key: &Option<String>) -> Option<IdempotentResult> {
+        let key = key.as_ref()?;
+        let position = self.results.iter().position(|(k, _)| k == key)?;
+        let entry = self.results.remove(position)?;
+        let result = entry.1.clone();
+        self.results.push_back(entry);
+        Some(result)
+    }
+
+    /// Remember the result of an operation with `key`, forgetting the least recently used key if
+    /// there are too many
+    fn insert(&mut self, key: Option<String>, result: IdempotentResult) {
+        if let Some(key) = key {
+            self.results.push_back((key, result));
+            if self.results.len() > MAX_IDEMPOTENCY_KEYS {
+                self.results.pop_front();
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Simulation<T>
@@ -15,29 +127,456 @@ where
     T: EthSpec,
 {
     store: Store<T>,
+    // Maximum number of EE states each shard may hold (unlimited if None)
+    max_ee_states: Option<usize>,
+    // Maximum size of the code of each EE, in bytes
+    max_ee_code_bytes: usize,
+    // Maximum number of transactions in each shard block (unlimited if None)
+    max_transactions_per_block: Option<usize>,
+    // Maximum size of the data of each transaction, in bytes (unlimited if None)
+    max_transaction_data_bytes: Option<usize>,
+    // Applied to the data of every transaction before execution (data is passed as-is if None)
+    transaction_preprocessor: Option<Box<dyn TransactionPreprocessor>>,
+    // Checked before executing every transaction (all transactions are accepted if None)
+    transaction_validator: Option<Box<dyn TransactionValidator>>,
+    // Maximum number of bytes of shard blocks to keep (unlimited if None)
+    memory_budget: Option<usize>,
+    eviction_stats: EvictionStats,
+    // Maximum number of historical state roots to keep
+    max_state_roots: usize,
+    metrics: Metrics,
+    // Results of recent creates, so they aren't repeated when retried with the same key
+    idempotent_results: IdempotentResults,
+}
+
+/// The number of historical state roots kept by default
+const DEFAULT_MAX_STATE_ROOTS: usize = 256;
+
+/// The maximum size of EE code allowed by default (1 MiB)
+const DEFAULT_MAX_EE_CODE_BYTES: usize = 1 << 20;
+
+/// The number of idempotency keys whose results are kept
+const MAX_IDEMPOTENCY_KEYS: usize = 1024;
+
+/// Configures a `Simulation` before it is created
+/// Each method sets the same option as the matching `Simulation::set_*` method, and anything not
+/// set keeps the default used by `Simulation::new`.
+#[derive(Debug)]
+pub struct SimulationBuilder<T>
+where
+    T: EthSpec,
+{
+    simulation: Simulation<T>,
+}
+
+impl<T: EthSpec> SimulationBuilder<T> {
+    pub fn max_ee_states(mut self, max_ee_states: usize) -> Self {
+        self.simulation.set_max_ee_states(Some(max_ee_states));
+        self
+    }
+
+    pub fn max_ee_code_bytes(mut self, max_ee_code_bytes: usize) -> Self {
+        self.simulation.set_max_ee_code_bytes(max_ee_code_bytes);
+        self
+    }
+
+    pub fn max_transactions_per_block(mut self, max_transactions_per_block: usize) -> Self {
+        self.simulation
+            .set_max_transactions_per_block(Some(max_transactions_per_block));
+        self
+    }
+
+    pub fn max_transaction_data_bytes(mut self, max_transaction_data_bytes: usize) -> Self {
+        self.simulation
+            .set_max_transaction_data_bytes(Some(max_transaction_data_bytes));
+        self
+    }
+
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.simulation.set_memory_budget(Some(memory_budget));
+        self
+    }
+
+    pub fn max_state_roots(mut self, max_state_roots: usize) -> Self {
+        self.simulation.set_max_state_roots(max_state_roots);
+        self
+    }
+
+    pub fn transaction_preprocessor(
+        mut self,
+        transaction_preprocessor: Box<dyn TransactionPreprocessor>,
+    ) -> Self {
+        self.simulation
+            .set_transaction_preprocessor(Some(transaction_preprocessor));
+        self
+    }
+
+    pub fn transaction_validator(
+        mut self,
+        transaction_validator: Box<dyn TransactionValidator>,
+    ) -> Self {
+        self.simulation
+            .set_transaction_validator(Some(transaction_validator));
+        self
+    }
+
+    pub fn build(self) -> Simulation<T> {
+        self.simulation
+    }
+}
+
+/// A read-only copy of a simulation's state at the time it was taken, see `Simulation::snapshot`
+/// Cloning a snapshot is cheap and clones can be read from several threads, without waiting for
+/// (or seeing) changes made to the live simulation.
+#[derive(Clone, Debug)]
+pub struct Snapshot<T>
+where
+    T: EthSpec,
+{
+    simulation: Arc<Simulation<T>>,
+}
+
+impl<T: EthSpec> Snapshot<T> {
+    /// Same as `Simulation::get_execution_environment`, as of when the snapshot was taken
+    pub fn get_execution_environment(
+        &self,
+        a: simulation_args::GetExecutionEnvironment,
+    ) -> Result<simulation_args::ExecutionEnvironment> {
+        self.simulation.get_execution_environment(a)
+    }
+
+    /// Same as `Simulation::get_shard_block`, as of when the snapshot was taken
+    pub fn get_shard_block(
+        &self,
+        a: simulation_args::GetShardBlock,
+    ) -> Result<simulation_args::ShardBlock> {
+        self.simulation.get_shard_block(a)
+    }
+
+    /// Same as `Simulation::simulation_state`, as of when the snapshot was taken
+    pub fn simulation_state(
+        &self,
+        a: simulation_args::GetSimulationState,
+    ) -> simulation_args::SimulationState {
+        self.simulation.simulation_state(a)
+    }
 }
 
 impl<T: EthSpec> Simulation<T> {
+    /// Start configuring a new simulation, for when the defaults of `new` aren't enough
+    pub fn builder() -> SimulationBuilder<T> {
+        SimulationBuilder {
+            simulation: Self::new(),
+        }
+    }
+
     pub fn new() -> Self {
-        Self {
+        let mut simulation = Self {
             store: Store::new(),
+            max_ee_states: None,
+            max_ee_code_bytes: DEFAULT_MAX_EE_CODE_BYTES,
+            max_transactions_per_block: None,
+            max_transaction_data_bytes: None,
+            transaction_preprocessor: None,
+            transaction_validator: None,
+            memory_budget: None,
+            eviction_stats: EvictionStats::default(),
+            max_state_roots: DEFAULT_MAX_STATE_ROOTS,
+            metrics: Metrics::default(),
+            idempotent_results: IdempotentResults::default(),
+        };
+
+        // The genesis state is the state as of operation 0
+        let state_root = simulation.state_root();
+        simulation.store.state_roots.push_back((0, state_root));
+        simulation
+    }
+
+    /// Limit how many EE states each shard may hold, or remove the limit with `None`
+    /// Existing EE states can still be updated once the limit is reached, but creating an EE
+    /// (which adds a state to every shard) will fail. Keyed states count towards the limit of
+    /// their shard, so writing a new state key past the limit fails too.
+    pub fn set_max_ee_states(&mut self, max_ee_states: Option<usize>) {
+        self.max_ee_states = max_ee_states;
+    }
+
+    /// Limit how many bytes of code each EE may have
+    /// Creating or updating an EE with larger code fails with `Error::CodeTooLarge`. EEs that
+    /// already exist are unaffected.
+    pub fn set_max_ee_code_bytes(&mut self, max_ee_code_bytes: usize) {
+        self.max_ee_code_bytes = max_ee_code_bytes;
+    }
+
+    /// Limit how many transactions each new shard block may have, or remove the limit with `None`
+    /// Creating a shard block with more transactions fails with `Error::BlockTooLarge`.
+    pub fn set_max_transactions_per_block(&mut self, max_transactions_per_block: Option<usize>) {
+        self.max_transactions_per_block = max_transactions_per_block;
+    }
+
+    /// Limit how many bytes of data each transaction in a new shard block may have, or remove the
+    /// limit with `None`
+    /// Creating a shard block with a larger transaction fails with `Error::BlockTooLarge`.
+    pub fn set_max_transaction_data_bytes(&mut self, max_transaction_data_bytes: Option<usize>) {
+        self.max_transaction_data_bytes = max_transaction_data_bytes;
+    }
+
+    /// Limit how many bytes of shard blocks are kept, or remove the limit with `None`
+    /// Once the limit is exceeded, the oldest blocks of the shards with the most blocks are
+    /// evicted until the stored blocks fit again. EE states, shard tip hashes, and the indices of
+    /// the remaining blocks are unaffected, since they don't depend on old blocks being kept, but
+    /// evicted blocks can no longer be fetched or replayed.
+    /// The budget only covers shard blocks (measured by their SSZ encoding), which are the only
+    /// part of the simulation that grows without bound.
+    pub fn set_memory_budget(&mut self, memory_budget: Option<usize>) {
+        self.memory_budget = memory_budget;
+        self.evict_to_memory_budget();
+    }
+
+    /// Set how many historical state roots are kept (at least one, the current state root, is
+    /// always kept)
+    pub fn set_max_state_roots(&mut self, max_state_roots: usize) {
+        self.max_state_roots = max_state_roots.max(1);
+        while self.store.state_roots.len() > self.max_state_roots {
+            self.store.state_roots.pop_front();
+        }
+    }
+
+    /// Get counts of the shard blocks evicted so far to stay within the memory budget
+    pub fn eviction_stats(&self) -> EvictionStats {
+        self.eviction_stats.clone()
+    }
+
+    /// Serialize the simulated chains so they can be restored later with `import_state`
+    /// This covers every EE, shard state, and shard block (along with the bookkeeping needed to
+    /// keep adding blocks) and the EE audit log, but not configuration, metrics, or the history of
+    /// state roots.
+    pub fn export_state(&self) -> Vec<u8> {
+        self.store.to_bytes()
+    }
+
+    /// Take a read-only copy of the current state, which later changes to the simulation don't
+    /// affect
+    /// The state is copied when the snapshot is taken, so this costs about as much as the state is
+    /// large. Configuration isn't part of the snapshot.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let mut simulation = Self::new();
+        simulation.store = self.store.clone();
+        Snapshot {
+            simulation: Arc::new(simulation),
+        }
+    }
+
+    /// Create a simulation from the output of `export_state`, with the default configuration
+    /// The imported state is recorded as the state as of operation 0.
+    pub fn import_state(bytes: &[u8]) -> Result<Self> {
+        let store = Store::from_bytes(bytes).map_err(|reason| Error::Deserialize { reason })?;
+        let mut simulation = Self::new();
+        simulation.store = store;
+        simulation.store.state_roots.clear();
+        simulation.record_state_root();
+        Ok(simulation)
+    }
+
+    /// Create a simulation that starts with the EEs in `genesis`, with the default configuration
+    /// The code of every EE must be a valid WASM module, and the EEs are all created at once, so
+    /// if any of them is invalid no simulation is created. The seeded EEs are recorded as the
+    /// state as of operation 0.
+    pub fn from_genesis(genesis: simulation_args::Genesis) -> Result<Self> {
+        let mut simulation = Self::new();
+        simulation.create_execution_environments(simulation_args::CreateExecutionEnvironments {
+            execution_environments: genesis.execution_environments,
+        })?;
+        simulation.store.state_roots.clear();
+        simulation.record_state_root();
+        Ok(simulation)
+    }
+
+    /// Re-apply every operation in the log, in order, stopping at the first one that fails
+    /// Replaying the log of a simulation against a new one reproduces it.
+    pub fn replay(&mut self, log: &simulation_args::OperationLog) -> Result<()> {
+        for operation in log.operations.iter().cloned() {
+            match operation {
+                simulation_args::LoggedOperation::AdvanceSlot(a) => {
+                    self.advance_slot(a);
+                }
+                simulation_args::LoggedOperation::CopyEeState(a) => self.copy_ee_state(a)?,
+                simulation_args::LoggedOperation::CreateExecutionEnvironment(a) => {
+                    self.create_execution_environment(a)?;
+                }
+                simulation_args::LoggedOperation::CreateExecutionEnvironments(a) => {
+                    self.create_execution_environments(a)?;
+                }
+                simulation_args::LoggedOperation::CreateShardBlock(a) => {
+                    self.create_shard_block(a)?;
+                }
+                simulation_args::LoggedOperation::DeleteExecutionEnvironment(a) => {
+                    self.delete_execution_environment(a)?
+                }
+                simulation_args::LoggedOperation::Reset(a) => {
+                    self.reset(a);
+                }
+                simulation_args::LoggedOperation::UpdateExecutionEnvironment(a) => {
+                    self.update_execution_environment(a)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the running totals of EEs created, shard blocks created, transactions executed, and
+    /// args that failed to decode since the simulation started
+    /// Only successful operations count towards the first three, so a shard block that fails
+    /// part way through doesn't count any of its transactions as executed.
+    pub fn metrics(&self, _: simulation_args::GetMetrics) -> simulation_args::MetricsSnapshot {
+        simulation_args::MetricsSnapshot {
+            execution_environments_created: self.metrics.execution_environments_created,
+            shard_blocks_created: self.metrics.shard_blocks_created,
+            transactions_executed: self.metrics.transactions_executed,
+            decode_errors: self.metrics.decode_errors,
+        }
+    }
+
+    /// Count the error if decoding args into internal types failed, then pass the result on
+    fn count_decode_error<V>(&mut self, res: Result<V>) -> Result<V> {
+        if let Err(Error::ArgsError { .. }) = res {
+            self.metrics.decode_errors += 1;
         }
+        res
+    }
+
+    /// Set the preprocessor applied to transaction data before execution, or remove it with `None`
+    pub fn set_transaction_preprocessor(
+        &mut self,
+        transaction_preprocessor: Option<Box<dyn TransactionPreprocessor>>,
+    ) {
+        self.transaction_preprocessor = transaction_preprocessor;
+    }
+
+    /// Set the validator checked before executing each transaction, or remove it with `None`
+    pub fn set_transaction_validator(
+        &mut self,
+        transaction_validator: Option<Box<dyn TransactionValidator>>,
+    ) {
+        self.transaction_validator = transaction_validator;
     }
 
     /// Add a new execution environment, return EE index
+    /// The EE code must be a WASM module that exports the entrypoint called by the runtime.
+    /// Retrying with the idempotency key of a recent create returns the index of the EE it created
+    /// instead of adding another one.
     pub fn create_execution_environment(
         &mut self,
         a: simulation_args::CreateExecutionEnvironment,
     ) -> Result<u64> {
-        // Create internal EE struct from args
-        let ee = ExecutionEnvironment::try_from(a.ee).context(ArgsError)?;
+        let idempotency_key = a.idempotency_key.clone();
+        match self.idempotent_results.get(&idempotency_key) {
+            Some(IdempotentResult::ExecutionEnvironment(ee_index)) => return Ok(ee_index),
+            Some(_) => return Err(reused_idempotency_key()),
+            None => {}
+        }
+
+        let ee = decode_execution_environment(a);
+        let ee = self.count_decode_error(ee)?;
+        self.check_code_size(&*ee.wasm_code)?;
+        check_entrypoint(&*ee.wasm_code)?;
+        self.check_room_for_execution_environments(1)?;
+        let ee_index = self.insert_execution_environment(ee)?;
+        self.record_state_root();
+
+        self.idempotent_results.insert(
+            idempotency_key,
+            IdempotentResult::ExecutionEnvironment(ee_index),
+        );
+        Ok(ee_index)
+    }
+
+    /// Add several new execution environments at once, return their EE indices in order
+    /// Every EE is decoded and checked against the limits before any is added, so if one is
+    /// invalid none of them are created.
+    pub fn create_execution_environments(
+        &mut self,
+        a: simulation_args::CreateExecutionEnvironments,
+    ) -> Result<Vec<u64>> {
+        let ees = a
+            .execution_environments
+            .into_iter()
+            .map(|ee| {
+                decode_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>();
+        let ees = self.count_decode_error(ees)?;
+        for ee in ees.iter() {
+            self.check_code_size(&*ee.wasm_code)?;
+            check_entrypoint(&*ee.wasm_code)?;
+        }
+        self.check_room_for_execution_environments(ees.len())?;
+
+        let mut ee_indices = Vec::with_capacity(ees.len());
+        for ee in ees {
+            ee_indices.push(self.insert_execution_environment(ee)?);
+        }
+        self.record_state_root();
+
+        Ok(ee_indices)
+    }
+
+    /// Make sure EE code is within the maximum code size
+    fn check_code_size(&self, wasm_code: &[u8]) -> Result<()> {
+        if wasm_code.len() > self.max_ee_code_bytes {
+            return Err(Error::CodeTooLarge {
+                size: wasm_code.len(),
+                max: self.max_ee_code_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Make sure `num_new_ees` more EEs can be added without exceeding any limit
+    fn check_room_for_execution_environments(&self, num_new_ees: usize) -> Result<()> {
+        let num_ees = self.store.current_beacon_state.execution_environments.len();
+
+        // Every shard holds one state per EE along with its keyed states, so adding an EE must
+        // not take the fullest shard past the EE state limit
+        if let Some(limit) = self.max_ee_states {
+            let max_keyed_states = self
+                .store
+                .current_beacon_state
+                .shard_states
+                .iter()
+                .map(|shard_state| shard_state.keyed_execution_environment_states.len())
+                .max()
+                .unwrap_or(0);
+            if num_ees + num_new_ees + max_keyed_states > limit {
+                return Err(Error::Limit {
+                    kind: LimitKind::EeState,
+                    limit,
+                });
+            }
+        }
+
+        if num_ees + num_new_ees > T::MaxExecutionEnvironments::to_usize() {
+            return Err(Error::MaxLengthExceeded {
+                what: format!("number of execution environments"),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Add a decoded EE to the beacon chain, with its initial state on every shard
+    fn insert_execution_environment(&mut self, ee: ExecutionEnvironment<T>) -> Result<u64> {
         let cloned_initial_state = ee.initial_state.clone();
+        let code_hash = hash_bytes(&*ee.wasm_code);
 
         // Add EE code to beacon chain
         self.store
             .current_beacon_state
             .execution_environments
-            .push(ee)
+            .push(Some(ee))
             .map_err(|_| Error::MaxLengthExceeded {
                 what: format!("number of execution environments"),
             })?;
@@ -60,62 +599,211 @@ impl<T: EthSpec> Simulation<T> {
         }
 
         let ee_index = self.store.current_beacon_state.execution_environments.len() - 1;
+
+        self.record_ee_audit_entry(
+            ee_index as u64,
+            simulation_args::EeAuditAction::Create,
+            code_hash,
+        );
+        self.metrics.execution_environments_created += 1;
+
         Ok(ee_index as u64)
     }
 
+    /// Freeze an EE so that new transactions can't reference it
+    /// The EE and its states can still be queried. Freezing an already frozen EE has no effect.
+    pub fn freeze_execution_environment(&mut self, ee_index: u64) -> Result<()> {
+        let code_hash = self.ee_code_hash(ee_index)?;
+        if self.store.frozen_ees.insert(EeIndex::new(ee_index)) {
+            self.record_ee_audit_entry(ee_index, simulation_args::EeAuditAction::Freeze, code_hash);
+        }
+        Ok(())
+    }
+
+    /// Allow new transactions to reference a frozen EE again
+    /// Unfreezing an EE that isn't frozen has no effect.
+    pub fn unfreeze_execution_environment(&mut self, ee_index: u64) -> Result<()> {
+        let code_hash = self.ee_code_hash(ee_index)?;
+        if self.store.frozen_ees.remove(&EeIndex::new(ee_index)) {
+            self.record_ee_audit_entry(
+                ee_index,
+                simulation_args::EeAuditAction::Unfreeze,
+                code_hash,
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete an EE so that it can no longer be queried or referenced by new transactions
+    /// The EE is replaced by a tombstone rather than removed, so the indices of later EEs (and the
+    /// `ee_index` of transactions referring to them) stay valid. Its index isn't reused, and its
+    /// states are left in place on every shard.
+    pub fn delete_execution_environment(
+        &mut self,
+        a: simulation_args::DeleteExecutionEnvironment,
+    ) -> Result<()> {
+        let code_hash = self.ee_code_hash(a.execution_environment_index)?;
+        let ee_index = to_index(a.execution_environment_index)?;
+        self.store.current_beacon_state.execution_environments[ee_index] = None;
+        self.store
+            .frozen_ees
+            .remove(&EeIndex::new(a.execution_environment_index));
+
+        self.record_ee_audit_entry(
+            a.execution_environment_index,
+            simulation_args::EeAuditAction::Delete,
+            code_hash,
+        );
+        self.record_state_root();
+
+        Ok(())
+    }
+
+    /// Replace the code of an EE, keeping its index so existing transactions still refer to it
+    /// The EE's states on every shard are kept, as is its initial state. Transactions for the EE
+    /// can't be replayed afterwards, since they may have run with the old code.
+    pub fn update_execution_environment(
+        &mut self,
+        a: simulation_args::UpdateExecutionEnvironment,
+    ) -> Result<()> {
+        let ee_index = to_index(a.execution_environment_index)?;
+        let new_ee =
+            ExecutionEnvironment::<T>::try_from(a.execution_environment).context(ArgsError);
+        let new_ee = self.count_decode_error(new_ee)?;
+        self.check_code_size(&*new_ee.wasm_code)?;
+        check_entrypoint(&*new_ee.wasm_code)?;
+        let ee = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get_mut(ee_index)
+            .and_then(Option::as_mut)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+        ee.wasm_code = new_ee.wasm_code;
+        let code_hash = hash_bytes(&*ee.wasm_code);
+        self.store
+            .updated_ees
+            .insert(EeIndex::new(a.execution_environment_index));
+
+        self.record_ee_audit_entry(
+            a.execution_environment_index,
+            simulation_args::EeAuditAction::Update,
+            code_hash,
+        );
+        self.record_state_root();
+
+        Ok(())
+    }
+
     /// Add a new shard block containing a list of transactions that need to be executed
-    /// Execute all transactions on the appropriate shards / EEs, return ShardBlock index
-    pub fn create_shard_block(&mut self, a: simulation_args::CreateShardBlock) -> Result<u64> {
-        // Get the specified ShardState (if it exists)
-        let shard_index = a.shard_index as usize;
+    /// Execute all transactions on the appropriate shards / EEs, return the ShardBlock index along
+    /// with the pre and post state of each transaction
+    /// Retrying with the idempotency key of a recent create returns the result of the original
+    /// block instead of adding another one.
+    pub fn create_shard_block(
+        &mut self,
+        a: simulation_args::CreateShardBlock,
+    ) -> Result<simulation_args::ShardBlockResult> {
+        let idempotency_key = a.idempotency_key.clone();
+        match self.idempotent_results.get(&idempotency_key) {
+            Some(IdempotentResult::ShardBlock(shard_block_result)) => {
+                return Ok(shard_block_result)
+            }
+            Some(_) => return Err(reused_idempotency_key()),
+            None => {}
+        }
+
+        let shard_block_result = self.add_shard_block(a)?;
+        self.evict_to_memory_budget();
+        self.record_state_root();
+
+        self.idempotent_results.insert(
+            idempotency_key,
+            IdempotentResult::ShardBlock(shard_block_result.clone()),
+        );
+        Ok(shard_block_result)
+    }
+
+    /// Execute and store a new shard block without enforcing the memory budget
+    /// Blocks are applied atomically: transactions are executed against a staged copy of the shard
+    /// state, which only replaces the shard state once every transaction has succeeded. If any
+    /// transaction fails, neither the shard state nor the stored blocks change.
+    fn add_shard_block(
+        &mut self,
+        a: simulation_args::CreateShardBlock,
+    ) -> Result<simulation_args::ShardBlockResult> {
+        // Get a staged copy of the specified ShardState (if it exists)
+        let shard_index = to_index(a.shard_index)?;
         let shard = Shard::new(a.shard_index);
-        let shard_state = self
+        let mut shard_state = self.shard_state(a.shard_index)?.clone();
+
+        self.check_block_size(&a.shard_block)?;
+
+        // Create the internal shard block from args
+        let shard_block = ShardBlock::try_from(a.shard_block).context(ArgsError);
+        let mut shard_block: ShardBlock<T> = self.count_decode_error(shard_block)?;
+
+        // Link the block to the newest block already on the shard
+        let parent_hash = self
             .store
-            .current_beacon_state
-            .shard_states
-            .get_mut(shard_index)
+            .latest_block_hashes
+            .get(&shard)
             .ok_or(Error::OutOfBounds {
-                what: WhatBound::Shard,
                 index: shard_index,
+                what: WhatBound::Shard,
             })?;
+        shard_block.parent_hash = Root::from(*parent_hash);
 
-        // Create the internal shard block from args
-        let shard_block: ShardBlock<T> = ShardBlock::try_from(a.shard_block).context(ArgsError)?;
+        // Reject the block up front if any transaction references an EE that doesn't exist, even
+        // one that would be skipped, so that stored blocks only ever reference existing EEs
+        self.check_transaction_ees(&shard_block)?;
 
-        // Execute transactions and update shard state for all transactions
-        for transaction in shard_block.transactions.iter() {
-            // Get the specified EE (if it exists)
-            let ee_index: usize = transaction.ee_index.into();
-            let execution_environment = self
-                .store
-                .current_beacon_state
-                .execution_environments
-                .get(ee_index)
-                .ok_or(Error::OutOfBounds {
-                    what: WhatBound::ExecutionEnvironment,
-                    index: ee_index,
-                })?;
+        // Execute transactions and update the staged shard state for all transactions
+        let mut skipped_transactions = Vec::new();
+        let mut transaction_results = Vec::with_capacity(shard_block.transactions.len());
+        for (transaction_index, transaction) in shard_block.transactions.iter().enumerate() {
+            let receipt = self.apply_transaction(transaction, &mut shard_state)?;
+            if receipt.skipped {
+                skipped_transactions.push(transaction_index as u64);
+            }
+            transaction_results.push(receipt);
+        }
 
-            // Get the current EE state
-            let pre_state = shard_state
-                .execution_environment_states
-                .get(ee_index)
-                .ok_or(Error::OutOfBounds {
-                    what: WhatBound::ExecutionEnvironmentState,
-                    index: ee_index,
-                })?;
+        // Every transaction succeeded, so commit the staged shard state
+        self.store.current_beacon_state.shard_states[shard_index] = shard_state;
+        self.metrics.shard_blocks_created += 1;
+        self.metrics.transactions_executed +=
+            (transaction_results.len() - skipped_transactions.len()) as u64;
 
-            // Create a new runtime with the EE code, transaction data, and pre state root
-            let wasm_code: &[u8] = &*execution_environment.wasm_code;
-            let data: &[u8] = &*transaction.data;
-            let pre_state: [u8; 32] = pre_state.clone().into();
-            let post_root = RootRuntime::new(wasm_code, data, pre_state).execute();
+        // Extend the shard's tip hash with the new block
+        let tip_hash = self
+            .store
+            .shard_tip_hashes
+            .get_mut(&shard)
+            .ok_or(Error::OutOfBounds {
+                index: shard_index,
+                what: WhatBound::Shard,
+            })?;
+        *tip_hash = next_tip_hash(tip_hash, &shard_block);
+        self.store
+            .latest_block_hashes
+            .insert(shard, block_hash(&shard_block));
 
-            // Update shard state with new root
-            shard_state.execution_environment_states[ee_index] = Root::from(post_root);
-        }
+        // Record which transactions were skipped
+        self.store
+            .skipped_transactions_by_shard
+            .get_mut(&shard)
+            .ok_or(Error::OutOfBounds {
+                index: shard_index,
+                what: WhatBound::Shard,
+            })?
+            .push(skipped_transactions);
 
         // Add shard block to store for later access
+        self.store.block_bytes += shard_block_size(&shard_block);
         let shard_blocks_for_shard =
             self.store
                 .shard_blocks_by_shard
@@ -126,52 +814,557 @@ impl<T: EthSpec> Simulation<T> {
                 })?;
         shard_blocks_for_shard.push(shard_block);
 
-        // Return the slot of the newly added shard block
-        Ok((shard_blocks_for_shard.len() - 1) as u64)
+        // Return the slot of the newly added shard block, counting any evicted blocks before it
+        let num_evicted = self.store.num_evicted_blocks_by_shard[&shard];
+        Ok(simulation_args::ShardBlockResult {
+            block_index: (num_evicted + shard_blocks_for_shard.len() - 1) as u64,
+            transaction_results,
+        })
     }
 
-    /// Get an EE that was previously added
-    pub fn get_execution_environment(
-        &self,
-        a: simulation_args::GetExecutionEnvironment,
-    ) -> Result<simulation_args::ExecutionEnvironment> {
-        let ee_index = a.ee_index as usize;
-        let ee = self
-            .store
-            .current_beacon_state
-            .execution_environments
-            .get(ee_index)
-            .ok_or(Error::OutOfBounds {
-                what: WhatBound::ExecutionEnvironment,
-                index: ee_index,
-            })?;
-        Ok(ee.clone().into())
+    /// Evict the oldest blocks of the shards with the most blocks until the stored blocks fit in
+    /// the memory budget
+    fn evict_to_memory_budget(&mut self) {
+        let memory_budget = match self.memory_budget {
+            Some(memory_budget) => memory_budget,
+            None => return,
+        };
+
+        while self.store.block_bytes > memory_budget {
+            // Prefer the lowest shard index among equally long shards, to keep eviction
+            // deterministic
+            let shard = self
+                .store
+                .shard_blocks_by_shard
+                .iter()
+                .filter(|(_, shard_blocks)| !shard_blocks.is_empty())
+                .max_by_key(|(shard, shard_blocks)| (shard_blocks.len(), Reverse(**shard)))
+                .map(|(shard, _)| *shard);
+            let shard = match shard {
+                Some(shard) => shard,
+                None => break,
+            };
+
+            let evicted_block = self
+                .store
+                .shard_blocks_by_shard
+                .get_mut(&shard)
+                .map(|shard_blocks| shard_blocks.remove(0));
+            if let Some(skipped_transactions) =
+                self.store.skipped_transactions_by_shard.get_mut(&shard)
+            {
+                skipped_transactions.remove(0);
+            }
+            *self
+                .store
+                .num_evicted_blocks_by_shard
+                .entry(shard)
+                .or_insert(0) += 1;
+
+            let evicted_bytes = evicted_block.map_or(0, |b| shard_block_size(&b));
+            self.store.block_bytes -= evicted_bytes;
+            self.eviction_stats.blocks_evicted += 1;
+            self.eviction_stats.bytes_evicted += evicted_bytes as u64;
+        }
     }
 
-    /// Get the current state of an execution environment on a shard
-    pub fn get_execution_environment_state(
-        &self,
-        a: simulation_args::GetExecutionEnvironmentState,
-    ) -> Result<[u8; 32]> {
-        let ee_index = a.ee_index as usize;
-        let shard_index = a.shard_index as usize;
-        let shard_state = self
-            .store
-            .current_beacon_state
-            .shard_states
-            .get(shard_index)
+    /// Make sure a shard block is within the limits on transactions per block and data per
+    /// transaction
+    fn check_transaction_ees(&self, shard_block: &ShardBlock<T>) -> Result<()> {
+        for (transaction_index, transaction) in shard_block.transactions.iter().enumerate() {
+            let ee_index: usize = transaction.ee_index.into();
+            let ee_exists = self
+                .store
+                .current_beacon_state
+                .execution_environments
+                .get(ee_index)
+                .map_or(false, Option::is_some);
+            if !ee_exists {
+                return Err(Error::OutOfBounds {
+                    what: WhatBound::TransactionExecutionEnvironment(transaction_index),
+                    index: ee_index,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_block_size(&self, shard_block: &simulation_args::ShardBlock) -> Result<()> {
+        if let Some(max) = self.max_transactions_per_block {
+            if shard_block.transactions.len() > max {
+                return Err(Error::BlockTooLarge {
+                    reason: format!(
+                        "{} transactions exceed the limit of {}",
+                        shard_block.transactions.len(),
+                        max
+                    ),
+                });
+            }
+        }
+
+        if let Some(max) = self.max_transaction_data_bytes {
+            for (transaction_index, transaction) in shard_block.transactions.iter().enumerate() {
+                if transaction.data.len() > max {
+                    return Err(Error::BlockTooLarge {
+                        reason: format!(
+                            "{} bytes of data in transaction {} exceed the limit of {}",
+                            transaction.data.len(),
+                            transaction_index,
+                            max
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single transaction against a shard state, updating the state of its EE
+    /// Transactions whose condition doesn't hold are skipped, leaving the shard state unchanged.
+    /// A transaction with a state key runs against that state slot instead of the main state.
+    fn apply_transaction(
+        &self,
+        transaction: &ShardTransaction,
+        shard_state: &mut ShardState<T>,
+    ) -> Result<simulation_args::TransactionReceipt> {
+        if self.store.frozen_ees.contains(&transaction.ee_index) {
+            return Err(Error::Frozen {
+                ee_index: transaction.ee_index.as_u64(),
+            });
+        }
+
+        // Skip conditional transactions whose condition doesn't hold
+        let ee_index: usize = transaction.ee_index.into();
+        if let Some(condition) = &transaction.condition {
+            if !condition_holds(condition, shard_state)? {
+                // Skipping never fails, even for an EE with no state on this shard
+                let state = self
+                    .ee_state(shard_state, ee_index, transaction.state_key)
+                    .unwrap_or([0; 32]);
+                return Ok(simulation_args::TransactionReceipt {
+                    ee_index: transaction.ee_index.as_u64(),
+                    state_key: transaction.state_key,
+                    pre_state: state,
+                    post_state: state,
+                    skipped: true,
+                });
+            }
+        }
+
+        // Get the specified EE (if it exists)
+        let execution_environment = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get(ee_index)
+            .and_then(Option::as_ref)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+
+        // Get the current EE state
+        let pre_state = self.ee_state(shard_state, ee_index, transaction.state_key)?;
+
+        let wasm_code: &[u8] = &*execution_environment.wasm_code;
+        let post_root = self.execute_ee_transaction(wasm_code, transaction, pre_state)?;
+
+        // Update shard state with new root
+        match transaction.state_key {
+            Some(state_key) => {
+                let state = Root::from(post_root);
+                set_keyed_ee_state(shard_state, ee_index, state_key, state, self.max_ee_states)?
+            }
+            None => shard_state.execution_environment_states[ee_index] = Root::from(post_root),
+        }
+        Ok(simulation_args::TransactionReceipt {
+            ee_index: transaction.ee_index.as_u64(),
+            state_key: transaction.state_key,
+            pre_state,
+            post_state: post_root,
+            skipped: false,
+        })
+    }
+
+    /// Validate and preprocess the data of a transaction, then execute it with the EE code
+    fn execute_ee_transaction(
+        &self,
+        wasm_code: &[u8],
+        transaction: &ShardTransaction,
+        pre_state: [u8; 32],
+    ) -> Result<[u8; 32]> {
+        if let Some(validator) = &self.transaction_validator {
+            validator.validate(transaction.ee_index.as_u64(), &*transaction.data)?;
+        }
+
+        // Create a new runtime with the EE code, transaction data, and pre state root
+        let data: Vec<u8> = match &self.transaction_preprocessor {
+            Some(preprocessor) => {
+                preprocessor.preprocess(transaction.ee_index.as_u64(), &*transaction.data)?
+            }
+            None => transaction.data.to_vec(),
+        };
+        execute_transaction(wasm_code, &data, pre_state)
+    }
+
+    /// Add several shard blocks, possibly on different shards, as a single atomic update
+    /// Returns the index of each new block in the same order as the args. If any block fails,
+    /// every block in the batch is rolled back along with the EE state changes it made.
+    pub fn apply_blocks(&mut self, a: Vec<simulation_args::CreateShardBlock>) -> Result<Vec<u64>> {
+        // Remember enough of the current state to undo the whole batch
+        let shard_states = self.store.current_beacon_state.shard_states.clone();
+        let shard_tip_hashes = self.store.shard_tip_hashes.clone();
+        let latest_block_hashes = self.store.latest_block_hashes.clone();
+        let block_bytes = self.store.block_bytes;
+        let shard_blocks_created = self.metrics.shard_blocks_created;
+        let transactions_executed = self.metrics.transactions_executed;
+        let num_blocks_by_shard: HashMap<Shard, usize> = self
+            .store
+            .shard_blocks_by_shard
+            .iter()
+            .map(|(shard, shard_blocks)| (*shard, shard_blocks.len()))
+            .collect();
+
+        let mut shard_block_indices = Vec::with_capacity(a.len());
+        for create_shard_block_args in a {
+            // Blocks are only evicted once the whole batch has succeeded, so rolling back only
+            // has to undo additions
+            match self.add_shard_block(create_shard_block_args) {
+                Ok(shard_block_result) => shard_block_indices.push(shard_block_result.block_index),
+                Err(e) => {
+                    self.store.current_beacon_state.shard_states = shard_states;
+                    self.store.shard_tip_hashes = shard_tip_hashes;
+                    self.store.latest_block_hashes = latest_block_hashes;
+                    self.store.block_bytes = block_bytes;
+                    for (shard, shard_blocks) in self.store.shard_blocks_by_shard.iter_mut() {
+                        shard_blocks.truncate(num_blocks_by_shard[shard]);
+                    }
+                    for (shard, skipped_transactions) in
+                        self.store.skipped_transactions_by_shard.iter_mut()
+                    {
+                        skipped_transactions.truncate(num_blocks_by_shard[shard]);
+                    }
+                    // Decode errors still count, as the args really failed to decode
+                    self.metrics.shard_blocks_created = shard_blocks_created;
+                    self.metrics.transactions_executed = transactions_executed;
+                    return Err(e);
+                }
+            }
+        }
+        self.evict_to_memory_budget();
+        self.record_state_root();
+
+        Ok(shard_block_indices)
+    }
+
+    /// Copy the state of an EE on one shard to the same EE on another shard
+    /// The two states are independent afterwards; later transactions on either shard don't affect
+    /// the other. Only the main state is copied, keyed states stay where they are.
+    pub fn copy_ee_state(&mut self, a: simulation_args::CopyEeState) -> Result<()> {
+        let ee_index = to_index(a.ee_index)?;
+        let ee_exists =
+            self.execution_environment_exists(simulation_args::ExecutionEnvironmentExists {
+                ee_index: a.ee_index,
+            });
+        if !ee_exists {
+            return Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            });
+        }
+
+        let state =
+            self.get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index: a.ee_index,
+                shard_index: a.source_shard_index,
+                state_key: None,
+            })?;
+
+        let target_shard_index = to_index(a.target_shard_index)?;
+        let target_shard_state = self
+            .store
+            .current_beacon_state
+            .shard_states
+            .get_mut(target_shard_index)
             .ok_or(Error::OutOfBounds {
                 what: WhatBound::Shard,
-                index: shard_index,
+                index: target_shard_index,
             })?;
-        let ee_state_root = shard_state
+        let target_state = target_shard_state
             .execution_environment_states
-            .get(ee_index)
+            .get_mut(ee_index)
             .ok_or(Error::OutOfBounds {
                 what: WhatBound::ExecutionEnvironmentState,
                 index: ee_index,
             })?;
-        Ok(ee_state_root.clone().into())
+        *target_state = Root::from(state);
+        self.store
+            .copied_ee_states
+            .insert((Shard::new(a.target_shard_index), EeIndex::new(a.ee_index)));
+        self.record_state_root();
+
+        Ok(())
+    }
+
+    /// Compute the root of the current state of the whole simulation
+    /// This is the hash of the SSZ encoding of the beacon state, which holds every EE and the EE
+    /// states on every shard. Note that this hashes all EE code, so it's not free to compute.
+    pub fn state_root(&self) -> [u8; 32] {
+        hash_bytes(&self.store.current_beacon_state.as_ssz_bytes())
+    }
+
+    /// Check a state root produced elsewhere (e.g. by a peer) against the current state root
+    pub fn verify_state_root(&self, claimed: [u8; 32]) -> bool {
+        self.state_root() == claimed
+    }
+
+    /// Get the state root as of an operation sequence number
+    /// Every successful operation that changes the simulation (creating, updating or deleting
+    /// EEs, creating or applying shard blocks, copying EE state, advancing the slot, or resetting)
+    /// increments the sequence number, starting from 0 for the genesis state. If the requested
+    /// state root is no longer (or not yet) kept, the nearest available one is returned instead,
+    /// along with its sequence number.
+    pub fn get_state_root_at(
+        &self,
+        a: simulation_args::GetStateRootAt,
+    ) -> simulation_args::StateRootAt {
+        let state_roots = &self.store.state_roots;
+        let (seq, state_root) = state_roots
+            .iter()
+            .rev()
+            .find(|(seq, _)| *seq <= a.seq)
+            .or_else(|| state_roots.front())
+            .copied()
+            .expect("the current state root is always kept");
+        simulation_args::StateRootAt { seq, state_root }
+    }
+
+    /// Get the indices of the EEs whose state differs between two shards, sorted by index
+    /// An EE with a state on only one of the shards counts as differing. Keyed state slots are
+    /// compared too, with a slot that hasn't been written on a shard holding the EE's initial
+    /// state there.
+    pub fn compare_shard_states(&self, shard_index_a: u64, shard_index_b: u64) -> Result<Vec<u64>> {
+        let shard_state_a = self.shard_state(shard_index_a)?;
+        let shard_state_b = self.shard_state(shard_index_b)?;
+        let ee_states_a = &shard_state_a.execution_environment_states;
+        let ee_states_b = &shard_state_b.execution_environment_states;
+
+        let num_ee_states = ee_states_a.len().max(ee_states_b.len());
+        let mut differing_ee_indices: BTreeSet<u64> = (0..num_ee_states)
+            .filter(|&ee_index| ee_states_a.get(ee_index) != ee_states_b.get(ee_index))
+            .map(|ee_index| ee_index as u64)
+            .collect();
+
+        let keyed_slots = shard_state_a
+            .keyed_execution_environment_states
+            .iter()
+            .chain(shard_state_b.keyed_execution_environment_states.iter())
+            .map(|keyed_state| (keyed_state.ee_index.as_u64(), keyed_state.state_key));
+        for (ee_index, state_key) in keyed_slots {
+            let state_a = self.ee_state(shard_state_a, ee_index as usize, Some(state_key));
+            let state_b = self.ee_state(shard_state_b, ee_index as usize, Some(state_key));
+            if state_a.ok() != state_b.ok() {
+                differing_ee_indices.insert(ee_index);
+            }
+        }
+        Ok(differing_ee_indices.into_iter().collect())
+    }
+
+    /// Get an EE that was previously added
+    pub fn get_execution_environment(
+        &self,
+        a: simulation_args::GetExecutionEnvironment,
+    ) -> Result<simulation_args::ExecutionEnvironment> {
+        let ee_index = to_index(a.ee_index)?;
+        let ee = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get(ee_index)
+            .and_then(Option::as_ref)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+        Ok(interface_ee(ee_index, ee))
+    }
+
+    /// Iterate over the EEs that haven't been deleted, in index order, along with their indices
+    /// Each EE is only converted to its interface type when the iterator reaches it.
+    pub fn iter_execution_environments(
+        &self,
+    ) -> impl Iterator<Item = (u64, simulation_args::ExecutionEnvironment)> + '_ {
+        self.store
+            .current_beacon_state
+            .execution_environments
+            .iter()
+            .enumerate()
+            .filter_map(|(ee_index, ee)| {
+                ee.as_ref()
+                    .map(|ee| (ee_index as u64, interface_ee(ee_index, ee)))
+            })
+    }
+
+    /// Get the indices of the EEs with a state on a shard, in ascending order
+    /// Every EE has a state on every shard, so this is every EE that hasn't been deleted.
+    pub fn list_execution_environment_states(
+        &self,
+        a: simulation_args::ListExecutionEnvironmentStates,
+    ) -> Result<Vec<u64>> {
+        let shard_state = self.shard_state(a.shard_index)?;
+        let execution_environments = &self.store.current_beacon_state.execution_environments;
+        let ee_indices = (0..shard_state.execution_environment_states.len())
+            .filter(|ee_index| {
+                execution_environments
+                    .get(*ee_index)
+                    .map_or(false, Option::is_some)
+            })
+            .map(|ee_index| ee_index as u64)
+            .collect();
+        Ok(ee_indices)
+    }
+
+    /// Get every EE that hasn't been deleted, sorted by index
+    pub fn list_execution_environments(
+        &self,
+        _: simulation_args::ListExecutionEnvironments,
+    ) -> Vec<simulation_args::ExecutionEnvironment> {
+        self.store
+            .current_beacon_state
+            .execution_environments
+            .iter()
+            .enumerate()
+            .filter_map(|(ee_index, ee)| ee.as_ref().map(|ee| interface_ee(ee_index, ee)))
+            .collect()
+    }
+
+    /// Get the hex-encoded SHA-256 hash of an EE's code
+    /// This is much smaller than the code itself, so it's a cheap way to tell whether an EE's code
+    /// has changed.
+    pub fn get_execution_environment_hash(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentHash,
+    ) -> Result<String> {
+        let code_hash = self.ee_code_hash(a.execution_environment_index)?;
+        Ok(to_hex(&code_hash))
+    }
+
+    /// Get the imports and exports declared by an EE's WASM module
+    pub fn get_execution_environment_module_info(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentModuleInfo,
+    ) -> Result<simulation_args::ModuleInfo> {
+        let ee_index = to_index(a.execution_environment_index)?;
+        let ee = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get(ee_index)
+            .and_then(Option::as_ref)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+        module_info(&*ee.wasm_code)
+    }
+
+    /// Check whether an EE exists (and hasn't been deleted) at the given index
+    pub fn execution_environment_exists(
+        &self,
+        a: simulation_args::ExecutionEnvironmentExists,
+    ) -> bool {
+        let execution_environments = &self.store.current_beacon_state.execution_environments;
+        to_index(a.ee_index).map_or(false, |ee_index| {
+            execution_environments
+                .get(ee_index)
+                .map_or(false, Option::is_some)
+        })
+    }
+
+    /// Get the index and code hash of every EE that hasn't been deleted, sorted by index
+    pub fn ee_code_hashes(&self) -> Vec<(u64, [u8; 32])> {
+        self.store
+            .current_beacon_state
+            .execution_environments
+            .iter()
+            .enumerate()
+            .filter_map(|(ee_index, ee)| {
+                ee.as_ref()
+                    .map(|ee| (ee_index as u64, hash_bytes(&*ee.wasm_code)))
+            })
+            .collect()
+    }
+
+    /// Get the chronological log of changes to the set of EEs
+    pub fn get_ee_audit_log(
+        &self,
+        _: simulation_args::GetEeAuditLog,
+    ) -> Vec<simulation_args::EeAuditEntry> {
+        self.store.ee_audit_log.clone()
+    }
+
+    /// Get the current state of an execution environment on a shard
+    /// With a state key, the state in that slot is returned instead of the main state. A slot that
+    /// has never been written holds the EE's initial state.
+    pub fn get_execution_environment_state(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentState,
+    ) -> Result<[u8; 32]> {
+        let ee_index = to_index(a.ee_index)?;
+        let shard_index = to_index(a.shard_index)?;
+        let shard_state = self
+            .store
+            .current_beacon_state
+            .shard_states
+            .get(shard_index)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                index: shard_index,
+            })?;
+        self.ee_state(shard_state, ee_index, a.state_key)
+    }
+
+    /// Get the state of an EE on a shard, from the slot with `state_key` or its main state
+    fn ee_state(
+        &self,
+        shard_state: &ShardState<T>,
+        ee_index: usize,
+        state_key: Option<u32>,
+    ) -> Result<[u8; 32]> {
+        let state_key = match state_key {
+            Some(state_key) => state_key,
+            None => {
+                let ee_state_root = shard_state
+                    .execution_environment_states
+                    .get(ee_index)
+                    .ok_or(Error::OutOfBounds {
+                        what: WhatBound::ExecutionEnvironmentState,
+                        index: ee_index,
+                    })?;
+                return Ok(ee_state_root.clone().into());
+            }
+        };
+
+        match keyed_ee_state_position(shard_state, ee_index, state_key) {
+            Ok(position) => Ok(shard_state.keyed_execution_environment_states[position]
+                .state
+                .clone()
+                .into()),
+            // Unwritten slots start out with the initial state of the EE
+            Err(_) => self
+                .store
+                .current_beacon_state
+                .execution_environments
+                .get(ee_index)
+                .and_then(Option::as_ref)
+                .map(|ee| ee.initial_state.clone().into())
+                .ok_or(Error::OutOfBounds {
+                    what: WhatBound::ExecutionEnvironment,
+                    index: ee_index,
+                }),
+        }
     }
 
     /// Get a shard block that was previously added
@@ -179,9 +1372,21 @@ impl<T: EthSpec> Simulation<T> {
         &self,
         a: simulation_args::GetShardBlock,
     ) -> Result<simulation_args::ShardBlock> {
-        let shard_index = a.shard_index as usize;
-        let shard_slot_index = a.shard_slot_index as usize;
+        let shard_block = self.shard_block(a.shard_index, a.shard_slot_index)?;
+        Ok(interface_shard_block(shard_block))
+    }
+
+    /// Get up to `count` consecutive shard blocks, starting with the one at `start_index`
+    /// The range is cut short at the tip of the shard's chain, so asking for blocks past the tip
+    /// isn't an error. Starting at a block that has been evicted is.
+    pub fn get_shard_block_range(
+        &self,
+        a: simulation_args::GetShardBlockRange,
+    ) -> Result<Vec<simulation_args::ShardBlock>> {
         let shard = Shard::new(a.shard_index);
+        let shard_index = to_index(a.shard_index)?;
+        let start_index = to_index(a.start_index)?;
+        let count = to_index(a.count)?;
         let shard_blocks =
             self.store
                 .shard_blocks_by_shard
@@ -190,13 +1395,97 @@ impl<T: EthSpec> Simulation<T> {
                     what: WhatBound::Shard,
                     index: shard_index,
                 })?;
-        let shard_block = shard_blocks
-            .get(shard_slot_index)
+        let num_evicted = self.store.num_evicted_blocks_by_shard[&shard];
+        let start_position = start_index
+            .checked_sub(num_evicted)
             .ok_or(Error::OutOfBounds {
                 what: WhatBound::ShardBlock(shard_index),
-                index: shard_slot_index,
+                index: start_index,
             })?;
-        Ok(shard_block.clone().into())
+        Ok(shard_blocks
+            .iter()
+            .skip(start_position)
+            .take(count)
+            .map(interface_shard_block)
+            .collect())
+    }
+
+    /// Iterate over the blocks kept for a shard, in slot order, along with their slot indices
+    /// Evicted blocks are skipped. Each block is only converted to its interface type when the
+    /// iterator reaches it.
+    pub fn iter_shard_blocks(
+        &self,
+        shard_index: u64,
+    ) -> Result<impl Iterator<Item = (u64, simulation_args::ShardBlock)> + '_> {
+        let shard = Shard::new(shard_index);
+        let shard_index = to_index(shard_index)?;
+        let shard_blocks =
+            self.store
+                .shard_blocks_by_shard
+                .get(&shard)
+                .ok_or(Error::OutOfBounds {
+                    what: WhatBound::Shard,
+                    index: shard_index,
+                })?;
+        let num_evicted = self.store.num_evicted_blocks_by_shard[&shard];
+        Ok(shard_blocks
+            .iter()
+            .enumerate()
+            .map(move |(position, shard_block)| {
+                let shard_slot_index = (num_evicted + position) as u64;
+                (shard_slot_index, interface_shard_block(shard_block))
+            }))
+    }
+
+    /// Get a single transaction from a shard block that was previously added
+    pub fn get_transaction(
+        &self,
+        a: simulation_args::GetTransaction,
+    ) -> Result<simulation_args::ShardTransaction> {
+        let shard_index = to_index(a.shard_index)?;
+        let shard_slot_index = to_index(a.shard_slot_index)?;
+        let transaction_index = to_index(a.transaction_index)?;
+        let shard_block = self.shard_block(a.shard_index, a.shard_slot_index)?;
+        let transaction =
+            shard_block
+                .transactions
+                .get(transaction_index)
+                .ok_or(Error::OutOfBounds {
+                    what: WhatBound::Transaction(shard_index, shard_slot_index),
+                    index: transaction_index,
+                })?;
+        Ok(transaction.clone().into())
+    }
+
+    /// Get the indices of the transactions in a shard block that were skipped because their
+    /// condition didn't hold
+    pub fn get_skipped_transactions(&self, a: simulation_args::GetShardBlock) -> Result<Vec<u64>> {
+        let (shard, position) = self.shard_block_position(a.shard_index, a.shard_slot_index)?;
+        Ok(self.store.skipped_transactions_by_shard[&shard][position].clone())
+    }
+
+    /// Check whether a matching transaction was included in a shard block, without returning the
+    /// whole block
+    pub fn transaction_exists(
+        &self,
+        a: simulation_args::GetShardBlock,
+        transaction: &simulation_args::ShardTransaction,
+    ) -> Result<bool> {
+        Ok(self.transaction_index(a, transaction)?.is_some())
+    }
+
+    /// Get the index of the first matching transaction in a shard block, if there is one
+    pub fn transaction_index(
+        &self,
+        a: simulation_args::GetShardBlock,
+        transaction: &simulation_args::ShardTransaction,
+    ) -> Result<Option<u64>> {
+        let shard_block = self.shard_block(a.shard_index, a.shard_slot_index)?;
+        let transaction_index = shard_block.transactions.iter().position(|t| {
+            let ee_index: u64 = t.ee_index.into();
+            ee_index == transaction.ee_index && *t.data == transaction.data[..]
+        });
+        Ok(transaction_index.map(|i| i as u64))
     }
 
     /// Get the specified ShardState, will contain EE states
@@ -204,7 +1493,7 @@ impl<T: EthSpec> Simulation<T> {
         &self,
         a: simulation_args::GetShardState,
     ) -> Result<simulation_args::ShardState> {
-        let shard_index = a.shard_index as usize;
+        let shard_index = to_index(a.shard_index)?;
         let shard_state = self
             .store
             .current_beacon_state
@@ -216,241 +1505,4289 @@ impl<T: EthSpec> Simulation<T> {
             })?;
         Ok(shard_state.clone().into())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use hex::FromHex;
-    use typenum::Unsigned;
-    use types::eth_spec::MainnetEthSpec;
-    use types::slot_epoch_root::{EeIndex, ShardSlot};
+    /// Get the number of blocks and EE states of a shard
+    pub fn get_shard_chain(
+        &self,
+        a: simulation_args::GetShardChain,
+    ) -> Result<simulation_args::ShardChainInfo> {
+        let shard_state = self.shard_state(a.shard_index)?;
+        let shard = Shard::new(a.shard_index);
+        let num_blocks = self.store.num_evicted_blocks_by_shard[&shard]
+            + self.store.shard_blocks_by_shard[&shard].len();
+        Ok(simulation_args::ShardChainInfo {
+            num_blocks: num_blocks as u64,
+            num_ee_states: shard_state.execution_environment_states.len() as u64,
+            state_root: to_hex(&shard_state_root(shard_state)),
+        })
+    }
 
+    /// Dry-run a transaction against the current state of a shard, without adding it to a block
+    /// The transaction is checked and runs exactly as it would as the only transaction of a new
+    /// shard block, but against a copy of the shard state which is then discarded, so nothing in
+    /// the simulation is modified. That includes the metrics, so a transaction that fails to
+    /// decode isn't counted as a decode error.
+    pub fn simulate_transaction(
+        &self,
+        a: simulation_args::SimulateTransaction,
+    ) -> Result<simulation_args::TransactionReceipt> {
+        let mut shard_state = self.shard_state(a.shard_index)?.clone();
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![a.transaction],
+            block_hash: None,
+            parent_hash: None,
+        };
+        self.check_block_size(&shard_block)?;
+        let shard_block = ShardBlock::<T>::try_from(shard_block).context(ArgsError)?;
+        self.check_transaction_ees(&shard_block)?;
+        self.apply_transaction(&shard_block.transactions[0], &mut shard_state)
+    }
 
-    #[test]
-    fn simulation_new() {
-        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
-        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
-        // Should have MaxShards shard states
-        assert_eq!(
-            simulation.store.current_beacon_state.shard_states.len(),
-            max_shards
-        );
-        // Should have no ees initially
-        assert_eq!(
-            simulation
-                .store
-                .current_beacon_state
-                .execution_environments
-                .len(),
-            0
-        );
-        // Should have no ee states initially
-        for i in 0..max_shards {
-            let shard_state = simulation
-                .store
-                .current_beacon_state
-                .shard_states
-                .get(i)
-                .unwrap();
-            assert_eq!(shard_state.execution_environment_states.len(), 0);
+    /// Re-execute a past transaction against the EE state it originally ran on
+    ///
+    /// Historical EE states aren't stored, so the pre state is rebuilt by replaying every earlier
+    /// transaction for the same EE on the shard, starting from the EE's initial state. Transactions
+    /// go through the current validator and preprocessor, as they did when they were executed.
+    /// Nothing in the simulation is modified.
+    ///
+    /// Returns `Error::HistoryUnavailable` when the pre state can't be rebuilt that way: when
+    /// blocks on the shard were evicted, when the EE's code was updated, or (for its main state)
+    /// when the EE's state on the shard was overwritten by `copy_ee_state`.
+    pub fn replay_transaction(
+        &self,
+        a: simulation_args::ReplayTransaction,
+    ) -> Result<simulation_args::TransactionReceipt> {
+        let shard_index = to_index(a.shard_index)?;
+        let shard_slot_index = to_index(a.shard_slot_index)?;
+        let transaction_index = to_index(a.transaction_index)?;
+        let shard_block = self.shard_block(a.shard_index, a.shard_slot_index)?;
+        let transaction =
+            shard_block
+                .transactions
+                .get(transaction_index)
+                .ok_or(Error::OutOfBounds {
+                    what: WhatBound::Transaction(shard_index, shard_slot_index),
+                    index: transaction_index,
+                })?;
+
+        let ee_index: usize = transaction.ee_index.into();
+        let execution_environment = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get(ee_index)
+            .and_then(Option::as_ref)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+        let wasm_code: &[u8] = &*execution_environment.wasm_code;
+
+        // Replay all earlier executed (not skipped) transactions for this EE and state slot on
+        // this shard to rebuild the pre state, which is only possible if they're all still stored
+        // and nothing but those transactions changed the state
+        let shard = Shard::new(a.shard_index);
+        if self.store.num_evicted_blocks_by_shard[&shard] > 0 {
+            return Err(Error::HistoryUnavailable {
+                reason: format!("blocks on shard {} were evicted", shard_index),
+            });
         }
-        // Should have MaxShards shards, but no shard blocks
-        assert_eq!(simulation.store.shard_blocks_by_shard.len(), max_shards);
-        for i in 0..max_shards {
-            let shard_blocks_for_shard_i = simulation
-                .store
-                .shard_blocks_by_shard
+        if self.store.updated_ees.contains(&transaction.ee_index) {
+            return Err(Error::HistoryUnavailable {
+                reason: format!("the code of execution environment {} was updated", ee_index),
+            });
+        }
+        let state_copied = self
+            .store
+            .copied_ee_states
+            .contains(&(shard, transaction.ee_index));
+        if transaction.state_key.is_none() && state_copied {
+            return Err(Error::HistoryUnavailable {
+                reason: format!(
+                    "the state of execution environment {} on shard {} was copied from another \
+                     shard",
+                    ee_index, shard_index
+                ),
+            });
+        }
+        let skipped_transactions_by_block = &self.store.skipped_transactions_by_shard[&shard];
+        let was_skipped = |slot: usize, index: usize| {
+            skipped_transactions_by_block[slot].contains(&(index as u64))
+        };
+        let earlier_transactions = self.store.shard_blocks_by_shard[&shard]
+            .iter()
+            .take(shard_slot_index + 1)
+            .enumerate()
+            .flat_map(|(slot, block)| {
+                block
+                    .transactions
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, t)| (slot, index, t))
+            })
+            .take_while(|(slot, index, _)| (*slot, *index) < (shard_slot_index, transaction_index))
+            .filter(|(slot, index, t)| {
+                t.ee_index == transaction.ee_index
+                    && t.state_key == transaction.state_key
+                    && !was_skipped(*slot, *index)
+            });
+        let mut pre_state: [u8; 32] = execution_environment.initial_state.clone().into();
+        for (_, _, earlier_transaction) in earlier_transactions {
+            pre_state = self.execute_ee_transaction(wasm_code, earlier_transaction, pre_state)?;
+        }
+
+        // A skipped transaction didn't execute, so it left the state unchanged
+        let skipped = was_skipped(shard_slot_index, transaction_index);
+        let post_state = if skipped {
+            pre_state
+        } else {
+            self.execute_ee_transaction(wasm_code, transaction, pre_state)?
+        };
+        Ok(simulation_args::TransactionReceipt {
+            ee_index: ee_index as u64,
+            state_key: transaction.state_key,
+            pre_state,
+            post_state,
+            skipped,
+        })
+    }
+
+    /// Check whether a shard exists at the given index
+    pub fn shard_exists(&self, a: simulation_args::ShardExists) -> bool {
+        let num_shards = self.store.current_beacon_state.shard_states.len();
+        to_index(a.shard_index).map_or(false, |shard_index| shard_index < num_shards)
+    }
+
+    /// Get the hash of the tip of a shard's chain of blocks
+    /// This is all zeroes for a shard with no blocks, and otherwise commits to every block on the
+    /// shard. It is maintained as blocks are added, so this is a constant-time lookup.
+    pub fn shard_tip_hash(&self, shard_index: u64) -> Result<[u8; 32]> {
+        self.store
+            .shard_tip_hashes
+            .get(&Shard::new(shard_index))
+            .copied()
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                index: shard_index as usize,
+            })
+    }
+
+    /// Find every EE state, keyed EE state, stored transaction, and transaction condition that
+    /// refers to an EE index that doesn't exist
+    /// A healthy simulation returns nothing. This is meant to be run after operations that change
+    /// the structure of the stored data, to check they left it consistent.
+    pub fn audit_ee_references(&self) -> Vec<ReferenceError> {
+        let num_ees = self.store.current_beacon_state.execution_environments.len() as u64;
+        let mut reference_errors = Vec::new();
+
+        for (shard_index, shard_state) in self
+            .store
+            .current_beacon_state
+            .shard_states
+            .iter()
+            .enumerate()
+        {
+            let shard_index = shard_index as u64;
+
+            // Each shard should hold exactly one state per EE, so any extra state is dangling
+            let num_ee_states = shard_state.execution_environment_states.len() as u64;
+            for ee_index in num_ees..num_ee_states {
+                reference_errors.push(ReferenceError {
+                    shard_index,
+                    location: ReferenceLocation::EeState,
+                    ee_index,
+                });
+            }
+            for keyed_state in shard_state.keyed_execution_environment_states.iter() {
+                let ee_index = keyed_state.ee_index.as_u64();
+                if ee_index >= num_ees {
+                    reference_errors.push(ReferenceError {
+                        shard_index,
+                        location: ReferenceLocation::KeyedEeState {
+                            state_key: keyed_state.state_key,
+                        },
+                        ee_index,
+                    });
+                }
+            }
+
+            let shard_blocks = match self
+                .store
+                .shard_blocks_by_shard
+                .get(&Shard::new(shard_index))
+            {
+                Some(shard_blocks) => shard_blocks,
+                None => continue,
+            };
+            let num_evicted = self.store.num_evicted_blocks_by_shard[&Shard::new(shard_index)];
+            for (position, shard_block) in shard_blocks.iter().enumerate() {
+                let shard_slot_index = num_evicted + position;
+                for (transaction_index, transaction) in shard_block.transactions.iter().enumerate()
+                {
+                    let ee_index = transaction.ee_index.as_u64();
+                    if ee_index >= num_ees {
+                        reference_errors.push(ReferenceError {
+                            shard_index,
+                            location: ReferenceLocation::Transaction {
+                                shard_slot_index: shard_slot_index as u64,
+                                transaction_index: transaction_index as u64,
+                            },
+                            ee_index,
+                        });
+                    }
+                    if let Some(condition) = &transaction.condition {
+                        let ee_index = condition.ee_index.as_u64();
+                        if ee_index >= num_ees {
+                            reference_errors.push(ReferenceError {
+                                shard_index,
+                                location: ReferenceLocation::TransactionCondition {
+                                    shard_slot_index: shard_slot_index as u64,
+                                    transaction_index: transaction_index as u64,
+                                },
+                                ee_index,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        reference_errors
+    }
+
+    /// Get an overview of the simulation: how many EEs and shards there are, how many blocks
+    /// each shard has and how many of them are finalized, and the current beacon chain slot
+    pub fn simulation_state(
+        &self,
+        _: simulation_args::GetSimulationState,
+    ) -> simulation_args::SimulationState {
+        let num_shard_chains = self.store.current_beacon_state.shard_states.len();
+        let shard_block_counts = (0..num_shard_chains as u64)
+            .map(|shard_index| self.num_shard_blocks(Shard::new(shard_index)) as u64)
+            .collect();
+        let finalized_block_counts = (0..num_shard_chains as u64)
+            .map(|shard_index| {
+                self.store.num_finalized_blocks_by_shard[&Shard::new(shard_index)] as u64
+            })
+            .collect();
+        simulation_args::SimulationState {
+            num_execution_environments: self.num_execution_environments() as u64,
+            num_shard_chains: num_shard_chains as u64,
+            shard_block_counts,
+            slot: self.store.current_beacon_state.slot.as_u64(),
+            finalized_block_counts,
+        }
+    }
+
+    /// Move the beacon chain on to its next slot, returning the new slot
+    /// The current tip block of every shard is crosslinked, which finalizes it along with every
+    /// earlier block on the shard. Blocks added afterwards stay unfinalized until the next slot.
+    pub fn advance_slot(&mut self, _: simulation_args::AdvanceSlot) -> u64 {
+        let shards: Vec<Shard> = self.store.shard_blocks_by_shard.keys().cloned().collect();
+        for shard in shards {
+            let num_blocks = self.num_shard_blocks(shard);
+            self.store
+                .num_finalized_blocks_by_shard
+                .insert(shard, num_blocks);
+        }
+        self.store.current_beacon_state.slot += 1;
+        self.record_state_root();
+        self.store.current_beacon_state.slot.as_u64()
+    }
+
+    /// The number of EEs that haven't been deleted
+    fn num_execution_environments(&self) -> usize {
+        self.store
+            .current_beacon_state
+            .execution_environments
+            .iter()
+            .filter(|ee| ee.is_some())
+            .count()
+    }
+
+    /// The number of blocks added to a shard, including evicted ones
+    fn num_shard_blocks(&self, shard: Shard) -> usize {
+        self.store.num_evicted_blocks_by_shard[&shard]
+            + self.store.shard_blocks_by_shard[&shard].len()
+    }
+
+    /// Discard every EE and shard block, going back to the state of a new simulation
+    /// Configuration (limits, memory budget, preprocessor and validator) is kept, as are the
+    /// metrics, the history of state roots, which records the reset like any other operation, and
+    /// the append-only EE audit log. The results of earlier operations with idempotency keys are
+    /// forgotten.
+    pub fn reset(&mut self, _: simulation_args::Reset) -> simulation_args::ResetSummary {
+        let num_execution_environments = self.num_execution_environments() as u64;
+        let num_shard_blocks: usize = self
+            .store
+            .shard_blocks_by_shard
+            .iter()
+            .map(|(shard, shard_blocks)| {
+                self.store.num_evicted_blocks_by_shard[shard] + shard_blocks.len()
+            })
+            .sum();
+
+        let state_roots = mem::replace(&mut self.store.state_roots, Default::default());
+        let ee_audit_log = mem::replace(&mut self.store.ee_audit_log, Default::default());
+        self.store = Store::new();
+        self.store.state_roots = state_roots;
+        self.store.ee_audit_log = ee_audit_log;
+        self.eviction_stats = EvictionStats::default();
+        self.idempotent_results = IdempotentResults::default();
+        self.record_state_root();
+
+        simulation_args::ResetSummary {
+            num_execution_environments,
+            num_shard_blocks: num_shard_blocks as u64,
+        }
+    }
+
+    /// Render a human-readable summary of the simulation, for debugging
+    /// Lists every EE with the hash of its code, then every shard that has had blocks with its
+    /// block count (including evicted blocks), the number of transactions in its stored blocks,
+    /// and the number and total size of its EE states, keyed states included. Shards that have
+    /// never had a block are only counted.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        // Writing to a String can't fail, so the results of writeln! are ignored
+        let _ = writeln!(report, "Beacon chain");
+        let ee_code_hashes = self.ee_code_hashes();
+        let _ = writeln!(report, "  Execution environments: {}", ee_code_hashes.len());
+        for (ee_index, code_hash) in ee_code_hashes.iter() {
+            let _ = writeln!(report, "    {}: code hash {}", ee_index, to_hex(code_hash));
+        }
+
+        let mut num_shards_without_blocks = 0;
+        for (shard_index, shard_state) in self
+            .store
+            .current_beacon_state
+            .shard_states
+            .iter()
+            .enumerate()
+        {
+            let shard = Shard::new(shard_index as u64);
+            let num_blocks = self.num_shard_blocks(shard);
+            if num_blocks == 0 {
+                num_shards_without_blocks += 1;
+                continue;
+            }
+
+            let shard_blocks = &self.store.shard_blocks_by_shard[&shard];
+            let num_transactions: usize = shard_blocks.iter().map(|b| b.transactions.len()).sum();
+            let num_ee_states = shard_state.execution_environment_states.len()
+                + shard_state.keyed_execution_environment_states.len();
+
+            let _ = writeln!(report, "Shard {}", shard_index);
+            let _ = writeln!(report, "  Blocks: {}", num_blocks);
+            let _ = writeln!(report, "  Transactions: {}", num_transactions);
+            let _ = writeln!(
+                report,
+                "  EE states: {} ({} bytes)",
+                num_ee_states,
+                num_ee_states * 32
+            );
+        }
+        let _ = writeln!(
+            report,
+            "Shards without blocks: {}",
+            num_shards_without_blocks
+        );
+
+        report
+    }
+
+    /// Hash the code of an EE
+    fn ee_code_hash(&self, ee_index: u64) -> Result<[u8; 32]> {
+        let ee_index = to_index(ee_index)?;
+        let ee = self
+            .store
+            .current_beacon_state
+            .execution_environments
+            .get(ee_index)
+            .and_then(Option::as_ref)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: ee_index,
+            })?;
+        Ok(hash_bytes(&*ee.wasm_code))
+    }
+
+    /// Record the state root after a successful operation, advancing the sequence number
+    fn record_state_root(&mut self) {
+        let seq = self.store.state_roots.back().map_or(0, |(seq, _)| seq + 1);
+        let state_root = self.state_root();
+        self.store.state_roots.push_back((seq, state_root));
+        while self.store.state_roots.len() > self.max_state_roots {
+            self.store.state_roots.pop_front();
+        }
+    }
+
+    /// Append an entry to the EE audit log
+    fn record_ee_audit_entry(
+        &mut self,
+        ee_index: u64,
+        action: simulation_args::EeAuditAction,
+        code_hash: [u8; 32],
+    ) {
+        let sequence = self.store.ee_audit_log.len() as u64;
+        self.store.ee_audit_log.push(simulation_args::EeAuditEntry {
+            sequence,
+            ee_index,
+            action,
+            code_hash,
+        });
+    }
+
+    /// Look up the current state of a shard by index
+    fn shard_state(&self, shard_index: u64) -> Result<&ShardState<T>> {
+        let shard_index = to_index(shard_index)?;
+        self.store
+            .current_beacon_state
+            .shard_states
+            .get(shard_index)
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                index: shard_index,
+            })
+    }
+
+    /// Look up a stored shard block by shard and slot index
+    fn shard_block(&self, shard_index: u64, shard_slot_index: u64) -> Result<&ShardBlock<T>> {
+        let (shard, position) = self.shard_block_position(shard_index, shard_slot_index)?;
+        Ok(&self.store.shard_blocks_by_shard[&shard][position])
+    }
+
+    /// Find where a shard block is stored, given its shard and slot index
+    /// Slot indices also count evicted blocks, so a block's position in the store is its slot
+    /// index less the number of blocks evicted from the shard.
+    fn shard_block_position(
+        &self,
+        shard_index: u64,
+        shard_slot_index: u64,
+    ) -> Result<(Shard, usize)> {
+        let shard = Shard::new(shard_index);
+        let shard_index = to_index(shard_index)?;
+        let shard_slot_index = to_index(shard_slot_index)?;
+        let shard_blocks =
+            self.store
+                .shard_blocks_by_shard
+                .get(&shard)
+                .ok_or(Error::OutOfBounds {
+                    what: WhatBound::Shard,
+                    index: shard_index,
+                })?;
+        let num_evicted = self.store.num_evicted_blocks_by_shard[&shard];
+        shard_slot_index
+            .checked_sub(num_evicted)
+            .filter(|position| *position < shard_blocks.len())
+            .map(|position| (shard, position))
+            .ok_or(Error::OutOfBounds {
+                what: WhatBound::ShardBlock(shard_index),
+                index: shard_slot_index,
+            })
+    }
+}
+
+/// Hash arbitrary bytes (eg. EE code) into a 32-byte digest
+fn hash_bytes(bytes: &[u8]) -> [u8; 32] {
+    let mut digest: [u8; 32] = [0; 32];
+    digest.copy_from_slice(&hash(bytes));
+    digest
+}
+
+/// Convert an index received through the public interface to one usable for indexing in memory
+/// Indices are u64 in the interface, so on platforms where usize is narrower, an index that
+/// doesn't fit must be rejected rather than silently truncated to a different, valid index.
+fn to_index(index: u64) -> Result<usize> {
+    usize::try_from(index).map_err(|_| Error::Limit {
+        kind: LimitKind::AddressableIndices,
+        limit: usize::max_value(),
+    })
+}
+
+/// Check whether a transaction's condition holds against the current state of the shard
+fn condition_holds<T: EthSpec>(condition: &Condition, shard_state: &ShardState<T>) -> Result<bool> {
+    let ee_index: usize = condition.ee_index.into();
+    let ee_state = shard_state
+        .execution_environment_states
+        .get(ee_index)
+        .ok_or(Error::OutOfBounds {
+            what: WhatBound::ExecutionEnvironmentState,
+            index: ee_index,
+        })?;
+    Ok((*ee_state == condition.state) == condition.equal)
+}
+
+/// The error for an idempotency key that was already used by a different kind of operation
+fn reused_idempotency_key() -> Error {
+    Error::InvalidArgs {
+        reason: format!("idempotency key was already used by a different operation"),
+    }
+}
+
+/// Find the keyed state slot of an EE on a shard
+/// Returns `Err` with the position the slot would be inserted at if it hasn't been written yet.
+fn keyed_ee_state_position<T: EthSpec>(
+    shard_state: &ShardState<T>,
+    ee_index: usize,
+    state_key: u32,
+) -> std::result::Result<usize, usize> {
+    shard_state
+        .keyed_execution_environment_states
+        .binary_search_by_key(&(ee_index as u64, state_key), |keyed_state| {
+            (keyed_state.ee_index.as_u64(), keyed_state.state_key)
+        })
+}
+
+/// Write the keyed state slot of an EE on a shard, adding the slot if it doesn't exist yet
+/// Adding a slot fails if the shard already holds `max_ee_states` states.
+fn set_keyed_ee_state<T: EthSpec>(
+    shard_state: &mut ShardState<T>,
+    ee_index: usize,
+    state_key: u32,
+    state: Root,
+    max_ee_states: Option<usize>,
+) -> Result<()> {
+    let position = keyed_ee_state_position(shard_state, ee_index, state_key);
+    let num_ee_states = shard_state.execution_environment_states.len();
+    let keyed_states = &mut shard_state.keyed_execution_environment_states;
+    match position {
+        Ok(position) => keyed_states[position].state = state,
+        Err(position) => {
+            if let Some(limit) = max_ee_states {
+                if num_ee_states + keyed_states.len() >= limit {
+                    return Err(Error::Limit {
+                        kind: LimitKind::EeState,
+                        limit,
+                    });
+                }
+            }
+
+            let mut states: Vec<KeyedEeState> = keyed_states.to_vec();
+            states.insert(
+                position,
+                KeyedEeState {
+                    ee_index: EeIndex::new(ee_index as u64),
+                    state_key,
+                    state,
+                },
+            );
+            *keyed_states = VariableList::new(states).map_err(|_| Error::MaxLengthExceeded {
+                what: format!("number of keyed execution environment states"),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Format bytes as lowercase hex, eg. for display in reports
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash the SSZ encoding of a shard block
+fn shard_block_hash<T: EthSpec>(shard_block: &ShardBlock<T>) -> [u8; 32] {
+    hash_bytes(&shard_block.as_ssz_bytes())
+}
+
+/// Hash the transactions of a shard block, identifying the block by its contents alone
+fn block_hash<T: EthSpec>(shard_block: &ShardBlock<T>) -> [u8; 32] {
+    hash_bytes(&shard_block.transactions.as_ssz_bytes())
+}
+
+/// Fold the EE states of a shard into a single root
+/// The states are hashed in EE index order, so equal shard states always have equal roots.
+/// Keyed states follow the main states, each prefixed with its EE index and key.
+fn shard_state_root<T: EthSpec>(shard_state: &ShardState<T>) -> [u8; 32] {
+    let mut preimage: Vec<u8> = shard_state
+        .execution_environment_states
+        .iter()
+        .flat_map(|ee_state| ee_state.as_bytes().iter().copied())
+        .collect();
+    for keyed_state in shard_state.keyed_execution_environment_states.iter() {
+        preimage.extend_from_slice(&keyed_state.ee_index.as_u64().to_le_bytes());
+        preimage.extend_from_slice(&keyed_state.state_key.to_le_bytes());
+        preimage.extend_from_slice(keyed_state.state.as_bytes());
+    }
+    hash_bytes(&preimage)
+}
+
+/// Compute the tip hash of a shard after adding `shard_block` on top of `tip_hash`
+fn next_tip_hash<T: EthSpec>(tip_hash: &[u8; 32], shard_block: &ShardBlock<T>) -> [u8; 32] {
+    let mut preimage = tip_hash.to_vec();
+    preimage.extend_from_slice(&shard_block_hash(shard_block));
+    hash_bytes(&preimage)
+}
+
+/// Get the size of a shard block as counted against the memory budget
+fn shard_block_size<T: EthSpec>(shard_block: &ShardBlock<T>) -> usize {
+    shard_block.as_ssz_bytes().len()
+}
+
+/// Convert a shard block to its interface type, including its hash
+fn interface_shard_block<T: EthSpec>(shard_block: &ShardBlock<T>) -> simulation_args::ShardBlock {
+    let mut interface_shard_block: simulation_args::ShardBlock = shard_block.clone().into();
+    interface_shard_block.block_hash = Some(to_hex(&block_hash(shard_block)));
+    interface_shard_block.parent_hash = Some(to_hex(shard_block.parent_hash.as_bytes()));
+    interface_shard_block
+}
+
+/// Convert an EE to its interface type, including its index
+fn interface_ee<T: EthSpec>(
+    ee_index: usize,
+    ee: &ExecutionEnvironment<T>,
+) -> simulation_args::ExecutionEnvironment {
+    let mut interface_ee: simulation_args::ExecutionEnvironment = ee.clone().into();
+    interface_ee.index = Some(ee_index as u64);
+    interface_ee
+}
+
+/// The function the runtime calls to execute a transaction, which takes and returns nothing
+const ENTRYPOINT: &str = "main";
+
+/// Make sure EE code is a WASM module exporting the entrypoint, so that broken EEs are caught
+/// when they're added rather than when they're first executed
+fn check_entrypoint(wasm_code: &[u8]) -> Result<()> {
+    use simulation_args::{FunctionSignature, ModuleItemKind};
+
+    let entrypoint_signature = FunctionSignature {
+        params: vec![],
+        results: vec![],
+    };
+    let has_entrypoint = module_info(wasm_code)?.exports.iter().any(|export| {
+        export.name == ENTRYPOINT
+            && export.kind == ModuleItemKind::Function
+            && export.signature.as_ref() == Some(&entrypoint_signature)
+    });
+    if !has_entrypoint {
+        return Err(Error::MissingEntrypoint {
+            expected: ENTRYPOINT.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Parse the imports and exports declared by a WASM module
+fn module_info(wasm_code: &[u8]) -> Result<simulation_args::ModuleInfo> {
+    use simulation_args::{FunctionSignature, ModuleExport, ModuleImport, ModuleItemKind};
+    use wasmparser::{ExternalKind, ImportSectionEntryType, ModuleReader, SectionCode};
+
+    let invalid = |e: wasmparser::BinaryReaderError| Error::InvalidWasm {
+        reason: e.to_string(),
+    };
+
+    // Function signatures are only known once all sections are read, so the imports and exports
+    // are collected along with the index of their signature (if they're functions) first
+    let mut signatures = Vec::new();
+    let mut function_types = Vec::new();
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+
+    let mut reader = ModuleReader::new(wasm_code).map_err(invalid)?;
+    while !reader.eof() {
+        let section = reader.read().map_err(invalid)?;
+        match section.code {
+            SectionCode::Type => {
+                for func_type in section.get_type_section_reader().map_err(invalid)? {
+                    let func_type = func_type.map_err(invalid)?;
+                    let to_names = |types: &[wasmparser::Type]| -> Vec<String> {
+                        types
+                            .iter()
+                            .map(|t| format!("{:?}", t).to_lowercase())
+                            .collect()
+                    };
+                    signatures.push(FunctionSignature {
+                        params: to_names(&func_type.params),
+                        results: to_names(&func_type.returns),
+                    });
+                }
+            }
+            SectionCode::Import => {
+                for import in section.get_import_section_reader().map_err(invalid)? {
+                    let import = import.map_err(invalid)?;
+                    let (kind, type_index) = match import.ty {
+                        ImportSectionEntryType::Function(type_index) => {
+                            // Imported functions come first in the function index space
+                            function_types.push(type_index);
+                            (ModuleItemKind::Function, Some(type_index))
+                        }
+                        ImportSectionEntryType::Table(_) => (ModuleItemKind::Table, None),
+                        ImportSectionEntryType::Memory(_) => (ModuleItemKind::Memory, None),
+                        ImportSectionEntryType::Global(_) => (ModuleItemKind::Global, None),
+                    };
+                    imports.push((
+                        import.module.to_string(),
+                        import.field.to_string(),
+                        kind,
+                        type_index,
+                    ));
+                }
+            }
+            SectionCode::Function => {
+                for type_index in section.get_function_section_reader().map_err(invalid)? {
+                    function_types.push(type_index.map_err(invalid)?);
+                }
+            }
+            SectionCode::Export => {
+                for export in section.get_export_section_reader().map_err(invalid)? {
+                    let export = export.map_err(invalid)?;
+                    let kind = match export.kind {
+                        ExternalKind::Function => ModuleItemKind::Function,
+                        ExternalKind::Table => ModuleItemKind::Table,
+                        ExternalKind::Memory => ModuleItemKind::Memory,
+                        ExternalKind::Global => ModuleItemKind::Global,
+                    };
+                    exports.push((export.field.to_string(), kind, export.index));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let signature = |type_index: u32| {
+        signatures
+            .get(type_index as usize)
+            .cloned()
+            .ok_or_else(|| Error::InvalidWasm {
+                reason: format!("no type exists at index: {}", type_index),
+            })
+    };
+    let imports = imports
+        .into_iter()
+        .map(|(module, name, kind, type_index)| {
+            Ok(ModuleImport {
+                module,
+                name,
+                kind,
+                signature: type_index.map(signature).transpose()?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let exports = exports
+        .into_iter()
+        .map(|(name, kind, index)| {
+            let signature = match kind {
+                ModuleItemKind::Function => {
+                    let type_index =
+                        function_types
+                            .get(index as usize)
+                            .ok_or_else(|| Error::InvalidWasm {
+                                reason: format!("no function exists at index: {}", index),
+                            })?;
+                    Some(signature(*type_index)?)
+                }
+                _ => None,
+            };
+            Ok(ModuleExport {
+                name,
+                kind,
+                signature,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(simulation_args::ModuleInfo { imports, exports })
+}
+
+/// Create an internal EE from args, reading its code from a file if a path is given
+fn decode_execution_environment<T: EthSpec>(
+    a: simulation_args::CreateExecutionEnvironment,
+) -> Result<ExecutionEnvironment<T>> {
+    // The EE code comes from exactly one of the args or a file
+    let mut interface_ee = a.ee;
+    match (a.wasm_path, interface_ee.wasm_code.is_empty()) {
+        (Some(wasm_path), true) => {
+            interface_ee.wasm_code = fs::read(&wasm_path).context(Io { path: &wasm_path })?;
+        }
+        (None, false) => {}
+        _ => {
+            return Err(Error::InvalidArgs {
+                reason: "exactly one of wasm_code or wasm_path must be set".to_string(),
+            })
+        }
+    }
+
+    // Create internal EE struct from args
+    ExecutionEnvironment::try_from(interface_ee).context(ArgsError)
+}
+
+/// Run the EE code against the transaction data and pre state root, returning the post state root
+///
+/// The ewasm runtime panics whenever the EE traps (eg. when deep recursion hits wasmi's call stack
+/// limit), so the panic is caught here and surfaced as `Error::ExecutionTrap` rather than taking
+/// down the host.
+fn execute_transaction(wasm_code: &[u8], data: &[u8], pre_state: [u8; 32]) -> Result<[u8; 32]> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        RootRuntime::new(wasm_code, data, pre_state).execute()
+    }))
+    .map_err(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown trap");
+        let reason = if message.contains("StackOverflow") {
+            "stack exhausted".to_string()
+        } else {
+            message.to_string()
+        };
+        Error::ExecutionTrap { reason }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::FromHex;
+    use std::path::PathBuf;
+    use typenum::Unsigned;
+    use types::eth_spec::MainnetEthSpec;
+    use types::slot_epoch_root::{EeIndex, ShardSlot};
+
+
+    #[test]
+    fn simulation_new() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        // Should have MaxShards shard states
+        assert_eq!(
+            simulation.store.current_beacon_state.shard_states.len(),
+            max_shards
+        );
+        // Should have no ees initially
+        assert_eq!(
+            simulation
+                .store
+                .current_beacon_state
+                .execution_environments
+                .len(),
+            0
+        );
+        // Should have no ee states initially
+        for i in 0..max_shards {
+            let shard_state = simulation
+                .store
+                .current_beacon_state
+                .shard_states
+                .get(i)
+                .unwrap();
+            assert_eq!(shard_state.execution_environment_states.len(), 0);
+        }
+        // Should have MaxShards shards, but no shard blocks
+        assert_eq!(simulation.store.shard_blocks_by_shard.len(), max_shards);
+        for i in 0..max_shards {
+            let shard_blocks_for_shard_i = simulation
+                .store
+                .shard_blocks_by_shard
                 .get(&Shard::new(i as u64))
                 .unwrap();
-            assert_eq!(shard_blocks_for_shard_i.len(), 0);
+            assert_eq!(shard_blocks_for_shard_i.len(), 0);
+        }
+    }
+
+    #[test]
+    fn can_create_and_get_ee() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+
+        // Set up args::CreateExecutionEnvironment
+        let mut initial_state: [u8; 32] = [0; 32];
+        initial_state[5] = 1;
+        let example_wasm_code: &[u8] = include_bytes!("../tests/do_nothing.wasm");
+        let example_wasm_code2: &[u8] = include_bytes!("../tests/phase2_bazaar.wasm");
+
+        let interface_ee = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: example_wasm_code.to_vec(),
+            index: None,
+        };
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee: interface_ee.clone(),
+            wasm_path: None,
+            idempotency_key: None,
+        };
+
+        let interface_ee2 = simulation_args::ExecutionEnvironment {
+            initial_state: initial_state.clone(),
+            wasm_code: example_wasm_code2.to_vec(),
+            index: None,
+        };
+        let create_ee_args2 = simulation_args::CreateExecutionEnvironment {
+            ee: interface_ee2.clone(),
+            wasm_path: None,
+            idempotency_key: None,
+        };
+
+        // Calling create_execution_environment repeatedly should return an increasing EE index
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args)
+            .unwrap();
+        assert_eq!(ee_index, 0);
+        let ee_index2 = simulation
+            .create_execution_environment(create_ee_args2)
+            .unwrap();
+        assert_eq!(ee_index2, 1);
+
+        // Set up simulation_args::GetExecutionEnvironment
+        let get_ee_args = simulation_args::GetExecutionEnvironment { ee_index };
+        let get_ee_args2 = simulation_args::GetExecutionEnvironment {
+            ee_index: ee_index2,
+        };
+
+        // Make sure the retrieved EEs are the ones originally passed in, and know their own index
+        let ee = simulation.get_execution_environment(get_ee_args).unwrap();
+        let ee2 = simulation.get_execution_environment(get_ee_args2).unwrap();
+        assert_eq!(
+            ee,
+            simulation_args::ExecutionEnvironment {
+                index: Some(ee_index),
+                ..interface_ee
+            }
+        );
+        assert_eq!(
+            ee2,
+            simulation_args::ExecutionEnvironment {
+                index: Some(ee_index2),
+                ..interface_ee2
+            }
+        );
+
+        // Make sure the EEs have the correct initial_state specified for every shard
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        for i in 0..max_shards as u64 {
+            let get_ee_state_args = simulation_args::GetExecutionEnvironmentState {
+                ee_index,
+                shard_index: i,
+                state_key: None,
+            };
+            let ee_state = simulation
+                .get_execution_environment_state(get_ee_state_args)
+                .unwrap();
+            assert_eq!(ee_state, initial_state);
+        }
+    }
+
+    fn test_block_with_single_transaction(
+        wasm_code: &[u8],
+        initial_state: [u8; 32],
+        data: Vec<u8>,
+        expected_post_state: [u8; 32],
+        shard_index: u64,
+    ) -> (
+        Simulation<MainnetEthSpec>,
+        simulation_args::ShardTransaction,
+        ShardSlot,
+        EeIndex,
+    ) {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+
+        // Create EE with the specified code and initial state
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: wasm_code.to_vec(),
+            index: None,
+        };
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee,
+            wasm_path: None,
+            idempotency_key: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args)
+            .unwrap();
+        assert_eq!(ee_index, 0);
+
+        // Set up a shard transaction with the specified data
+        let shard_transaction = simulation_args::ShardTransaction {
+            data,
+            ee_index,
+            condition: None,
+            state_key: None,
+        };
+        let shard_transaction_copy = shard_transaction.clone();
+
+        // Create a shard block with the one transaction in it
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![shard_transaction],
+            block_hash: None,
+            parent_hash: None,
+        };
+        let create_shard_block_args = simulation_args::CreateShardBlock {
+            shard_index,
+            shard_block,
+            idempotency_key: None,
+        };
+        // This creates the block and runs all the transactions inside it
+        let shard_slot_index = simulation
+            .create_shard_block(create_shard_block_args)
+            .unwrap()
+            .block_index;
+
+        // Get back the EE state to make sure it matches the expected_post_state
+        let get_ee_state_args = simulation_args::GetExecutionEnvironmentState {
+            ee_index,
+            shard_index,
+            state_key: None,
+        };
+        let ee_post_state = simulation
+            .get_execution_environment_state(get_ee_state_args)
+            .unwrap();
+        assert_eq!(
+            ee_post_state, expected_post_state,
+            "actual post state root should match expected post state root"
+        );
+
+        (
+            simulation,
+            shard_transaction_copy,
+            ShardSlot::new(shard_slot_index),
+            EeIndex::new(ee_index),
+        )
+    }
+
+    #[test]
+    fn run_scout_helloworld_and_get_shard_block_and_state() {
+        let initial_state = [0; 32];
+        let expected_post_state = [0; 32];
+        let data: Vec<u8> = Vec::new();
+        let shard_index = 0;
+        let (simulation, shard_transaction, shard_slot, ee_index) =
+            test_block_with_single_transaction(
+                include_bytes!("../tests/phase2_helloworld.wasm"),
+                initial_state,
+                data,
+                expected_post_state,
+                shard_index,
+            );
+
+        let ee_index: u64 = ee_index.into();
+
+        // Test that GetShardBlock is working as expected
+        let shard_slot_index: u64 = shard_slot.into();
+        let get_shard_block_args = simulation_args::GetShardBlock {
+            shard_index,
+            shard_slot_index,
+        };
+        let shard_block = simulation.get_shard_block(get_shard_block_args).unwrap();
+
+        // Make sure the transaction on the retrieved block matches the transaction
+        // on the created block
+        assert_eq!(
+            shard_block.transactions.get(0).unwrap().clone(),
+            shard_transaction
+        );
+
+        // Test that GetShardState is working as expected
+        let get_shard_state_args = simulation_args::GetShardState { shard_index };
+        let shard_state = simulation.get_shard_state(get_shard_state_args).unwrap();
+        let ee_index = ee_index as usize;
+        let ee_state = shard_state
+            .execution_environment_states
+            .get(ee_index)
+            .unwrap();
+        assert_eq!(ee_state, &expected_post_state);
+    }
+    #[test]
+    fn run_scout_bazaar_test() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let shard_index = 0;
+        let (_simulation, _shard_transaction, _shard_slot, _ee_index) =
+            test_block_with_single_transaction(
+                include_bytes!("../tests/phase2_bazaar.wasm"),
+                initial_state,
+                data,
+                expected_post_state,
+                shard_index,
+            );
+    }
+
+    #[test]
+    fn deep_recursion_surfaces_as_execution_trap() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/deep_recursion.wasm").to_vec(),
+            index: None,
+        };
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee,
+            wasm_path: None,
+            idempotency_key: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args)
+            .unwrap();
+
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![simulation_args::ShardTransaction {
+                data: Vec::new(),
+                ee_index,
+                condition: None,
+                state_key: None,
+            }],
+            block_hash: None,
+            parent_hash: None,
+        };
+        let create_shard_block_args = simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block,
+            idempotency_key: None,
+        };
+        match simulation.create_shard_block(create_shard_block_args) {
+            Err(Error::ExecutionTrap { reason }) => assert_eq!(reason, "stack exhausted"),
+            other => panic!("expected an execution trap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_get_ee_code_hashes() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        assert!(simulation.ee_code_hashes().is_empty());
+
+        let example_wasm_code: &[u8] = include_bytes!("../tests/do_nothing.wasm");
+        let example_wasm_code2: &[u8] = include_bytes!("../tests/phase2_bazaar.wasm");
+        let wasm_codes = vec![example_wasm_code, example_wasm_code2];
+        for wasm_code in wasm_codes.iter() {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: wasm_code.to_vec(),
+                index: None,
+            };
+            let create_ee_args = simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            };
+            simulation
+                .create_execution_environment(create_ee_args)
+                .unwrap();
+        }
+
+        // Hashes should be sorted by EE index and match the hash of the original code
+        let ee_code_hashes = simulation.ee_code_hashes();
+        assert_eq!(ee_code_hashes.len(), 2);
+        for (i, (ee_index, code_hash)) in ee_code_hashes.into_iter().enumerate() {
+            assert_eq!(ee_index, i as u64);
+            assert_eq!(code_hash.to_vec(), hash(wasm_codes[i]));
+        }
+    }
+
+    #[test]
+    fn can_check_transaction_inclusion() {
+        let shard_index = 0;
+        let (simulation, shard_transaction, shard_slot, _ee_index) =
+            test_block_with_single_transaction(
+                include_bytes!("../tests/phase2_helloworld.wasm"),
+                [0; 32],
+                vec![1, 2, 3],
+                [0; 32],
+                shard_index,
+            );
+        let shard_slot_index: u64 = shard_slot.into();
+        let block = || simulation_args::GetShardBlock {
+            shard_index,
+            shard_slot_index,
+        };
+
+        // The included transaction is found at index 0
+        assert!(simulation
+            .transaction_exists(block(), &shard_transaction)
+            .unwrap());
+        assert_eq!(
+            simulation
+                .transaction_index(block(), &shard_transaction)
+                .unwrap(),
+            Some(0)
+        );
+
+        // A transaction with different data is not found
+        let other_transaction = simulation_args::ShardTransaction {
+            data: vec![4, 5, 6],
+            ee_index: shard_transaction.ee_index,
+            condition: None,
+            state_key: None,
+        };
+        assert!(!simulation
+            .transaction_exists(block(), &other_transaction)
+            .unwrap());
+
+        // Asking about a block that doesn't exist is an error
+        let missing_block = simulation_args::GetShardBlock {
+            shard_index,
+            shard_slot_index: shard_slot_index + 1,
+        };
+        assert!(simulation
+            .transaction_exists(missing_block, &shard_transaction)
+            .is_err());
+    }
+
+    #[test]
+    fn max_ee_states_limits_ee_creation() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_max_ee_states(Some(1));
+        let create_ee_args = || simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                index: None,
+            },
+            wasm_path: None,
+            idempotency_key: None,
+        };
+
+        // The first EE fits within the limit, the second does not
+        assert_eq!(
+            simulation
+                .create_execution_environment(create_ee_args())
+                .unwrap(),
+            0
+        );
+        match simulation.create_execution_environment(create_ee_args()) {
+            Err(Error::Limit {
+                kind: LimitKind::EeState,
+                limit: 1,
+            }) => {}
+            other => panic!("expected the EE state limit to be reached, got {:?}", other),
+        }
+        assert_eq!(
+            simulation
+                .store
+                .current_beacon_state
+                .execution_environments
+                .len(),
+            1
+        );
+
+        // Removing the limit allows more EEs again
+        simulation.set_max_ee_states(None);
+        assert_eq!(
+            simulation
+                .create_execution_environment(create_ee_args())
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn can_check_shard_and_ee_existence() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+
+        assert!(simulation.shard_exists(simulation_args::ShardExists { shard_index: 0 }));
+        assert!(simulation.shard_exists(simulation_args::ShardExists {
+            shard_index: max_shards - 1
+        }));
+        assert!(!simulation.shard_exists(simulation_args::ShardExists {
+            shard_index: max_shards
+        }));
+
+        assert!(!simulation.execution_environment_exists(
+            simulation_args::ExecutionEnvironmentExists { ee_index: 0 }
+        ));
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert!(simulation.execution_environment_exists(
+            simulation_args::ExecutionEnvironmentExists { ee_index }
+        ));
+        assert!(!simulation.execution_environment_exists(
+            simulation_args::ExecutionEnvironmentExists {
+                ee_index: ee_index + 1
+            }
+        ));
+    }
+
+    /// Compute the tip hash of a shard from scratch by walking all of its blocks
+    fn tip_hash_of<T: EthSpec>(shard_blocks: &[ShardBlock<T>]) -> [u8; 32] {
+        shard_blocks.iter().fold([0; 32], |tip_hash, shard_block| {
+            next_tip_hash(&tip_hash, shard_block)
+        })
+    }
+
+    // Returns the (initial state, transaction data, expected post state) of the scout bazaar example
+    fn bazaar_test_vector() -> ([u8; 32], Vec<u8>, [u8; 32]) {
+        use simulation_args::ToBytes32;
+
+        let initial_state = "22ea9b045f8792170b45ec629c98e1b92bc6a19cd8d0e9f37baaadf2564142f4";
+        let initial_state = Vec::from_hex(initial_state).unwrap().to_bytes32().unwrap();
+        let expected_post_state =
+            "29505fd952857b5766c759bcb4af58eb8df5a91043540c1398dd987a503127fc";
+        let expected_post_state = Vec::from_hex(expected_post_state)
+            .unwrap()
+            .to_bytes32()
+            .unwrap();
+        let data: Vec<u8> = Vec::from_hex("5c0000005000000001000000000000000000000000000000000000000000000000000000000000000000000000000000020000000000000001010101010101010101010101010101010101010101010101010101010101010400000000000000").unwrap();
+        (initial_state, data, expected_post_state)
+    }
+
+    #[test]
+    fn can_replay_transaction() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let shard_index = 0;
+        let (mut simulation, _shard_transaction, shard_slot, ee_index) =
+            test_block_with_single_transaction(
+                include_bytes!("../tests/phase2_bazaar.wasm"),
+                initial_state,
+                data,
+                expected_post_state,
+                shard_index,
+            );
+        let shard_slot_index: u64 = shard_slot.into();
+
+        // Replaying the transaction reproduces the original state transition
+        let replay_args = simulation_args::ReplayTransaction {
+            shard_index,
+            shard_slot_index,
+            transaction_index: 0,
+        };
+        let receipt = simulation.replay_transaction(replay_args).unwrap();
+        assert_eq!(
+            receipt,
+            simulation_args::TransactionReceipt {
+                ee_index: ee_index.into(),
+                state_key: None,
+                pre_state: initial_state,
+                post_state: expected_post_state,
+                skipped: false,
+            }
+        );
+
+        // Replaying a transaction that doesn't exist is an error
+        let replay_args = simulation_args::ReplayTransaction {
+            shard_index,
+            shard_slot_index,
+            transaction_index: 1,
+        };
+        match simulation.replay_transaction(replay_args) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Transaction(0, 0),
+                index: 1,
+            }) => {}
+            other => panic!("expected an out of bounds transaction, got {:?}", other),
+        }
+
+        // Once the EE's state on the shard is overwritten by a copy, the replayed pre state
+        // would no longer match what actually ran
+        let replay_args = simulation_args::ReplayTransaction {
+            shard_index,
+            shard_slot_index,
+            transaction_index: 0,
+        };
+        simulation
+            .copy_ee_state(simulation_args::CopyEeState {
+                source_shard_index: 1,
+                target_shard_index: shard_index,
+                ee_index: ee_index.into(),
+            })
+            .unwrap();
+        match simulation.replay_transaction(replay_args) {
+            Err(Error::HistoryUnavailable { .. }) => {}
+            other => panic!("expected unavailable history, got {:?}", other),
+        }
+
+        // The same goes for every shard once the EE's code is replaced
+        let (mut simulation, _, _, ee_index) = test_block_with_single_transaction(
+            include_bytes!("../tests/phase2_bazaar.wasm"),
+            initial_state,
+            bazaar_test_vector().1,
+            expected_post_state,
+            shard_index,
+        );
+        let ee_index: u64 = ee_index.into();
+        simulation
+            .update_execution_environment(simulation_args::UpdateExecutionEnvironment {
+                execution_environment_index: ee_index,
+                execution_environment: simulation_args::ExecutionEnvironment {
+                    initial_state,
+                    wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+                    index: None,
+                },
+            })
+            .unwrap();
+        let replay_args = simulation_args::ReplayTransaction {
+            shard_index,
+            shard_slot_index,
+            transaction_index: 0,
+        };
+        match simulation.replay_transaction(replay_args) {
+            Err(Error::HistoryUnavailable { reason }) => assert_eq!(
+                reason,
+                format!("the code of execution environment {} was updated", ee_index)
+            ),
+            other => panic!("expected unavailable history, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn apply_blocks_rolls_back_every_block_on_failure() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let create_shard_block_args = |shard_index, ee_index| simulation_args::CreateShardBlock {
+            shard_index,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction {
+                    data,
+                    ee_index,
+                    condition: None,
+                    state_key: None,
+                }],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+        let get_ee_state = |simulation: &Simulation<MainnetEthSpec>, shard_index| {
+            simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index,
+                    shard_index,
+                    state_key: None,
+                })
+                .unwrap()
+        };
+
+        // The third block references an EE that doesn't exist, so the first two are undone
+        let batch = vec![
+            create_shard_block_args(0, ee_index),
+            create_shard_block_args(1, ee_index),
+            create_shard_block_args(2, ee_index + 1),
+        ];
+        assert!(simulation.apply_blocks(batch).is_err());
+        // The undone blocks and their transactions don't count towards the metrics
+        let metrics = simulation.metrics(simulation_args::GetMetrics {});
+        assert_eq!(metrics.shard_blocks_created, 0);
+        assert_eq!(metrics.transactions_executed, 0);
+        for shard_index in 0..3 {
+            assert_eq!(get_ee_state(&simulation, shard_index), initial_state);
+            let get_shard_block_args = simulation_args::GetShardBlock {
+                shard_index,
+                shard_slot_index: 0,
+            };
+            assert!(simulation.get_shard_block(get_shard_block_args).is_err());
+        }
+
+        // A batch of valid blocks is applied in full
+        let batch = vec![
+            create_shard_block_args(0, ee_index),
+            create_shard_block_args(1, ee_index),
+        ];
+        assert_eq!(simulation.apply_blocks(batch).unwrap(), vec![0, 0]);
+        let metrics = simulation.metrics(simulation_args::GetMetrics {});
+        assert_eq!(metrics.shard_blocks_created, 2);
+        assert_eq!(metrics.transactions_executed, 2);
+        assert_eq!(get_ee_state(&simulation, 0), expected_post_state);
+        assert_eq!(get_ee_state(&simulation, 1), expected_post_state);
+        assert_eq!(get_ee_state(&simulation, 2), initial_state);
+    }
+
+    #[test]
+    fn shard_tip_hash_matches_recomputation() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/phase2_helloworld.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // A shard without blocks has an all-zero tip hash
+        assert_eq!(simulation.shard_tip_hash(0).unwrap(), [0; 32]);
+
+        let mut previous_tip_hash = [0; 32];
+        for i in 0..3 {
+            let create_shard_block_args = simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![simulation_args::ShardTransaction {
+                        data: vec![i],
+                        ee_index,
+                        condition: None,
+                        state_key: None,
+                    }],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            };
+            simulation
+                .create_shard_block(create_shard_block_args)
+                .unwrap();
+
+            // The incrementally maintained hash changes with every block and matches a full walk
+            let tip_hash = simulation.shard_tip_hash(0).unwrap();
+            assert_ne!(tip_hash, previous_tip_hash);
+            assert_eq!(
+                tip_hash,
+                tip_hash_of(&simulation.store.shard_blocks_by_shard[&Shard::new(0)])
+            );
+            previous_tip_hash = tip_hash;
+        }
+
+        // Other shards are unaffected, and shards past the end don't exist
+        assert_eq!(simulation.shard_tip_hash(1).unwrap(), [0; 32]);
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        assert!(simulation.shard_tip_hash(max_shards).is_err());
+    }
+
+    #[test]
+    fn ee_audit_log_records_creations_in_order() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        assert!(simulation
+            .get_ee_audit_log(simulation_args::GetEeAuditLog {})
+            .is_empty());
+
+        let wasm_codes: Vec<&[u8]> = vec![
+            include_bytes!("../tests/phase2_helloworld.wasm"),
+            include_bytes!("../tests/do_nothing.wasm"),
+        ];
+        for wasm_code in wasm_codes.iter() {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: wasm_code.to_vec(),
+                index: None,
+            };
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+
+        let audit_log = simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {});
+        assert_eq!(audit_log.len(), 2);
+        for (i, entry) in audit_log.iter().enumerate() {
+            assert_eq!(entry.sequence, i as u64);
+            assert_eq!(entry.ee_index, i as u64);
+            assert_eq!(entry.action, simulation_args::EeAuditAction::Create);
+            assert_eq!(entry.code_hash, hash_bytes(wasm_codes[i]));
+        }
+    }
+
+    /// Rejects transactions with empty data, and passes all other data through unchanged
+    #[derive(Debug)]
+    struct RejectEmptyData;
+
+    impl TransactionPreprocessor for RejectEmptyData {
+        fn preprocess(&self, ee_index: u64, data: &[u8]) -> Result<Vec<u8>> {
+            if data.is_empty() {
+                return Err(Error::PreprocessingFailed {
+                    ee_index,
+                    reason: "empty transaction data".to_string(),
+                });
+            }
+            Ok(data.to_vec())
+        }
+    }
+
+    /// Drops the first byte of transaction data
+    #[derive(Debug)]
+    struct DropFirstByte;
+
+    impl TransactionPreprocessor for DropFirstByte {
+        fn preprocess(&self, _: u64, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().skip(1).copied().collect())
+        }
+    }
+
+    #[test]
+    fn replaying_a_transaction_preprocesses_its_data() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_preprocessor(Some(Box::new(DropFirstByte)));
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // The EE only sees the test vector once the preprocessor drops the extra first byte
+        let prefixed_data = [&[0xff][..], &data[..]].concat();
+        let transaction = simulation_args::ShardTransaction::from_data(ee_index, &prefixed_data);
+        let shard_block_result = simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(
+            shard_block_result.transaction_results[0].post_state,
+            expected_post_state
+        );
+
+        // Replaying the transaction gives the same result as the original execution
+        let replayed = simulation
+            .replay_transaction(simulation_args::ReplayTransaction {
+                shard_index: 0,
+                shard_slot_index: 0,
+                transaction_index: 0,
+            })
+            .unwrap();
+        assert_eq!(replayed, shard_block_result.transaction_results[0]);
+    }
+
+    #[test]
+    fn transaction_preprocessor_can_reject_transactions() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_preprocessor(Some(Box::new(RejectEmptyData)));
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/phase2_helloworld.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let shard_block_with_data = |data: Vec<u8>| simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction {
+                    data,
+                    ee_index,
+                    condition: None,
+                    state_key: None,
+                }],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+
+        // Data accepted by the preprocessor executes as normal
+        simulation
+            .create_shard_block(shard_block_with_data(vec![1]))
+            .unwrap();
+
+        // Rejected data surfaces the preprocessor's error, and no block is added
+        let err = simulation
+            .create_shard_block(shard_block_with_data(vec![]))
+            .unwrap_err();
+        match err {
+            Error::PreprocessingFailed { ee_index: e, .. } => assert_eq!(e, ee_index),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(
+            simulation.store.shard_blocks_by_shard[&Shard::new(0)].len(),
+            1
+        );
+    }
+
+    #[test]
+    fn audit_ee_references_finds_dangling_references() {
+        let (mut simulation, _, _, ee_index) = test_block_with_single_transaction(
+            include_bytes!("../tests/phase2_helloworld.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            2,
+        );
+        let ee_index: u64 = ee_index.into();
+
+        // Keyed states and conditions that refer to an existing EE aren't dangling
+        let shard_states = &mut simulation.store.current_beacon_state.shard_states;
+        set_keyed_ee_state(&mut shard_states[3], 0, 7, Root::from([9; 32]), None).unwrap();
+        let shard_blocks = simulation
+            .store
+            .shard_blocks_by_shard
+            .get_mut(&Shard::new(2))
+            .unwrap();
+        shard_blocks[0].transactions[0].condition = Some(Condition {
+            ee_index: EeIndex::new(ee_index),
+            state: Root::from([0; 32]),
+            equal: true,
+        });
+
+        // Nothing dangles in a healthy simulation
+        assert!(simulation.audit_ee_references().is_empty());
+
+        // Simulate a buggy remap that dropped the EE but left its states and transactions behind
+        simulation
+            .store
+            .current_beacon_state
+            .execution_environments
+            .pop();
+
+        let reference_errors = simulation.audit_ee_references();
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        assert_eq!(reference_errors.len(), max_shards + 3);
+        assert!(reference_errors.contains(&ReferenceError {
+            shard_index: 0,
+            location: ReferenceLocation::EeState,
+            ee_index,
+        }));
+        assert!(reference_errors.contains(&ReferenceError {
+            shard_index: 2,
+            location: ReferenceLocation::Transaction {
+                shard_slot_index: 0,
+                transaction_index: 0,
+            },
+            ee_index,
+        }));
+        assert!(reference_errors.contains(&ReferenceError {
+            shard_index: 3,
+            location: ReferenceLocation::KeyedEeState { state_key: 7 },
+            ee_index,
+        }));
+        assert!(reference_errors.contains(&ReferenceError {
+            shard_index: 2,
+            location: ReferenceLocation::TransactionCondition {
+                shard_slot_index: 0,
+                transaction_index: 0,
+            },
+            ee_index,
+        }));
+    }
+
+    #[test]
+    fn report_summarizes_simulation() {
+        let wasm_code = include_bytes!("../tests/phase2_helloworld.wasm");
+        let (mut simulation, _, _, _) =
+            test_block_with_single_transaction(wasm_code, [0; 32], vec![], [0; 32], 1);
+
+        let report = simulation.report();
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        let expected = format!(
+            "Beacon chain
+  Execution environments: 1
+    0: code hash {}
+Shard 1
+  Blocks: 1
+  Transactions: 1
+  EE states: 1 (32 bytes)
+Shards without blocks: {}
+",
+            to_hex(&hash_bytes(wasm_code)),
+            max_shards - 1
+        );
+        assert_eq!(report, expected);
+
+        // Evicted blocks and keyed states are counted
+        let shard_states = &mut simulation.store.current_beacon_state.shard_states;
+        set_keyed_ee_state(&mut shard_states[1], 0, 5, Root::from([9; 32]), None).unwrap();
+        *simulation
+            .store
+            .num_evicted_blocks_by_shard
+            .get_mut(&Shard::new(1))
+            .unwrap() += 2;
+        let report = simulation.report();
+        assert!(
+            report.contains(
+                "Shard 1
+  Blocks: 3
+  Transactions: 1
+  EE states: 2 (64 bytes)
+"
+            ),
+            "unexpected report:\n{}",
+            report
+        );
+    }
+
+    #[test]
+    fn copied_ee_state_is_independent() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let (mut simulation, transaction, _, ee_index) = test_block_with_single_transaction(
+            include_bytes!("../tests/phase2_bazaar.wasm"),
+            initial_state,
+            data,
+            expected_post_state,
+            0,
+        );
+        let ee_index: u64 = ee_index.into();
+        let ee_state = |simulation: &Simulation<MainnetEthSpec>, shard_index| {
+            simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index,
+                    shard_index,
+                    state_key: None,
+                })
+                .unwrap()
+        };
+        assert_eq!(ee_state(&simulation, 1), initial_state);
+
+        simulation
+            .copy_ee_state(simulation_args::CopyEeState {
+                source_shard_index: 0,
+                target_shard_index: 1,
+                ee_index,
+            })
+            .unwrap();
+        assert_eq!(ee_state(&simulation, 1), expected_post_state);
+
+        // Resetting the source shard (from a shard that was never touched) leaves the target alone
+        simulation
+            .copy_ee_state(simulation_args::CopyEeState {
+                source_shard_index: 2,
+                target_shard_index: 0,
+                ee_index,
+            })
+            .unwrap();
+        assert_eq!(ee_state(&simulation, 0), initial_state);
+        assert_eq!(ee_state(&simulation, 1), expected_post_state);
+
+        // ...and executing on the source shard again leaves the target alone
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(ee_state(&simulation, 0), expected_post_state);
+        simulation
+            .copy_ee_state(simulation_args::CopyEeState {
+                source_shard_index: 2,
+                target_shard_index: 1,
+                ee_index,
+            })
+            .unwrap();
+        assert_eq!(ee_state(&simulation, 0), expected_post_state);
+        assert_eq!(ee_state(&simulation, 1), initial_state);
+
+        // Both shards and the EE must exist
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        let bad_args = vec![
+            (max_shards, 1, ee_index),
+            (0, max_shards, ee_index),
+            (0, 1, ee_index + 1),
+        ];
+        for (source_shard_index, target_shard_index, ee_index) in bad_args {
+            assert!(simulation
+                .copy_ee_state(simulation_args::CopyEeState {
+                    source_shard_index,
+                    target_shard_index,
+                    ee_index,
+                })
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn to_index_rejects_indices_that_would_truncate() {
+        assert_eq!(to_index(0).unwrap(), 0);
+        let max_index = usize::max_value() as u64;
+        assert_eq!(to_index(max_index).unwrap(), usize::max_value());
+
+        // Only reachable where usize is narrower than u64
+        if let Some(too_big) = max_index.checked_add(1) {
+            match to_index(too_big).unwrap_err() {
+                Error::Limit {
+                    kind: LimitKind::AddressableIndices,
+                    limit,
+                } => assert_eq!(limit, usize::max_value()),
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn conditional_transactions_only_execute_if_condition_holds() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let wasm_codes: Vec<&[u8]> = vec![
+            include_bytes!("../tests/phase2_bazaar.wasm"),
+            include_bytes!("../tests/phase2_helloworld.wasm"),
+        ];
+        for wasm_code in wasm_codes {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state,
+                wasm_code: wasm_code.to_vec(),
+                index: None,
+            };
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+        let bazaar_still_initial = simulation_args::Condition {
+            ee_index: 0,
+            state: initial_state,
+            comparison: simulation_args::Comparison::Equal,
+        };
+        let bazaar_transaction = simulation_args::ShardTransaction {
+            data,
+            ee_index: 0,
+            condition: Some(bazaar_still_initial.clone()),
+            state_key: None,
+        };
+
+        // The condition holds, so the transaction executes
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![bazaar_transaction.clone()],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // Now the bazaar EE state has changed, so the same transaction is skipped, while one
+        // conditional on the state having changed executes
+        let bazaar_changed = simulation_args::Condition {
+            comparison: simulation_args::Comparison::NotEqual,
+            ..bazaar_still_initial
+        };
+        let helloworld_transaction = simulation_args::ShardTransaction {
+            data: vec![],
+            ee_index: 1,
+            condition: Some(bazaar_changed),
+            state_key: None,
+        };
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![bazaar_transaction, helloworld_transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let get_skipped = |shard_slot_index| {
+            simulation
+                .get_skipped_transactions(simulation_args::GetShardBlock {
+                    shard_index: 0,
+                    shard_slot_index,
+                })
+                .unwrap()
+        };
+        assert_eq!(get_skipped(0), Vec::<u64>::new());
+        assert_eq!(get_skipped(1), vec![0]);
+        let bazaar_state = simulation
+            .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index: 0,
+                shard_index: 0,
+                state_key: None,
+            })
+            .unwrap();
+        assert_eq!(bazaar_state, expected_post_state);
+
+        // The skipped transaction is marked as such when replayed
+        let receipt = simulation
+            .replay_transaction(simulation_args::ReplayTransaction {
+                shard_index: 0,
+                shard_slot_index: 1,
+                transaction_index: 0,
+            })
+            .unwrap();
+        assert_eq!(
+            receipt,
+            simulation_args::TransactionReceipt {
+                ee_index: 0,
+                state_key: None,
+                pre_state: expected_post_state,
+                post_state: expected_post_state,
+                skipped: true,
+            }
+        );
+    }
+
+    #[test]
+    fn can_create_ee_from_wasm_path() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let wasm_path = || {
+            Some(PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/phase2_helloworld.wasm"
+            )))
+        };
+        let create_ee_args = |wasm_code: Vec<u8>, wasm_path: Option<PathBuf>| {
+            simulation_args::CreateExecutionEnvironment {
+                ee: simulation_args::ExecutionEnvironment {
+                    initial_state: [0; 32],
+                    wasm_code,
+                    index: None,
+                },
+                wasm_path,
+                idempotency_key: None,
+            }
+        };
+
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args(vec![], wasm_path()))
+            .unwrap();
+        let ee = simulation
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index })
+            .unwrap();
+        assert_eq!(
+            ee.wasm_code,
+            include_bytes!("../tests/phase2_helloworld.wasm").to_vec()
+        );
+
+        // Exactly one source of code must be given
+        for (wasm_code, wasm_path) in vec![(vec![0], wasm_path()), (vec![], None)] {
+            match simulation.create_execution_environment(create_ee_args(wasm_code, wasm_path)) {
+                Err(Error::InvalidArgs { .. }) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+
+        // IO errors are surfaced with the offending path
+        let missing_path = PathBuf::from("does/not/exist.wasm");
+        match simulation.create_execution_environment(create_ee_args(vec![], Some(missing_path))) {
+            Err(Error::Io { path, .. }) => assert_eq!(path, PathBuf::from("does/not/exist.wasm")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn can_compare_shard_states() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let (mut simulation, _, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/phase2_bazaar.wasm"),
+            initial_state,
+            data,
+            expected_post_state,
+            0,
+        );
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // Only the bazaar EE has executed, and only on shard 0
+        assert_eq!(simulation.compare_shard_states(0, 1).unwrap(), vec![0]);
+        assert_eq!(simulation.compare_shard_states(1, 0).unwrap(), vec![0]);
+        assert!(simulation.compare_shard_states(1, 2).unwrap().is_empty());
+        assert!(simulation.compare_shard_states(0, 0).unwrap().is_empty());
+
+        // An EE state present on only one shard also differs
+        simulation.store.current_beacon_state.shard_states[2]
+            .execution_environment_states
+            .push(Root::from([0; 32]))
+            .unwrap();
+        assert_eq!(simulation.compare_shard_states(1, 2).unwrap(), vec![2]);
+
+        // Keyed state slots are compared too, with unwritten slots holding the initial state
+        let shard_states = &mut simulation.store.current_beacon_state.shard_states;
+        set_keyed_ee_state(&mut shard_states[1], 1, 5, Root::from([0; 32]), None).unwrap();
+        assert_eq!(simulation.compare_shard_states(1, 2).unwrap(), vec![2]);
+        let shard_states = &mut simulation.store.current_beacon_state.shard_states;
+        set_keyed_ee_state(&mut shard_states[1], 1, 5, Root::from([9; 32]), None).unwrap();
+        assert_eq!(simulation.compare_shard_states(1, 2).unwrap(), vec![1, 2]);
+        assert_eq!(simulation.compare_shard_states(2, 1).unwrap(), vec![1, 2]);
+
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        assert!(simulation.compare_shard_states(0, max_shards).is_err());
+        assert!(simulation.compare_shard_states(max_shards, 0).is_err());
+    }
+
+    /// Rejects transactions with more than a fixed number of bytes of data
+    #[derive(Debug)]
+    struct MaxDataSize(usize);
+
+    impl TransactionValidator for MaxDataSize {
+        fn validate(&self, _: u64, data: &[u8]) -> Result<()> {
+            if data.len() > self.0 {
+                return Err(Error::PolicyRejected {
+                    reason: format!("data is larger than {} bytes", self.0),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transaction_validator_can_reject_transactions() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_validator(Some(Box::new(MaxDataSize(2))));
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/phase2_helloworld.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let shard_block_with_data = |data: Vec<u8>| simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction {
+                    data,
+                    ee_index,
+                    condition: None,
+                    state_key: None,
+                }],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+
+        simulation
+            .create_shard_block(shard_block_with_data(vec![1, 2]))
+            .unwrap();
+        match simulation.create_shard_block(shard_block_with_data(vec![1, 2, 3])) {
+            Err(Error::PolicyRejected { reason }) => {
+                assert_eq!(reason, "data is larger than 2 bytes")
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            simulation.store.shard_blocks_by_shard[&Shard::new(0)].len(),
+            1
+        );
+    }
+
+    #[test]
+    fn frozen_ees_reject_transactions_but_remain_queryable() {
+        let (mut simulation, transaction, _, ee_index) = test_block_with_single_transaction(
+            include_bytes!("../tests/phase2_helloworld.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            0,
+        );
+        let ee_index: u64 = ee_index.into();
+        let create_shard_block_args = || simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![transaction.clone()],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+
+        simulation.freeze_execution_environment(ee_index).unwrap();
+        match simulation.create_shard_block(create_shard_block_args()) {
+            Err(Error::Frozen { ee_index: e }) => assert_eq!(e, ee_index),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(simulation
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index })
+            .is_ok());
+        assert!(simulation
+            .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index,
+                shard_index: 0,
+                state_key: None,
+            })
+            .is_ok());
+
+        simulation.unfreeze_execution_environment(ee_index).unwrap();
+        simulation
+            .create_shard_block(create_shard_block_args())
+            .unwrap();
+
+        // Both changes are recorded in the audit log
+        let actions: Vec<simulation_args::EeAuditAction> = simulation
+            .get_ee_audit_log(simulation_args::GetEeAuditLog {})
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                simulation_args::EeAuditAction::Create,
+                simulation_args::EeAuditAction::Freeze,
+                simulation_args::EeAuditAction::Unfreeze,
+            ]
+        );
+
+        assert!(simulation
+            .freeze_execution_environment(ee_index + 1)
+            .is_err());
+        assert!(simulation
+            .unfreeze_execution_environment(ee_index + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn memory_budget_evicts_oldest_blocks_of_longest_shards() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/phase2_helloworld.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let create_shard_block_args = |shard_index| simulation_args::CreateShardBlock {
+            shard_index,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction {
+                    data: vec![],
+                    ee_index,
+                    condition: None,
+                    state_key: None,
+                }],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+
+        // All blocks are the same size, so the budget is expressed in blocks
+        simulation
+            .create_shard_block(create_shard_block_args(0))
+            .unwrap();
+        let block_size = simulation.store.block_bytes;
+        simulation.set_memory_budget(Some(3 * block_size));
+
+        // Up to the threshold nothing is evicted
+        simulation
+            .create_shard_block(create_shard_block_args(0))
+            .unwrap();
+        simulation
+            .create_shard_block(create_shard_block_args(1))
+            .unwrap();
+        assert_eq!(simulation.eviction_stats(), EvictionStats::default());
+        let tip_hash = simulation.shard_tip_hash(0).unwrap();
+
+        // Going over evicts the oldest block of shard 0, which has the most blocks
+        let shard_slot_index = simulation
+            .create_shard_block(create_shard_block_args(1))
+            .unwrap()
+            .block_index;
+        assert_eq!(shard_slot_index, 1);
+        assert_eq!(
+            simulation.eviction_stats(),
+            EvictionStats {
+                blocks_evicted: 1,
+                bytes_evicted: block_size as u64,
+            }
+        );
+        assert_eq!(simulation.store.block_bytes, 3 * block_size);
+
+        // The evicted block is gone, but other block indices and derived state are unaffected
+        let get_shard_block = |shard_index, shard_slot_index| {
+            simulation.get_shard_block(simulation_args::GetShardBlock {
+                shard_index,
+                shard_slot_index,
+            })
+        };
+        assert!(get_shard_block(0, 0).is_err());
+        assert!(get_shard_block(0, 1).is_ok());
+        assert!(get_shard_block(1, 0).is_ok());
+        assert!(get_shard_block(1, 1).is_ok());
+        assert_eq!(simulation.shard_tip_hash(0).unwrap(), tip_hash);
+
+        // Later transactions on the shard can't be replayed without the evicted ones
+        match simulation.replay_transaction(simulation_args::ReplayTransaction {
+            shard_index: 0,
+            shard_slot_index: 1,
+            transaction_index: 0,
+        }) {
+            Err(Error::HistoryUnavailable { reason }) => {
+                assert_eq!(reason, "blocks on shard 0 were evicted")
+            }
+            other => panic!("expected unavailable history, got {:?}", other),
+        }
+        assert!(simulation
+            .replay_transaction(simulation_args::ReplayTransaction {
+                shard_index: 1,
+                shard_slot_index: 1,
+                transaction_index: 0,
+            })
+            .is_ok());
+        assert_eq!(
+            simulation
+                .create_shard_block(create_shard_block_args(0))
+                .unwrap()
+                .block_index,
+            2
+        );
+    }
+
+    #[test]
+    fn can_get_execution_environment_module_info() {
+        use simulation_args::{FunctionSignature, ModuleExport, ModuleImport, ModuleItemKind};
+
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let get_module_info = |simulation: &Simulation<MainnetEthSpec>, index| {
+            simulation.get_execution_environment_module_info(
+                simulation_args::GetExecutionEnvironmentModuleInfo {
+                    execution_environment_index: index,
+                },
+            )
+        };
+        match get_module_info(&simulation, 0) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: 0,
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let info = get_module_info(&simulation, ee_index).unwrap();
+
+        let host_function = |name: &str| ModuleImport {
+            module: "env".to_string(),
+            name: name.to_string(),
+            kind: ModuleItemKind::Function,
+            signature: Some(FunctionSignature {
+                params: vec!["i32".to_string()],
+                results: vec![],
+            }),
+        };
+        assert_eq!(
+            info.imports,
+            vec![
+                host_function("eth2_loadPreStateRoot"),
+                host_function("eth2_savePostStateRoot"),
+            ]
+        );
+        assert_eq!(
+            info.exports,
+            vec![
+                ModuleExport {
+                    name: "memory".to_string(),
+                    kind: ModuleItemKind::Memory,
+                    signature: None,
+                },
+                ModuleExport {
+                    name: "main".to_string(),
+                    kind: ModuleItemKind::Function,
+                    signature: Some(FunctionSignature {
+                        params: vec![],
+                        results: vec![],
+                    }),
+                },
+            ]
+        );
+
+        match module_info(b"not a wasm module") {
+            Err(Error::InvalidWasm { .. }) => {}
+            other => panic!("expected InvalidWasm, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deleting_an_execution_environment_keeps_later_indices() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        for initial_state in 0..2 {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state: [initial_state; 32],
+                wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                index: None,
+            };
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+
+        simulation
+            .delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+                execution_environment_index: 0,
+            })
+            .unwrap();
+
+        // EE 0 is gone...
+        assert!(!simulation.execution_environment_exists(
+            simulation_args::ExecutionEnvironmentExists { ee_index: 0 }
+        ));
+        match simulation
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index: 0 })
+        {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: 0,
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+        match simulation.delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+            execution_environment_index: 0,
+        }) {
+            Err(Error::OutOfBounds { .. }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+
+        // ...but EE 1 is still reachable at the same index
+        let ee = simulation
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index: 1 })
+            .unwrap();
+        assert_eq!(ee.initial_state, [1; 32]);
+        let transaction = |ee_index| simulation_args::ShardTransaction {
+            data: vec![],
+            ee_index,
+            condition: None,
+            state_key: None,
+        };
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction(1)],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // New transactions can't reference the deleted EE
+        match simulation.create_shard_block(simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![transaction(0)],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        }) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: 0,
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+
+        // A new EE gets a fresh index rather than reusing the deleted one
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [2; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(ee_index, 2);
+
+        let log = simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {});
+        assert_eq!(log[2].ee_index, 0);
+        assert_eq!(log[2].action, simulation_args::EeAuditAction::Delete);
+    }
+
+    #[test]
+    fn can_list_execution_environments() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let list = |simulation: &Simulation<MainnetEthSpec>| {
+            simulation.list_execution_environments(simulation_args::ListExecutionEnvironments {})
+        };
+        assert!(list(&simulation).is_empty());
+
+        for initial_state in 0..3 {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state: [initial_state; 32],
+                wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                index: None,
+            };
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+
+        let ees = list(&simulation);
+        assert_eq!(ees.len(), 3);
+        for (i, ee) in ees.iter().enumerate() {
+            assert_eq!(ee.index, Some(i as u64));
+            assert_eq!(ee.initial_state, [i as u8; 32]);
+        }
+
+        // Deleted EEs aren't listed, and the others keep their indices
+        simulation
+            .delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+                execution_environment_index: 1,
+            })
+            .unwrap();
+        let ee_indices: Vec<Option<u64>> = list(&simulation).iter().map(|ee| ee.index).collect();
+        assert_eq!(ee_indices, vec![Some(0), Some(2)]);
+    }
+
+    // The SHA-256 of no bytes, the state root of a shard without EE states
+    const EMPTY_SHARD_STATE_ROOT: &str =
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn can_get_shard_chain() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let get_shard_chain = |simulation: &Simulation<MainnetEthSpec>, shard_index| {
+            simulation.get_shard_chain(simulation_args::GetShardChain { shard_index })
+        };
+        assert_eq!(
+            get_shard_chain(&simulation, 1).unwrap(),
+            simulation_args::ShardChainInfo {
+                num_blocks: 0,
+                num_ee_states: 0,
+                state_root: EMPTY_SHARD_STATE_ROOT.to_string(),
+            }
+        );
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        for _ in 0..2 {
+            let transaction = simulation_args::ShardTransaction {
+                data: vec![],
+                ee_index,
+                condition: None,
+                state_key: None,
+            };
+            simulation
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 1,
+                    shard_block: simulation_args::ShardBlock {
+                        transactions: vec![transaction],
+                        block_hash: None,
+                        parent_hash: None,
+                    },
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            get_shard_chain(&simulation, 1).unwrap(),
+            simulation_args::ShardChainInfo {
+                num_blocks: 2,
+                num_ee_states: 1,
+                // The SHA-256 of the single zero EE state
+                state_root: "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+                    .to_string(),
+            }
+        );
+        // Other shards have the EE state, but no blocks
+        assert_eq!(get_shard_chain(&simulation, 0).unwrap().num_blocks, 0);
+
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        match get_shard_chain(&simulation, max_shards) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                ..
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_update_execution_environment() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let do_nothing: &[u8] = include_bytes!("../tests/do_nothing.wasm");
+        let helloworld: &[u8] = include_bytes!("../tests/phase2_helloworld.wasm");
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [1; 32],
+            wasm_code: do_nothing.to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let update = |ee_index| simulation_args::UpdateExecutionEnvironment {
+            execution_environment_index: ee_index,
+            execution_environment: simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: helloworld.to_vec(),
+                index: None,
+            },
+        };
+        simulation
+            .update_execution_environment(update(ee_index))
+            .unwrap();
+
+        // The EE keeps its index and initial state, but has the new code
+        let ee = simulation
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index })
+            .unwrap();
+        assert_eq!(ee.index, Some(ee_index));
+        assert_eq!(ee.wasm_code, helloworld.to_vec());
+        assert_eq!(ee.initial_state, [1; 32]);
+        let log = simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {});
+        assert_eq!(log[1].action, simulation_args::EeAuditAction::Update);
+        assert_eq!(log[1].code_hash, hash_bytes(helloworld));
+
+        match simulation.update_execution_environment(update(1)) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: 1,
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_reset() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            1,
+        );
+        let genesis_state_root = Simulation::<MainnetEthSpec>::new().state_root();
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 2,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let summary = simulation.reset(simulation_args::Reset {});
+        assert_eq!(
+            summary,
+            simulation_args::ResetSummary {
+                num_execution_environments: 1,
+                num_shard_blocks: 2,
+            }
+        );
+
+        assert!(simulation
+            .list_execution_environments(simulation_args::ListExecutionEnvironments {})
+            .is_empty());
+        for shard_index in 0..3 {
+            assert_eq!(
+                simulation
+                    .get_shard_chain(simulation_args::GetShardChain { shard_index })
+                    .unwrap(),
+                simulation_args::ShardChainInfo {
+                    num_blocks: 0,
+                    num_ee_states: 0,
+                    state_root: EMPTY_SHARD_STATE_ROOT.to_string(),
+                }
+            );
+        }
+        assert!(simulation.verify_state_root(genesis_state_root));
+        // The EE audit log is append-only, so it survives the reset
+        let audit_log = simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {});
+        assert_eq!(audit_log.len(), 1);
+        assert_eq!(audit_log[0].action, simulation_args::EeAuditAction::Create);
+
+        // The simulation is usable as normal afterwards
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(ee_index, 0);
+        let audit_log = simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {});
+        assert_eq!(audit_log.len(), 2);
+        assert_eq!(audit_log[1].sequence, 1);
+    }
+
+    #[test]
+    fn failed_shard_blocks_leave_the_shard_unchanged() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let ee_state = |simulation: &Simulation<MainnetEthSpec>| {
+            simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index,
+                    shard_index: 0,
+                    state_key: None,
+                })
+                .unwrap()
+        };
+
+        // The first transaction changes the EE state, but the second refers to a missing EE
+        let transactions = vec![
+            simulation_args::ShardTransaction {
+                data,
+                ee_index,
+                condition: None,
+                state_key: None,
+            },
+            simulation_args::ShardTransaction {
+                data: vec![],
+                ee_index: 5,
+                condition: None,
+                state_key: None,
+            },
+        ];
+        match simulation.create_shard_block(simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: transactions.clone(),
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        }) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ExecutionEnvironment,
+                index: 5,
+            }) => {}
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+
+        // The first transaction's state change was rolled back, and no block was stored
+        assert_eq!(ee_state(&simulation), initial_state);
+        assert_eq!(
+            simulation
+                .get_shard_chain(simulation_args::GetShardChain { shard_index: 0 })
+                .unwrap()
+                .num_blocks,
+            0
+        );
+        assert_eq!(simulation.shard_tip_hash(0).unwrap(), [0; 32]);
+
+        // Without the failing transaction the block applies as usual
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: transactions[..1].to_vec(),
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(ee_state(&simulation), expected_post_state);
+    }
+
+    #[test]
+    fn can_get_simulation_state() {
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            1,
+        );
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 1,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        let simulation_state = simulation.simulation_state(simulation_args::GetSimulationState {});
+        assert_eq!(simulation_state.num_execution_environments, 1);
+        assert_eq!(simulation_state.num_shard_chains, max_shards as u64);
+        let mut expected_block_counts = vec![0; max_shards];
+        expected_block_counts[1] = 2;
+        assert_eq!(simulation_state.shard_block_counts, expected_block_counts);
+    }
+
+    #[test]
+    fn create_shard_block_returns_transaction_results() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ees = vec![
+            (
+                initial_state,
+                include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+            ),
+            ([7; 32], include_bytes!("../tests/do_nothing.wasm").to_vec()),
+        ];
+        for (initial_state, wasm_code) in ees {
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee: simulation_args::ExecutionEnvironment {
+                        initial_state,
+                        wasm_code,
+                        index: None,
+                    },
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+
+        let shard_block_result = simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![
+                        simulation_args::ShardTransaction {
+                            data,
+                            ee_index: 0,
+                            condition: None,
+                            state_key: None,
+                        },
+                        simulation_args::ShardTransaction {
+                            data: vec![],
+                            ee_index: 1,
+                            condition: None,
+                            state_key: None,
+                        },
+                    ],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_eq!(
+            shard_block_result,
+            simulation_args::ShardBlockResult {
+                block_index: 0,
+                transaction_results: vec![
+                    simulation_args::TransactionReceipt {
+                        ee_index: 0,
+                        state_key: None,
+                        pre_state: initial_state,
+                        post_state: expected_post_state,
+                        skipped: false,
+                    },
+                    simulation_args::TransactionReceipt {
+                        ee_index: 1,
+                        state_key: None,
+                        pre_state: [7; 32],
+                        post_state: [7; 32],
+                        skipped: false,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn advancing_slots_finalizes_shard_tips() {
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            0,
+        );
+        let create_shard_block_args = || simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![transaction.clone()],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+        let finalized_block_counts = |simulation: &Simulation<MainnetEthSpec>| {
+            simulation
+                .simulation_state(simulation_args::GetSimulationState {})
+                .finalized_block_counts
+        };
+        let mut expected_finalized_block_counts = vec![0; max_shards];
+        assert_eq!(
+            simulation
+                .simulation_state(simulation_args::GetSimulationState {})
+                .slot,
+            0
+        );
+        assert_eq!(
+            finalized_block_counts(&simulation),
+            expected_finalized_block_counts
+        );
+
+        // The block already on shard 0 is finalized by the first slot...
+        assert_eq!(simulation.advance_slot(simulation_args::AdvanceSlot {}), 1);
+        expected_finalized_block_counts[0] = 1;
+        assert_eq!(
+            finalized_block_counts(&simulation),
+            expected_finalized_block_counts
+        );
+
+        // ...while a block added afterwards waits for the next one
+        simulation
+            .create_shard_block(create_shard_block_args())
+            .unwrap();
+        assert_eq!(
+            finalized_block_counts(&simulation),
+            expected_finalized_block_counts
+        );
+        assert_eq!(simulation.advance_slot(simulation_args::AdvanceSlot {}), 2);
+        expected_finalized_block_counts[0] = 2;
+        let simulation_state = simulation.simulation_state(simulation_args::GetSimulationState {});
+        assert_eq!(simulation_state.slot, 2);
+        assert_eq!(
+            simulation_state.finalized_block_counts,
+            expected_finalized_block_counts
+        );
+    }
+
+    #[test]
+    fn shard_blocks_referencing_missing_ees_are_rejected() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            0,
+        );
+        let missing_ee_transaction = simulation_args::ShardTransaction {
+            ee_index: 99,
+            ..transaction.clone()
+        };
+        let tip_hash = simulation.shard_tip_hash(0).unwrap();
+
+        match simulation.create_shard_block(simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![transaction, missing_ee_transaction],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        }) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::TransactionExecutionEnvironment(1),
+                index: 99,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(simulation.shard_tip_hash(0).unwrap(), tip_hash);
+    }
+
+    #[test]
+    fn can_create_execution_environments_in_bulk() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = |initial_state| simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+
+        let ee_indices = simulation
+            .create_execution_environments(simulation_args::CreateExecutionEnvironments {
+                execution_environments: (0..5).map(|i| ee([i; 32])).collect(),
+            })
+            .unwrap();
+        assert_eq!(ee_indices, (0..5).collect::<Vec<u64>>());
+        for ee_index in ee_indices {
+            let ee_state = simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index,
+                    shard_index: 0,
+                    state_key: None,
+                })
+                .unwrap();
+            assert_eq!(ee_state, [ee_index as u8; 32]);
+        }
+
+        // A single invalid EE means none of the batch is created
+        let mut invalid_ee = ee([0; 32]);
+        invalid_ee.wasm_code = vec![];
+        match simulation.create_execution_environments(
+            simulation_args::CreateExecutionEnvironments {
+                execution_environments: vec![ee([0; 32]), invalid_ee],
+            },
+        ) {
+            Err(Error::InvalidArgs { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        simulation.set_max_ee_states(Some(6));
+        match simulation.create_execution_environments(
+            simulation_args::CreateExecutionEnvironments {
+                execution_environments: vec![ee([0; 32]), ee([0; 32])],
+            },
+        ) {
+            Err(Error::Limit {
+                kind: LimitKind::EeState,
+                limit: 6,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            simulation
+                .list_execution_environments(simulation_args::ListExecutionEnvironments {})
+                .len(),
+            5
+        );
+    }
+
+    #[test]
+    fn metrics_count_successful_work_and_decode_errors() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![],
+            [0; 32],
+            0,
+        );
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction.clone(), transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // EE code over the maximum size fails to decode
+        let max_code_size = <MainnetEthSpec as EthSpec>::MaxEEByteCodeSize::to_usize();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: vec![0; max_code_size + 1],
+            index: None,
+        };
+        simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            simulation.metrics(simulation_args::GetMetrics {}),
+            simulation_args::MetricsSnapshot {
+                execution_environments_created: 1,
+                shard_blocks_created: 2,
+                transactions_executed: 3,
+                decode_errors: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn can_export_and_import_state() {
+        let (mut simulation, transaction, _, ee_index) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [3; 32],
+            vec![],
+            [3; 32],
+            2,
+        );
+        simulation.advance_slot(simulation_args::AdvanceSlot {});
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 5,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+            .unwrap();
+        simulation
+            .freeze_execution_environment(ee_index.into())
+            .unwrap();
+
+        let imported =
+            Simulation::<MainnetEthSpec>::import_state(&simulation.export_state()).unwrap();
+        assert_eq!(
+            imported.simulation_state(simulation_args::GetSimulationState {}),
+            simulation.simulation_state(simulation_args::GetSimulationState {})
+        );
+        assert_eq!(imported.state_root(), simulation.state_root());
+        // The imported state is the state as of operation 0
+        assert_eq!(
+            imported.get_state_root_at(simulation_args::GetStateRootAt { seq: 0 }),
+            simulation_args::StateRootAt {
+                seq: 0,
+                state_root: simulation.state_root(),
+            }
+        );
+        assert_eq!(imported.store.state_roots.len(), 1);
+        assert_eq!(imported.store.frozen_ees, simulation.store.frozen_ees);
+        assert_eq!(
+            imported.get_ee_audit_log(simulation_args::GetEeAuditLog {}),
+            simulation.get_ee_audit_log(simulation_args::GetEeAuditLog {})
+        );
+        assert_eq!(imported.store.ee_audit_log.len(), 2);
+        assert_eq!(imported.store.block_bytes, simulation.store.block_bytes);
+        for shard_index in 0..<MainnetEthSpec as EthSpec>::MaxShards::to_u64() {
+            assert_eq!(
+                imported.shard_tip_hash(shard_index).unwrap(),
+                simulation.shard_tip_hash(shard_index).unwrap()
+            );
+        }
+        assert_eq!(
+            imported.store.shard_blocks_by_shard,
+            simulation.store.shard_blocks_by_shard
+        );
+        assert_eq!(
+            imported.store.skipped_transactions_by_shard,
+            simulation.store.skipped_transactions_by_shard
+        );
+
+        // Truncated bytes are rejected rather than producing a broken simulation
+        let exported = simulation.export_state();
+        match Simulation::<MainnetEthSpec>::import_state(&exported[..exported.len() - 1]) {
+            Err(Error::Deserialize { .. }) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn can_get_transaction() {
+        let (simulation, transaction, shard_slot, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![1, 2, 3],
+            [0; 32],
+            1,
+        );
+        let get_transaction = |shard_index, shard_slot_index, transaction_index| {
+            simulation.get_transaction(simulation_args::GetTransaction {
+                shard_index,
+                shard_slot_index,
+                transaction_index,
+            })
+        };
+
+        let found = get_transaction(1, shard_slot.as_u64(), 0).unwrap();
+        assert_eq!(found.data, transaction.data);
+        assert_eq!(found.ee_index, transaction.ee_index);
+
+        match get_transaction(64, 0, 0) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                index: 64,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match get_transaction(1, 1, 0) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ShardBlock(1),
+                index: 1,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match get_transaction(1, 0, 1) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Transaction(1, 0),
+                index: 1,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn can_get_a_range_of_shard_blocks() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![0],
+            [0; 32],
+            0,
+        );
+        for data in 1..4 {
+            simulation
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 0,
+                    shard_block: simulation_args::ShardBlock {
+                        transactions: vec![simulation_args::ShardTransaction {
+                            data: vec![data],
+                            ..transaction.clone()
+                        }],
+                        block_hash: None,
+                        parent_hash: None,
+                    },
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+        let get_range = |shard_index, start_index, count| {
+            simulation
+                .get_shard_block_range(simulation_args::GetShardBlockRange {
+                    shard_index,
+                    start_index,
+                    count,
+                })
+                .map(|shard_blocks| {
+                    shard_blocks
+                        .into_iter()
+                        .map(|shard_block| shard_block.transactions[0].data[0])
+                        .collect::<Vec<u8>>()
+                })
+        };
+
+        assert_eq!(get_range(0, 0, 4).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(get_range(0, 1, 2).unwrap(), vec![1, 2]);
+
+        // Ranges that overshoot the tip are cut short
+        assert_eq!(get_range(0, 2, 10).unwrap(), vec![2, 3]);
+        assert_eq!(get_range(0, 10, 1).unwrap(), Vec::<u8>::new());
+
+        match get_range(64, 0, 1) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                index: 64,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn shard_blocks_are_identified_by_their_transactions() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![1],
+            [0; 32],
+            0,
+        );
+        for data in vec![vec![1], vec![2]] {
+            simulation
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 0,
+                    shard_block: simulation_args::ShardBlock {
+                        transactions: vec![simulation_args::ShardTransaction {
+                            data,
+                            ..transaction.clone()
+                        }],
+                        block_hash: None,
+                        parent_hash: None,
+                    },
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+        let block_hash = |shard_slot_index| {
+            simulation
+                .get_shard_block(simulation_args::GetShardBlock {
+                    shard_index: 0,
+                    shard_slot_index,
+                })
+                .unwrap()
+                .block_hash
+                .unwrap()
+        };
+
+        // The same transactions hash the same, even in another block or when fetched again...
+        assert_eq!(block_hash(0), block_hash(1));
+        assert_eq!(block_hash(0), block_hash(0));
+        assert_eq!(block_hash(0).len(), 64);
+
+        // ...while different transaction data changes the hash
+        assert_ne!(block_hash(1), block_hash(2));
+    }
+
+    #[test]
+    fn shard_blocks_link_to_their_parent() {
+        // The helper creates the first of three blocks on shard 0
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![1],
+            [0; 32],
+            0,
+        );
+        for data in vec![vec![2], vec![3]] {
+            simulation
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 0,
+                    shard_block: simulation_args::ShardBlock {
+                        transactions: vec![simulation_args::ShardTransaction {
+                            data,
+                            ..transaction.clone()
+                        }],
+                        block_hash: None,
+                        parent_hash: None,
+                    },
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+        let shard_blocks = simulation
+            .get_shard_block_range(simulation_args::GetShardBlockRange {
+                shard_index: 0,
+                start_index: 0,
+                count: 3,
+            })
+            .unwrap();
+        assert_eq!(shard_blocks.len(), 3);
+
+        // The genesis block has no parent...
+        assert_eq!(shard_blocks[0].parent_hash, Some("0".repeat(64)));
+
+        // ...and every later block links to the block before it
+        for pair in shard_blocks.windows(2) {
+            assert_eq!(pair[1].parent_hash, pair[0].block_hash);
+        }
+    }
+
+    #[test]
+    fn max_ee_code_bytes_limits_ee_code_size() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        assert_eq!(simulation.max_ee_code_bytes, 1024 * 1024);
+        // The smallest test module, which is 36 bytes
+        let wasm_code = include_bytes!("../tests/deep_recursion.wasm");
+        simulation.set_max_ee_code_bytes(37);
+        let create_ee_args = |padding| simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: [&wasm_code[..], &vec![0; padding][..]].concat(),
+                index: None,
+            },
+            wasm_path: None,
+            idempotency_key: None,
+        };
+
+        // Code over the limit is rejected without creating an EE...
+        match simulation.create_execution_environment(create_ee_args(2)) {
+            Err(Error::CodeTooLarge { size: 38, max: 37 }) => {}
+            other => panic!("expected the EE code to be too large, got {:?}", other),
+        }
+        assert!(simulation
+            .store
+            .current_beacon_state
+            .execution_environments
+            .is_empty());
+
+        // ...while code just under the limit is accepted
+        assert_eq!(
+            simulation
+                .create_execution_environment(create_ee_args(0))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn simulating_a_transaction_leaves_the_shard_unchanged() {
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: simulation_args::ExecutionEnvironment {
+                    initial_state,
+                    wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+                    index: None,
+                },
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let state_root = simulation.state_root();
+
+        // The dry run reports the state the transaction would write...
+        let receipt = simulation
+            .simulate_transaction(simulation_args::SimulateTransaction {
+                shard_index: 0,
+                transaction: simulation_args::ShardTransaction::from_data(ee_index, &data),
+            })
+            .unwrap();
+        assert_eq!(receipt.pre_state, initial_state);
+        assert_eq!(receipt.post_state, expected_post_state);
+        assert!(!receipt.skipped);
+
+        // ...without writing it or adding a block
+        let ee_state = simulation
+            .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index,
+                shard_index: 0,
+                state_key: None,
+            })
+            .unwrap();
+        assert_eq!(ee_state, initial_state);
+        assert!(simulation.store.shard_blocks_by_shard[&Shard::new(0)].is_empty());
+        assert_eq!(simulation.state_root(), state_root);
+
+        // Transactions a new block would reject are rejected the same way
+        simulation.set_max_transaction_data_bytes(Some(data.len() - 1));
+        match simulation.simulate_transaction(simulation_args::SimulateTransaction {
+            shard_index: 0,
+            transaction: simulation_args::ShardTransaction::from_data(ee_index, &data),
+        }) {
+            Err(Error::BlockTooLarge { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match simulation.simulate_transaction(simulation_args::SimulateTransaction {
+            shard_index: 0,
+            transaction: simulation_args::ShardTransaction::from_data(ee_index + 1, &[]),
+        }) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::TransactionExecutionEnvironment(0),
+                index: 1,
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn can_get_execution_environment_hash() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: simulation_args::ExecutionEnvironment {
+                    initial_state: [0; 32],
+                    wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                    index: None,
+                },
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+
+        // The SHA-256 digest of do_nothing.wasm
+        let code_hash = simulation
+            .get_execution_environment_hash(simulation_args::GetExecutionEnvironmentHash {
+                execution_environment_index: ee_index,
+            })
+            .unwrap();
+        assert_eq!(
+            code_hash,
+            "d687663de55a6955a77cfff1c14c32f8feb256ac94b813786498c257b16b97e1"
+        );
+
+        assert!(simulation
+            .get_execution_environment_hash(simulation_args::GetExecutionEnvironmentHash {
+                execution_environment_index: ee_index + 1,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn block_size_limits_reject_large_blocks() {
+        let (mut simulation, transaction, _, _) = test_block_with_single_transaction(
+            include_bytes!("../tests/do_nothing.wasm"),
+            [0; 32],
+            vec![1, 2],
+            [0; 32],
+            0,
+        );
+        simulation.set_max_transactions_per_block(Some(2));
+        simulation.set_max_transaction_data_bytes(Some(2));
+        let create_shard_block_args = |transactions| simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions,
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
+        };
+
+        // Too many transactions...
+        match simulation.create_shard_block(create_shard_block_args(vec![transaction.clone(); 3])) {
+            Err(Error::BlockTooLarge { .. }) => {}
+            other => panic!("expected the block to be too large, got {:?}", other),
+        }
+
+        // ...or too much data in a transaction are rejected without adding a block
+        let large_transaction = simulation_args::ShardTransaction {
+            data: vec![1, 2, 3],
+            ..transaction.clone()
+        };
+        match simulation.create_shard_block(create_shard_block_args(vec![large_transaction])) {
+            Err(Error::BlockTooLarge { .. }) => {}
+            other => panic!("expected the block to be too large, got {:?}", other),
+        }
+        assert_eq!(
+            simulation.store.shard_blocks_by_shard[&Shard::new(0)].len(),
+            1
+        );
+
+        // Blocks at the limits are accepted
+        assert_eq!(
+            simulation
+                .create_shard_block(create_shard_block_args(vec![transaction; 2]))
+                .unwrap()
+                .block_index,
+            1
+        );
+    }
+
+    #[test]
+    fn builder_configures_limits() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::builder()
+            .max_ee_states(1)
+            .max_ee_code_bytes(36)
+            .build();
+        // The smallest test module, which is 36 bytes
+        let wasm_code = include_bytes!("../tests/deep_recursion.wasm");
+        let create_ee_args = |padding| simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: [&wasm_code[..], &vec![0; padding][..]].concat(),
+                index: None,
+            },
+            wasm_path: None,
+            idempotency_key: None,
+        };
+
+        match simulation.create_execution_environment(create_ee_args(1)) {
+            Err(Error::CodeTooLarge { size: 37, max: 36 }) => {}
+            other => panic!("expected the EE code to be too large, got {:?}", other),
+        }
+        simulation
+            .create_execution_environment(create_ee_args(0))
+            .unwrap();
+        match simulation.create_execution_environment(create_ee_args(0)) {
+            Err(Error::Limit {
+                kind: LimitKind::EeState,
+                limit: 1,
+            }) => {}
+            other => panic!("expected the EE state limit to be reached, got {:?}", other),
+        }
+
+        // Options that aren't set keep their defaults
+        assert_eq!(simulation.max_state_roots, DEFAULT_MAX_STATE_ROOTS);
+        assert_eq!(simulation.memory_budget, None);
+    }
+
+    #[test]
+    fn can_list_execution_environment_states() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let list_ee_states = |simulation: &Simulation<MainnetEthSpec>, shard_index| {
+            simulation.list_execution_environment_states(
+                simulation_args::ListExecutionEnvironmentStates { shard_index },
+            )
+        };
+        assert_eq!(list_ee_states(&simulation, 3).unwrap(), Vec::<u64>::new());
+
+        for _ in 0..3 {
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee: simulation_args::ExecutionEnvironment {
+                        initial_state: [0; 32],
+                        wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                        index: None,
+                    },
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+        }
+        simulation
+            .delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+                execution_environment_index: 1,
+            })
+            .unwrap();
+
+        // The deleted EE's state is no longer listed
+        assert_eq!(list_ee_states(&simulation, 3).unwrap(), vec![0, 2]);
+
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        match list_ee_states(&simulation, max_shards) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                ..
+            }) => {}
+            other => panic!("expected the shard to be out of bounds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn shards_with_equal_states_have_equal_state_roots() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let state_root = |simulation: &Simulation<MainnetEthSpec>, shard_index| {
+            simulation
+                .get_shard_chain(simulation_args::GetShardChain { shard_index })
+                .unwrap()
+                .state_root
+        };
+
+        for initial_state in [[1; 32], [2; 32]].iter() {
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee: simulation_args::ExecutionEnvironment {
+                        initial_state: *initial_state,
+                        wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                        index: None,
+                    },
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
         }
+        let root = state_root(&simulation, 0);
+        assert_eq!(state_root(&simulation, 1), root);
+        assert_eq!(state_root(&simulation, 5), root);
+        assert_ne!(root, EMPTY_SHARD_STATE_ROOT);
+
+        // The root covers every EE state, so a new EE changes it
+        simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: simulation_args::ExecutionEnvironment {
+                    initial_state: [3; 32],
+                    wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                    index: None,
+                },
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        assert_ne!(state_root(&simulation, 0), root);
+        assert_eq!(state_root(&simulation, 0), state_root(&simulation, 1));
     }
 
     #[test]
-    fn can_create_and_get_ee() {
-        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+    fn can_start_from_genesis() {
+        let (initial_state, _, _) = bazaar_test_vector();
+        let genesis = simulation_args::Genesis {
+            execution_environments: vec![
+                simulation_args::ExecutionEnvironment {
+                    initial_state,
+                    wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+                    index: None,
+                },
+                simulation_args::ExecutionEnvironment::from_wasm_bytes(include_bytes!(
+                    "../tests/do_nothing.wasm"
+                )),
+            ],
+        };
+        let simulation: Simulation<MainnetEthSpec> =
+            Simulation::from_genesis(genesis.clone()).unwrap();
 
-        // Set up args::CreateExecutionEnvironment
-        let mut initial_state: [u8; 32] = [0; 32];
-        initial_state[5] = 1;
-        let example_wasm_code: &[u8] = include_bytes!("../tests/do_nothing.wasm");
-        let example_wasm_code2: &[u8] = include_bytes!("../tests/phase2_bazaar.wasm");
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
+        assert_eq!(
+            simulation.simulation_state(simulation_args::GetSimulationState {}),
+            simulation_args::SimulationState {
+                num_execution_environments: 2,
+                num_shard_chains: max_shards as u64,
+                shard_block_counts: vec![0; max_shards],
+                slot: 0,
+                finalized_block_counts: vec![0; max_shards],
+            }
+        );
+        for shard_index in 0..3 {
+            let ee_state = simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index: 0,
+                    shard_index,
+                    state_key: None,
+                })
+                .unwrap();
+            assert_eq!(ee_state, initial_state);
+        }
+        // The genesis EEs are the state as of operation 0
+        assert_eq!(
+            simulation
+                .get_state_root_at(simulation_args::GetStateRootAt { seq: 0 })
+                .state_root,
+            simulation.state_root()
+        );
 
-        let interface_ee = simulation_args::ExecutionEnvironment {
+        // A single invalid EE fails the whole genesis
+        let mut invalid_genesis = genesis;
+        invalid_genesis.execution_environments.push(
+            simulation_args::ExecutionEnvironment::from_wasm_bytes(&[1, 2, 3]),
+        );
+        match Simulation::<MainnetEthSpec>::from_genesis(invalid_genesis) {
+            Err(Error::InvalidWasm { .. }) => {}
+            other => panic!("expected InvalidWasm, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn transactions_can_target_state_keys() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let ee = simulation_args::ExecutionEnvironment {
             initial_state,
-            wasm_code: example_wasm_code.to_vec(),
+            wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let shard_state_root = |simulation: &Simulation<MainnetEthSpec>| {
+            simulation
+                .get_shard_chain(simulation_args::GetShardChain { shard_index: 0 })
+                .unwrap()
+                .state_root
         };
-        let create_ee_args = simulation_args::CreateExecutionEnvironment { ee: interface_ee };
+        let shard_state_root_before = shard_state_root(&simulation);
 
-        let interface_ee2 = simulation_args::ExecutionEnvironment {
-            initial_state: initial_state.clone(),
-            wasm_code: example_wasm_code2.to_vec(),
+        // Write two keys of the same EE, in the opposite order to how they're kept
+        let transaction = |state_key| simulation_args::ShardTransaction {
+            data: data.clone(),
+            ee_index,
+            condition: None,
+            state_key: Some(state_key),
+        };
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![transaction(2), transaction(1)],
+            block_hash: None,
+            parent_hash: None,
         };
-        let create_ee_args2 = simulation_args::CreateExecutionEnvironment { ee: interface_ee2 };
+        simulation
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block,
+                idempotency_key: None,
+            })
+            .unwrap();
 
-        // Calling create_execution_environment repeatedly should return an increasing EE index
+        let get_ee_state = |state_key| {
+            simulation
+                .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                    ee_index,
+                    shard_index: 0,
+                    state_key,
+                })
+                .unwrap()
+        };
+        // Both keys ran against the initial state, leaving the main state untouched
+        assert_eq!(get_ee_state(Some(1)), expected_post_state);
+        assert_eq!(get_ee_state(Some(2)), expected_post_state);
+        assert_eq!(get_ee_state(None), initial_state);
+        // Keys that were never written hold the initial state
+        assert_eq!(get_ee_state(Some(3)), initial_state);
+
+        let keyed_states = &simulation.store.current_beacon_state.shard_states[0]
+            .keyed_execution_environment_states;
+        let keys: Vec<u32> = keyed_states.iter().map(|s| s.state_key).collect();
+        assert_eq!(keys, vec![1, 2]);
+        assert_ne!(shard_state_root(&simulation), shard_state_root_before);
+
+        // Replaying a keyed transaction starts from the state of its own key
+        let receipt = simulation
+            .replay_transaction(simulation_args::ReplayTransaction {
+                shard_index: 0,
+                shard_slot_index: 0,
+                transaction_index: 1,
+            })
+            .unwrap();
+        assert_eq!(receipt.pre_state, initial_state);
+        assert_eq!(receipt.post_state, expected_post_state);
+    }
+
+    #[test]
+    fn retried_creates_with_an_idempotency_key_only_apply_once() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment {
+                initial_state,
+                wasm_code: include_bytes!("../tests/phase2_bazaar.wasm").to_vec(),
+                index: None,
+            },
+            wasm_path: None,
+            idempotency_key: Some("create ee".to_string()),
+        };
         let ee_index = simulation
+            .create_execution_environment(create_ee_args.clone())
+            .unwrap();
+        let retried_ee_index = simulation
             .create_execution_environment(create_ee_args)
             .unwrap();
-        assert_eq!(ee_index, 0);
-        let ee_index2 = simulation
-            .create_execution_environment(create_ee_args2)
+        assert_eq!(retried_ee_index, ee_index);
+
+        let create_shard_block_args = simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction::from_data(
+                    ee_index, &data,
+                )],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: Some("create block".to_string()),
+        };
+        let shard_block_result = simulation
+            .create_shard_block(create_shard_block_args.clone())
             .unwrap();
-        assert_eq!(ee_index2, 1);
+        let retried_shard_block_result = simulation
+            .create_shard_block(create_shard_block_args.clone())
+            .unwrap();
+        assert_eq!(retried_shard_block_result, shard_block_result);
+        assert_eq!(
+            shard_block_result.transaction_results[0].post_state,
+            expected_post_state
+        );
 
-        // Set up simulation_args::GetExecutionEnvironment
-        let get_ee_args = simulation_args::GetExecutionEnvironment { ee_index };
-        let get_ee_args2 = simulation_args::GetExecutionEnvironment {
-            ee_index: ee_index2,
+        // Only one EE and one block were created
+        let simulation_state = simulation.simulation_state(simulation_args::GetSimulationState {});
+        assert_eq!(simulation_state.num_execution_environments, 1);
+        assert_eq!(simulation_state.shard_block_counts[0], 1);
+
+        // Without a key, or with a new one, the block is created again
+        let mut create_shard_block_args = create_shard_block_args;
+        create_shard_block_args.idempotency_key = None;
+        let shard_block_result = simulation
+            .create_shard_block(create_shard_block_args.clone())
+            .unwrap();
+        assert_eq!(shard_block_result.block_index, 1);
+        create_shard_block_args.idempotency_key = Some("create another block".to_string());
+        let shard_block_result = simulation
+            .create_shard_block(create_shard_block_args.clone())
+            .unwrap();
+        assert_eq!(shard_block_result.block_index, 2);
+
+        // A key can't be reused for a different kind of operation
+        create_shard_block_args.idempotency_key = Some("create ee".to_string());
+        match simulation.create_shard_block(create_shard_block_args) {
+            Err(Error::InvalidArgs { .. }) => {}
+            other => panic!("expected invalid args, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_iterate_over_ees_and_shard_blocks() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
         };
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee,
+            wasm_path: None,
+            idempotency_key: None,
+        };
+        for _ in 0..3 {
+            simulation
+                .create_execution_environment(create_ee_args.clone())
+                .unwrap();
+        }
+        simulation
+            .delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+                execution_environment_index: 1,
+            })
+            .unwrap();
 
-        // Make sure the retrieved EEs have the same wasm code originally passed in
-        let ee = simulation.get_execution_environment(get_ee_args).unwrap();
-        let ee2 = simulation.get_execution_environment(get_ee_args2).unwrap();
-        assert_eq!(ee.wasm_code, example_wasm_code.to_vec());
-        assert_eq!(ee2.wasm_code, example_wasm_code2.to_vec());
+        // Deleted EEs are skipped
+        let ee_indices: Vec<u64> = simulation
+            .iter_execution_environments()
+            .map(|(ee_index, ee)| {
+                assert_eq!(ee.index, Some(ee_index));
+                ee_index
+            })
+            .collect();
+        assert_eq!(ee_indices, vec![0, 2]);
 
-        // Make sure the EEs have the correct initial_state specified for every shard
-        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_usize();
-        for i in 0..max_shards as u64 {
-            let get_ee_state_args = simulation_args::GetExecutionEnvironmentState {
-                ee_index,
-                shard_index: i,
+        for num_transactions in 1..=3 {
+            let shard_block = simulation_args::ShardBlock {
+                transactions: vec![
+                    simulation_args::ShardTransaction::from_data(0, &[]);
+                    num_transactions
+                ],
+                block_hash: None,
+                parent_hash: None,
             };
-            let ee_state = simulation
-                .get_execution_environment_state(get_ee_state_args)
+            simulation
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 0,
+                    shard_block,
+                    idempotency_key: None,
+                })
                 .unwrap();
-            assert_eq!(ee_state, initial_state);
+        }
+
+        let num_transactions: usize = simulation
+            .iter_shard_blocks(0)
+            .unwrap()
+            .map(|(_, shard_block)| shard_block.transactions.len())
+            .sum();
+        assert_eq!(num_transactions, 6);
+        let shard_slot_indices: Vec<u64> = simulation
+            .iter_shard_blocks(0)
+            .unwrap()
+            .map(|(shard_slot_index, _)| shard_slot_index)
+            .collect();
+        assert_eq!(shard_slot_indices, vec![0, 1, 2]);
+        assert_eq!(simulation.iter_shard_blocks(1).unwrap().count(), 0);
+
+        let max_shards = <MainnetEthSpec as EthSpec>::MaxShards::to_u64();
+        match simulation.iter_shard_blocks(max_shards) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::Shard,
+                ..
+            }) => {}
+            Err(e) => panic!("expected an out of bounds shard, got {:?}", e),
+            Ok(_) => panic!("expected an out of bounds shard"),
         }
     }
 
-    fn test_block_with_single_transaction(
-        wasm_code: &[u8],
-        initial_state: [u8; 32],
-        data: Vec<u8>,
-        expected_post_state: [u8; 32],
-        shard_index: u64,
-    ) -> (
-        Simulation<MainnetEthSpec>,
-        simulation_args::ShardTransaction,
-        ShardSlot,
-        EeIndex,
-    ) {
+    #[test]
+    fn snapshots_are_unaffected_by_later_changes() {
         let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
-
-        // Create EE with the specified code and initial state
         let ee = simulation_args::ExecutionEnvironment {
-            initial_state,
-            wasm_code: wasm_code.to_vec(),
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
         };
-        let create_ee_args = simulation_args::CreateExecutionEnvironment { ee };
         let ee_index = simulation
-            .create_execution_environment(create_ee_args)
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
             .unwrap();
-        assert_eq!(ee_index, 0);
-
-        // Set up a shard transaction with the specified data
-        let shard_transaction = simulation_args::ShardTransaction { data, ee_index };
-        let shard_transaction_copy = shard_transaction.clone();
-
-        // Create a shard block with the one transaction in it
-        let shard_block = simulation_args::ShardBlock {
-            transactions: vec![shard_transaction],
-        };
         let create_shard_block_args = simulation_args::CreateShardBlock {
-            shard_index,
-            shard_block,
+            shard_index: 0,
+            shard_block: simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction::from_data(ee_index, &[])],
+                block_hash: None,
+                parent_hash: None,
+            },
+            idempotency_key: None,
         };
-        // This creates the block and runs all the transactions inside it
-        let shard_slot_index = simulation
-            .create_shard_block(create_shard_block_args)
+        simulation
+            .create_shard_block(create_shard_block_args.clone())
             .unwrap();
 
-        // Get back the EE state to make sure it matches the expected_post_state
-        let get_ee_state_args = simulation_args::GetExecutionEnvironmentState {
-            ee_index,
-            shard_index,
-        };
-        let ee_post_state = simulation
-            .get_execution_environment_state(get_ee_state_args)
-            .unwrap();
+        let snapshot = simulation.snapshot();
+        let snapshot_state = snapshot.simulation_state(simulation_args::GetSimulationState {});
         assert_eq!(
-            ee_post_state, expected_post_state,
-            "actual post state root should match expected post state root"
+            snapshot_state,
+            simulation.simulation_state(simulation_args::GetSimulationState {})
         );
 
-        (
-            simulation,
-            shard_transaction_copy,
-            ShardSlot::new(shard_slot_index),
-            EeIndex::new(ee_index),
-        )
+        // Change the live simulation
+        simulation
+            .create_shard_block(create_shard_block_args)
+            .unwrap();
+        simulation
+            .delete_execution_environment(simulation_args::DeleteExecutionEnvironment {
+                execution_environment_index: ee_index,
+            })
+            .unwrap();
+
+        // The snapshot still sees the state as of when it was taken
+        assert_eq!(
+            snapshot.simulation_state(simulation_args::GetSimulationState {}),
+            snapshot_state
+        );
+        assert_eq!(snapshot_state.shard_block_counts[0], 1);
+        let ee = snapshot
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index })
+            .unwrap();
+        assert_eq!(ee.index, Some(ee_index));
+        snapshot
+            .get_shard_block(simulation_args::GetShardBlock {
+                shard_index: 0,
+                shard_slot_index: 0,
+            })
+            .unwrap();
+        match snapshot.get_shard_block(simulation_args::GetShardBlock {
+            shard_index: 0,
+            shard_slot_index: 1,
+        }) {
+            Err(Error::OutOfBounds {
+                what: WhatBound::ShardBlock(0),
+                index: 1,
+            }) => {}
+            other => panic!("expected an out of bounds shard block, got {:?}", other),
+        }
     }
 
     #[test]
-    fn run_scout_helloworld_and_get_shard_block_and_state() {
-        let initial_state = [0; 32];
-        let expected_post_state = [0; 32];
-        let data: Vec<u8> = Vec::new();
-        let shard_index = 0;
-        let (simulation, shard_transaction, shard_slot, ee_index) =
-            test_block_with_single_transaction(
-                include_bytes!("../tests/phase2_helloworld.wasm"),
-                initial_state,
-                data,
-                expected_post_state,
-                shard_index,
-            );
+    fn ee_code_must_export_the_entrypoint() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let create_ee_args = |wasm_code: &[u8]| simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment::from_wasm_bytes(wasm_code),
+            wasm_path: None,
+            idempotency_key: None,
+        };
 
-        let ee_index: u64 = ee_index.into();
+        // Valid modules without a `main` function taking and returning nothing
+        let empty_module: &[u8] = &[0, 0x61, 0x73, 0x6d, 1, 0, 0, 0];
+        let main_with_param: &[u8] = &[
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            1, 5, 1, 0x60, 1, 0x7f, 0, // type section: (i32) -> ()
+            3, 2, 1, 0, // function section
+            7, 8, 1, 4, b'm', b'a', b'i', b'n', 0, 0, // export section: "main"
+            10, 4, 1, 2, 0, 0x0b, // code section
+        ];
+        for wasm_code in vec![empty_module, main_with_param] {
+            match simulation.create_execution_environment(create_ee_args(wasm_code)) {
+                Err(Error::MissingEntrypoint { expected }) => assert_eq!(expected, "main"),
+                other => panic!("expected a missing entrypoint, got {:?}", other),
+            }
+        }
+        assert!(simulation
+            .store
+            .current_beacon_state
+            .execution_environments
+            .is_empty());
 
-        // Test that GetShardBlock is working as expected
-        let shard_slot_index: u64 = shard_slot.into();
-        let get_shard_block_args = simulation_args::GetShardBlock {
-            shard_index,
-            shard_slot_index,
+        // Code that isn't WASM at all is still reported as such
+        match simulation.create_execution_environment(create_ee_args(b"not a wasm module")) {
+            Err(Error::InvalidWasm { .. }) => {}
+            other => panic!("expected InvalidWasm, got {:?}", other),
+        }
+
+        // Updating an EE checks the new code too
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args(include_bytes!(
+                "../tests/do_nothing.wasm"
+            )))
+            .unwrap();
+        let update_args = simulation_args::UpdateExecutionEnvironment {
+            execution_environment_index: ee_index,
+            execution_environment: simulation_args::ExecutionEnvironment::from_wasm_bytes(
+                empty_module,
+            ),
         };
-        let shard_block = simulation.get_shard_block(get_shard_block_args).unwrap();
+        match simulation.update_execution_environment(update_args) {
+            Err(Error::MissingEntrypoint { .. }) => {}
+            other => panic!("expected a missing entrypoint, got {:?}", other),
+        }
+    }
 
-        // Make sure the transaction on the retrieved block matches the transaction
-        // on the created block
+    #[test]
+    fn keyed_states_count_towards_the_ee_state_limit() {
+        let mut simulation: Simulation<MainnetEthSpec> =
+            Simulation::builder().max_ee_states(2).build();
+        let create_ee_args = simulation_args::CreateExecutionEnvironment {
+            ee: simulation_args::ExecutionEnvironment::from_wasm_bytes(include_bytes!(
+                "../tests/do_nothing.wasm"
+            )),
+            wasm_path: None,
+            idempotency_key: None,
+        };
+        let ee_index = simulation
+            .create_execution_environment(create_ee_args.clone())
+            .unwrap();
+        let create_shard_block = |simulation: &mut Simulation<MainnetEthSpec>, state_key| {
+            let mut transaction = simulation_args::ShardTransaction::from_data(ee_index, &[]);
+            transaction.state_key = Some(state_key);
+            simulation.create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block: simulation_args::ShardBlock {
+                    transactions: vec![transaction],
+                    block_hash: None,
+                    parent_hash: None,
+                },
+                idempotency_key: None,
+            })
+        };
+
+        // The main state and one keyed state fill the shard...
+        create_shard_block(&mut simulation, 1).unwrap();
+        match create_shard_block(&mut simulation, 2) {
+            Err(Error::Limit {
+                kind: LimitKind::EeState,
+                limit: 2,
+            }) => {}
+            other => panic!("expected the EE state limit to be reached, got {:?}", other),
+        }
+        // ...but existing keyed states can still be written
+        create_shard_block(&mut simulation, 1).unwrap();
+
+        // A new EE would add a third state to that shard
+        match simulation.create_execution_environment(create_ee_args) {
+            Err(Error::Limit {
+                kind: LimitKind::EeState,
+                limit: 2,
+            }) => {}
+            other => panic!("expected the EE state limit to be reached, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_get_historical_state_roots() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_max_state_roots(3);
+        let state_root_at = |simulation: &Simulation<MainnetEthSpec>, seq| {
+            simulation.get_state_root_at(simulation_args::GetStateRootAt { seq })
+        };
+        let genesis_state_root = simulation.state_root();
         assert_eq!(
-            shard_block.transactions.get(0).unwrap().clone(),
-            shard_transaction
+            state_root_at(&simulation, 0),
+            simulation_args::StateRootAt {
+                seq: 0,
+                state_root: genesis_state_root,
+            }
         );
 
-        // Test that GetShardState is working as expected
-        let get_shard_state_args = simulation_args::GetShardState { shard_index };
-        let shard_state = simulation.get_shard_state(get_shard_state_args).unwrap();
-        let ee_index = ee_index as usize;
-        let ee_state = shard_state
-            .execution_environment_states
-            .get(ee_index)
-            .unwrap();
-        assert_eq!(ee_state, &expected_post_state);
+        // Every operation records a new state root
+        let mut state_roots = vec![genesis_state_root];
+        for _ in 0..3 {
+            let ee = simulation_args::ExecutionEnvironment {
+                initial_state: [0; 32],
+                wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+                index: None,
+            };
+            simulation
+                .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                    ee,
+                    wasm_path: None,
+                    idempotency_key: None,
+                })
+                .unwrap();
+            state_roots.push(simulation.state_root());
+        }
+        assert_ne!(state_roots[1], state_roots[0]);
+        assert_ne!(state_roots[3], state_roots[2]);
+
+        for seq in 1..=3 {
+            assert_eq!(
+                state_root_at(&simulation, seq).state_root,
+                state_roots[seq as usize]
+            );
+        }
+
+        // Only 3 roots are kept, so the genesis root was dropped and the nearest one is returned
+        assert_eq!(state_root_at(&simulation, 0).seq, 1);
+        // Sequence numbers past the current one get the current root
+        assert_eq!(
+            state_root_at(&simulation, 10),
+            simulation_args::StateRootAt {
+                seq: 3,
+                state_root: state_roots[3],
+            }
+        );
     }
+
     #[test]
-    fn run_scout_bazaar_test() {
-        use simulation_args::ToBytes32;
+    fn can_verify_state_roots() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let genesis_state_root = simulation.state_root();
+        assert!(simulation.verify_state_root(genesis_state_root));
 
-        let initial_state = "22ea9b045f8792170b45ec629c98e1b92bc6a19cd8d0e9f37baaadf2564142f4";
-        let initial_state = Vec::from_hex(initial_state).unwrap().to_bytes32().unwrap();
-        let expected_post_state =
-            "29505fd952857b5766c759bcb4af58eb8df5a91043540c1398dd987a503127fc";
-        let expected_post_state = Vec::from_hex(expected_post_state)
-            .unwrap()
-            .to_bytes32()
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
             .unwrap();
-        let data: Vec<u8> = Vec::from_hex("5c0000005000000001000000000000000000000000000000000000000000000000000000000000000000000000000000020000000000000001010101010101010101010101010101010101010101010101010101010101010400000000000000").unwrap();
-        let shard_index = 0;
-        let (_simulation, _shard_transaction, _shard_slot, _ee_index) =
-            test_block_with_single_transaction(
-                include_bytes!("../tests/phase2_bazaar.wasm"),
-                initial_state,
-                data,
-                expected_post_state,
-                shard_index,
-            );
+        let state_root = simulation.state_root();
+        assert!(simulation.verify_state_root(state_root));
+
+        // A stale root no longer matches
+        assert!(!simulation.verify_state_root(genesis_state_root));
+
+        // Nor does a root with a single bit flipped
+        let mut tampered = state_root;
+        tampered[31] ^= 1;
+        assert!(!simulation.verify_state_root(tampered));
+
+        // An identical simulation built independently produces the same root
+        let mut peer: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        peer.create_execution_environment(simulation_args::CreateExecutionEnvironment {
+            ee,
+            wasm_path: None,
+            idempotency_key: None,
+        })
+        .unwrap();
+        assert!(simulation.verify_state_root(peer.state_root()));
     }
 }
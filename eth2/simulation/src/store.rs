@@ -1,16 +1,20 @@
-use std::collections::HashMap;
+use simulation_args::{EeAuditAction, EeAuditEntry};
+use ssz::{Decode, Encode};
+use ssz_derive::{Decode as DeriveDecode, Encode as DeriveEncode};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 use typenum::Unsigned;
 use types::beacon_state::BeaconState;
 use types::eth_spec::EthSpec;
 use types::shard_block::ShardBlock;
-use types::slot_epoch_root::Shard;
+use types::slot_epoch_root::{EeIndex, Shard};
 
 /// Contains arbitrary state stored by the simulation
 /// This struct need not adhere to any official Eth2 spec -- it will store internal
 /// simulation state in whatever manner is most convenient.
 /// However, some types that ARE listed in a spec (eg. BeaconState) will still be spec-compliant
 /// even if they happen to be stored in Store.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Store<T>
 where
     T: EthSpec,
@@ -21,19 +25,284 @@ where
     // HashMap<Shard, Vec<ShardBlock>> is used instead of Vec<Vec<ShardBlock>> because the former
     // is easier to read and immediately understand what is being stored.
     pub shard_blocks_by_shard: HashMap<Shard, Vec<ShardBlock<T>>>,
+
+    // A mapping from shard to the number of its oldest shard blocks that have been evicted to stay
+    // within the memory budget. Slot indices still count evicted blocks, so the block at slot
+    // index i is stored at position i - (number evicted) in `shard_blocks_by_shard`.
+    pub num_evicted_blocks_by_shard: HashMap<Shard, usize>,
+
+    // The total size of all shard blocks in `shard_blocks_by_shard`, in bytes of SSZ encoding
+    pub block_bytes: usize,
+
+    // A mapping from shard to the number of its oldest shard blocks that have been finalized
+    // Advancing the beacon chain slot finalizes every block added to a shard before it.
+    pub num_finalized_blocks_by_shard: HashMap<Shard, usize>,
+
+    // A mapping from shard to the hash of the tip of its chain of shard blocks
+    // Each time a block is added, the tip hash becomes hash(previous tip hash ++ block hash), so
+    // it commits to every block on the shard without having to walk them all on each query.
+    pub shard_tip_hashes: HashMap<Shard, [u8; 32]>,
+
+    // A mapping from shard to the block hash of its newest shard block (all zero before the first
+    // block), kept when that block is evicted so the next block can still link to it
+    pub latest_block_hashes: HashMap<Shard, [u8; 32]>,
+
+    // A mapping from shard to the indices of the transactions skipped in each of its shard blocks
+    // because their condition didn't hold. Parallel to `shard_blocks_by_shard`.
+    pub skipped_transactions_by_shard: HashMap<Shard, Vec<Vec<u64>>>,
+
+    // EEs that new transactions may not reference
+    pub frozen_ees: HashSet<EeIndex>,
+
+    // EEs whose code has been replaced, so earlier transactions can't be replayed with their
+    // current code
+    pub updated_ees: HashSet<EeIndex>,
+
+    // The main EE states that have been overwritten by copying another shard's state, so they
+    // can't be rebuilt by replaying the transactions of their shard
+    pub copied_ee_states: HashSet<(Shard, EeIndex)>,
+
+    // The most recent whole-simulation state roots, each with the sequence number of the
+    // operation that produced it, oldest first
+    pub state_roots: VecDeque<(u64, [u8; 32])>,
+
+    // An append-only, chronological log of every change made to the set of EEs
+    pub ee_audit_log: Vec<EeAuditEntry>,
 }
 
 impl<T: EthSpec> Store<T> {
     pub fn new() -> Self {
         // Initialize shard blocks storage for all shards
         let mut shard_blocks_by_shard = HashMap::new();
+        let mut num_evicted_blocks_by_shard = HashMap::new();
+        let mut num_finalized_blocks_by_shard = HashMap::new();
+        let mut shard_tip_hashes = HashMap::new();
+        let mut latest_block_hashes = HashMap::new();
+        let mut skipped_transactions_by_shard = HashMap::new();
         for shard in 0..T::MaxShards::to_u64() {
             let shard = Shard::new(shard);
             shard_blocks_by_shard.insert(shard, Vec::new());
+            num_evicted_blocks_by_shard.insert(shard, 0);
+            num_finalized_blocks_by_shard.insert(shard, 0);
+            shard_tip_hashes.insert(shard, [0; 32]);
+            latest_block_hashes.insert(shard, [0; 32]);
+            skipped_transactions_by_shard.insert(shard, Vec::new());
         }
         Self {
             current_beacon_state: BeaconState::new(),
             shard_blocks_by_shard,
+            num_evicted_blocks_by_shard,
+            block_bytes: 0,
+            num_finalized_blocks_by_shard,
+            shard_tip_hashes,
+            latest_block_hashes,
+            skipped_transactions_by_shard,
+            frozen_ees: HashSet::new(),
+            updated_ees: HashSet::new(),
+            copied_ee_states: HashSet::new(),
+            state_roots: VecDeque::new(),
+            ee_audit_log: Vec::new(),
+        }
+    }
+
+    /// Encode the simulated chains as SSZ, to be restored with `from_bytes`
+    /// Covers the beacon state, the frozen and updated EEs, the EE audit log, and each shard's
+    /// blocks, tip hash, latest block hash, skipped transactions, copied EE states, and counts of
+    /// evicted and finalized blocks. The history of state roots isn't included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let shards = || (0..T::MaxShards::to_u64()).map(Shard::new);
+        let mut frozen_ees: Vec<EeIndex> = self.frozen_ees.iter().cloned().collect();
+        frozen_ees.sort();
+        let mut updated_ees: Vec<EeIndex> = self.updated_ees.iter().cloned().collect();
+        updated_ees.sort();
+        let snapshot = StoreSnapshot {
+            beacon_state: self.current_beacon_state.clone(),
+            shard_blocks: shards()
+                .map(|shard| self.shard_blocks_by_shard[&shard].clone())
+                .collect(),
+            num_evicted_blocks: shards()
+                .map(|shard| self.num_evicted_blocks_by_shard[&shard] as u64)
+                .collect(),
+            num_finalized_blocks: shards()
+                .map(|shard| self.num_finalized_blocks_by_shard[&shard] as u64)
+                .collect(),
+            shard_tip_hashes: shards()
+                .map(|shard| self.shard_tip_hashes[&shard])
+                .collect(),
+            latest_block_hashes: shards()
+                .map(|shard| self.latest_block_hashes[&shard])
+                .collect(),
+            skipped_transactions: shards()
+                .map(|shard| self.skipped_transactions_by_shard[&shard].clone())
+                .collect(),
+            copied_ee_states: shards()
+                .map(|shard| {
+                    let mut ee_indices: Vec<EeIndex> = self
+                        .copied_ee_states
+                        .iter()
+                        .filter(|(copied_shard, _)| *copied_shard == shard)
+                        .map(|(_, ee_index)| *ee_index)
+                        .collect();
+                    ee_indices.sort();
+                    ee_indices
+                })
+                .collect(),
+            frozen_ees,
+            updated_ees,
+            ee_audit_log: self
+                .ee_audit_log
+                .iter()
+                .map(AuditEntrySnapshot::from)
+                .collect(),
+        };
+        snapshot.as_ssz_bytes()
+    }
+
+    /// Decode a store encoded by `to_bytes`, describing what is wrong if the bytes are corrupt
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let snapshot = StoreSnapshot::<T>::from_ssz_bytes(bytes).map_err(|e| format!("{:?}", e))?;
+
+        // Every shard must be present, with its data consistent with the beacon state
+        let num_shards = T::MaxShards::to_usize();
+        let shard_lengths = [
+            snapshot.beacon_state.shard_states.len(),
+            snapshot.shard_blocks.len(),
+            snapshot.num_evicted_blocks.len(),
+            snapshot.num_finalized_blocks.len(),
+            snapshot.shard_tip_hashes.len(),
+            snapshot.latest_block_hashes.len(),
+            snapshot.skipped_transactions.len(),
+            snapshot.copied_ee_states.len(),
+        ];
+        if shard_lengths.iter().any(|len| *len != num_shards) {
+            return Err(format!("expected data for {} shards", num_shards));
+        }
+        let num_ees = snapshot.beacon_state.execution_environments.len();
+        for shard_index in 0..num_shards {
+            if snapshot.beacon_state.shard_states[shard_index]
+                .execution_environment_states
+                .len()
+                != num_ees
+            {
+                return Err(format!(
+                    "shard {} doesn't have one state per execution environment",
+                    shard_index
+                ));
+            }
+            if snapshot.skipped_transactions[shard_index].len()
+                != snapshot.shard_blocks[shard_index].len()
+            {
+                return Err(format!(
+                    "shard {} doesn't have skipped transactions for every block",
+                    shard_index
+                ));
+            }
         }
+
+        let mut store = Self::new();
+        for (shard_index, shard_blocks) in snapshot.shard_blocks.into_iter().enumerate() {
+            let shard = Shard::new(shard_index as u64);
+            store.block_bytes += shard_blocks
+                .iter()
+                .map(|shard_block| shard_block.as_ssz_bytes().len())
+                .sum::<usize>();
+            store.shard_blocks_by_shard.insert(shard, shard_blocks);
+            store
+                .num_evicted_blocks_by_shard
+                .insert(shard, snapshot.num_evicted_blocks[shard_index] as usize);
+            store
+                .num_finalized_blocks_by_shard
+                .insert(shard, snapshot.num_finalized_blocks[shard_index] as usize);
+            store
+                .shard_tip_hashes
+                .insert(shard, snapshot.shard_tip_hashes[shard_index]);
+            store
+                .latest_block_hashes
+                .insert(shard, snapshot.latest_block_hashes[shard_index]);
+            store
+                .skipped_transactions_by_shard
+                .insert(shard, snapshot.skipped_transactions[shard_index].clone());
+            store.copied_ee_states.extend(
+                snapshot.copied_ee_states[shard_index]
+                    .iter()
+                    .map(|ee_index| (shard, *ee_index)),
+            );
+        }
+        store.current_beacon_state = snapshot.beacon_state;
+        store.frozen_ees = snapshot.frozen_ees.into_iter().collect();
+        store.updated_ees = snapshot.updated_ees.into_iter().collect();
+        store.ee_audit_log = snapshot
+            .ee_audit_log
+            .into_iter()
+            .map(EeAuditEntry::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok(store)
+    }
+}
+
+/// The parts of a `Store` encoded by `Store::to_bytes`, with per-shard data listed by shard index
+#[derive(DeriveDecode, DeriveEncode)]
+struct StoreSnapshot<T>
+where
+    T: EthSpec,
+{
+    beacon_state: BeaconState<T>,
+    shard_blocks: Vec<Vec<ShardBlock<T>>>,
+    num_evicted_blocks: Vec<u64>,
+    num_finalized_blocks: Vec<u64>,
+    shard_tip_hashes: Vec<[u8; 32]>,
+    latest_block_hashes: Vec<[u8; 32]>,
+    skipped_transactions: Vec<Vec<Vec<u64>>>,
+    copied_ee_states: Vec<Vec<EeIndex>>,
+    frozen_ees: Vec<EeIndex>,
+    updated_ees: Vec<EeIndex>,
+    ee_audit_log: Vec<AuditEntrySnapshot>,
+}
+
+/// An `EeAuditEntry` as encoded by `Store::to_bytes`, with its action as a number
+#[derive(DeriveDecode, DeriveEncode)]
+struct AuditEntrySnapshot {
+    sequence: u64,
+    ee_index: u64,
+    action: u8,
+    code_hash: [u8; 32],
+}
+
+impl From<&EeAuditEntry> for AuditEntrySnapshot {
+    fn from(entry: &EeAuditEntry) -> Self {
+        let action = match entry.action {
+            EeAuditAction::Create => 0,
+            EeAuditAction::Freeze => 1,
+            EeAuditAction::Unfreeze => 2,
+            EeAuditAction::Delete => 3,
+            EeAuditAction::Update => 4,
+        };
+        AuditEntrySnapshot {
+            sequence: entry.sequence,
+            ee_index: entry.ee_index,
+            action,
+            code_hash: entry.code_hash,
+        }
+    }
+}
+
+impl TryFrom<AuditEntrySnapshot> for EeAuditEntry {
+    type Error = String;
+
+    fn try_from(entry: AuditEntrySnapshot) -> Result<Self, String> {
+        let action = match entry.action {
+            0 => EeAuditAction::Create,
+            1 => EeAuditAction::Freeze,
+            2 => EeAuditAction::Unfreeze,
+            3 => EeAuditAction::Delete,
+            4 => EeAuditAction::Update,
+            action => return Err(format!("unknown EE audit action {}", action)),
+        };
+        Ok(EeAuditEntry {
+            sequence: entry.sequence,
+            ee_index: entry.ee_index,
+            action,
+            code_hash: entry.code_hash,
+        })
     }
 }
@@ -0,0 +1,59 @@
+//! Drives a `Simulation` directly, without the async dispatch machinery of the server, to make
+//! sure the core simulation stays usable from plain synchronous code.
+
+use simulation::Simulation;
+use types::eth_spec::MainnetEthSpec;
+
+#[test]
+fn can_run_a_block_without_an_async_runtime() {
+    let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+
+    let ee = simulation_args::ExecutionEnvironment {
+        initial_state: [7; 32],
+        wasm_code: include_bytes!("do_nothing.wasm").to_vec(),
+        index: None,
+    };
+    let ee_index = simulation
+        .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+            ee,
+            wasm_path: None,
+            idempotency_key: None,
+        })
+        .unwrap();
+
+    let shard_block = simulation_args::ShardBlock {
+        transactions: vec![simulation_args::ShardTransaction {
+            data: vec![],
+            ee_index,
+            condition: None,
+            state_key: None,
+        }],
+        block_hash: None,
+        parent_hash: None,
+    };
+    let shard_slot_index = simulation
+        .create_shard_block(simulation_args::CreateShardBlock {
+            shard_index: 0,
+            shard_block,
+            idempotency_key: None,
+        })
+        .unwrap()
+        .block_index;
+    assert_eq!(shard_slot_index, 0);
+
+    // The EE leaves its state unchanged
+    let ee_state = simulation
+        .get_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+            ee_index,
+            shard_index: 0,
+            state_key: None,
+        })
+        .unwrap();
+    assert_eq!(ee_state, [7; 32]);
+
+    // Errors are plain values too
+    let error = simulation
+        .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index: 1 })
+        .unwrap_err();
+    assert_eq!(error.code(), 6);
+}
@@ -5,14 +5,15 @@ use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::path::PathBuf;
 
 mod internal_types {
     pub use ssz_types::{Error, VariableList};
     pub use types::eth_spec::EthSpec;
     pub use types::execution_environment::ExecutionEnvironment;
     pub use types::shard_block::ShardBlock;
-    pub use types::shard_state::ShardState;
-    pub use types::shard_transaction::ShardTransaction;
+    pub use types::shard_state::{KeyedEeState, ShardState};
+    pub use types::shard_transaction::{Condition, ShardTransaction};
     pub use types::slot_epoch_root::Root;
 }
 
@@ -41,11 +42,22 @@ impl fmt::Display for WhatBound {
 /// Errors arising from the simulation.
 #[derive(Debug, Snafu)]
 pub enum Error {
+    #[snafu(display(
+        "malformed block bytes in {}: {}",
+        decode_location(field, *transaction_index),
+        reason
+    ))]
+    Decode {
+        field: String,
+        /// The index of the transaction containing the field, if it's part of a transaction
+        transaction_index: Option<usize>,
+        reason: String,
+    },
     #[snafu(display("{} exceeds max allowable length", what))]
     MaxLengthExceeded {
         what: String,
     },
-    #[snafu(display("error in internal ssz types library"))]
+    #[snafu(display("error in internal ssz types library: {}", source))]
     SszTypesError {
         source: internal_types::Error,
     },
@@ -54,36 +66,153 @@ pub enum Error {
         what: WhatBound,
         index: usize,
     },
+    #[snafu(display("value is not exactly 32 bytes long"))]
     InvalidBytes32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Describe where in a block a decoding error happened, eg. "data of transaction 2"
+fn decode_location(field: &str, transaction_index: Option<usize>) -> String {
+    match transaction_index {
+        Some(transaction_index) => format!("{} of transaction {}", field, transaction_index),
+        None => field.to_string(),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdvanceSlot {}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CopyEeState {
+    pub source_shard_index: u64,
+    pub target_shard_index: u64,
+    pub ee_index: u64,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CreateExecutionEnvironment {
     pub ee: ExecutionEnvironment,
+    /// Read the EE code from this file instead of `ee.wasm_code`, which must then be empty
+    /// The path is resolved on the machine running the simulation (ie. the server, when going
+    /// through the API), and the file contents can be read back through the created EE.
+    #[serde(default)]
+    pub wasm_path: Option<PathBuf>,
+    /// Retrying with the same key returns the original result instead of creating another EE
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateExecutionEnvironments {
+    pub execution_environments: Vec<ExecutionEnvironment>,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CreateShardBlock {
     pub shard_index: u64,
     pub shard_block: ShardBlock,
+    /// Retrying with the same key returns the original result instead of creating another block
+    /// Only `create_shard_block` checks the key, it is ignored when applying several blocks.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteExecutionEnvironment {
+    pub execution_environment_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExecutionEnvironmentExists {
+    pub ee_index: u64,
 }
+/// The EEs a simulation starts with, see `Simulation::from_genesis`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Genesis {
+    /// Created in order, so the EE at position `i` gets index `i`
+    pub execution_environments: Vec<ExecutionEnvironment>,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetEeAuditLog {}
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetExecutionEnvironment {
     pub ee_index: u64,
 }
 #[derive(Debug, Deserialize, Serialize)]
+pub struct GetExecutionEnvironmentHash {
+    pub execution_environment_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetExecutionEnvironmentModuleInfo {
+    pub execution_environment_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GetExecutionEnvironmentState {
     pub ee_index: u64,
     pub shard_index: u64,
+    /// Get the EE's state slot with this key instead of its main state
+    #[serde(default)]
+    pub state_key: Option<u32>,
 }
 #[derive(Debug, Deserialize, Serialize)]
+pub struct GetMetrics {}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetOperationLog {}
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GetShardBlock {
     pub shard_index: u64,
     pub shard_slot_index: u64,
 }
 #[derive(Debug, Deserialize, Serialize)]
+pub struct GetShardBlockRange {
+    pub shard_index: u64,
+    /// Slot index of the first block to get
+    pub start_index: u64,
+    /// The most blocks to get; fewer are returned if the shard's chain ends first
+    pub count: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetShardChain {
+    pub shard_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GetShardState {
     pub shard_index: u64,
 }
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetSimulationState {}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetStateRootAt {
+    pub seq: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetTransaction {
+    pub shard_index: u64,
+    pub shard_slot_index: u64,
+    pub transaction_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListExecutionEnvironmentStates {
+    pub shard_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListExecutionEnvironments {}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplayTransaction {
+    pub shard_index: u64,
+    pub shard_slot_index: u64,
+    pub transaction_index: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimulateTransaction {
+    pub shard_index: u64,
+    pub transaction: ShardTransaction,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateExecutionEnvironment {
+    pub execution_environment_index: u64,
+    /// Only the code is used; the EE keeps its initial state
+    pub execution_environment: ExecutionEnvironment,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Reset {}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShardExists {
+    pub shard_index: u64,
+}
 
 /// Defines custom serialization for basic return types
 /// If serialization is required, appropriate basic types returned from the Simulation can be
@@ -92,34 +221,520 @@ pub struct GetShardState {
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum CustomSerializedReturnTypes {
-    #[serde(with = "base64_arr")]
+    #[serde(with = "base64_fields::root")]
     Base64EncodedRoot([u8; 32]),
 }
 
-// Interface structs
+/// The body the server returns when an operation fails
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    /// Stable numeric code of the error
+    pub code: u32,
+    pub message: String,
+}
 
+/// The outcome of one entry of a batch request
+/// Each entry succeeds or fails on its own, so one bad entry doesn't fail the whole batch.
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum BatchEntry<T> {
+    Ok(T),
+    /// The error the entry failed with, as it would be returned for a request of its own
+    Err {
+        code: u32,
+        message: String,
+    },
+}
+
+impl<T> BatchEntry<T> {
+    /// Get the value of a successful entry, discarding the error of a failed one
+    pub fn ok(self) -> Option<T> {
+        match self {
+            BatchEntry::Ok(value) => Some(value),
+            BatchEntry::Err { .. } => None,
+        }
+    }
+}
+
+// Interface structs
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ExecutionEnvironment {
-    #[serde(with = "base64_arr")]
+    #[serde(with = "base64_fields::initial_state")]
     pub initial_state: [u8; 32],
 
-    #[serde(with = "base64_vec")]
+    #[serde(default, with = "base64_fields::wasm_code")]
     pub wasm_code: Vec<u8>,
+
+    /// The index of the EE, set on EEs returned by the simulation and ignored when creating one
+    #[serde(default)]
+    pub index: Option<u64>,
 }
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ShardTransaction {
     pub data: Vec<u8>,
     pub ee_index: u64,
+
+    /// The transaction is skipped (without executing) unless this holds when it is reached
+    #[serde(default)]
+    pub condition: Option<Condition>,
+
+    /// Run against (and update) the EE's state slot with this key instead of its main state
+    /// Each EE has a separate state for every key on every shard, which starts out as the EE's
+    /// initial state.
+    #[serde(default)]
+    pub state_key: Option<u32>,
 }
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+}
+/// Compares the current state of an EE against a value
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Condition {
+    pub ee_index: u64,
+
+    #[serde(with = "base64_fields::state")]
+    pub state: [u8; 32],
+
+    pub comparison: Comparison,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ShardBlock {
     pub transactions: Vec<ShardTransaction>,
+    /// Hex-encoded hash of the block's transactions, which is the same for any two blocks with
+    /// the same transactions
+    /// Set on blocks returned by the simulation, and ignored when creating a block.
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    /// Hex-encoded `block_hash` of the previous block on the same shard (all zero for the first
+    /// block), linking the blocks of a shard into a chain
+    /// Set on blocks returned by the simulation, and ignored when creating a block.
+    #[serde(default)]
+    pub parent_hash: Option<String>,
+}
+
+/// The differences between the transactions of two shard blocks, see `ShardBlock::diff`
+#[derive(Debug, Default, PartialEq)]
+pub struct BlockDiff {
+    pub only_in_self: Vec<ShardTransaction>,
+    pub only_in_other: Vec<ShardTransaction>,
+    pub in_both: Vec<ShardTransaction>,
+}
+
+impl ExecutionEnvironment {
+    /// Build an EE to create from its WASM code, with an all zero initial state
+    /// The code is base64 encoded when the EE is serialized, so it's given here as raw bytes.
+    pub fn from_wasm_bytes(wasm_code: &[u8]) -> Self {
+        ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: wasm_code.to_vec(),
+            index: None,
+        }
+    }
+}
+
+impl ShardTransaction {
+    /// Build an unconditional transaction for an EE from its raw data
+    pub fn from_data(ee_index: u64, data: &[u8]) -> Self {
+        ShardTransaction {
+            data: data.to_vec(),
+            ee_index,
+            condition: None,
+            state_key: None,
+        }
+    }
+}
+
+impl ShardBlock {
+    /// Compare the transactions in two blocks by content, ignoring their order
+    /// Duplicate transactions are matched up one-to-one, so a transaction appearing twice in one
+    /// block and once in the other is reported once in `in_both` and once as only in the first.
+    /// Each list keeps the order the transactions appear in their block.
+    pub fn diff(&self, other: &ShardBlock) -> BlockDiff {
+        let mut diff = BlockDiff::default();
+        let mut unmatched: Vec<Option<&ShardTransaction>> =
+            other.transactions.iter().map(Some).collect();
+
+        for transaction in self.transactions.iter() {
+            let matching = unmatched
+                .iter_mut()
+                .find(|t| t.map_or(false, |t| t == transaction));
+            match matching {
+                Some(t) => {
+                    *t = None;
+                    diff.in_both.push(transaction.clone());
+                }
+                None => diff.only_in_self.push(transaction.clone()),
+            }
+        }
+        diff.only_in_other = unmatched.into_iter().flatten().cloned().collect();
+
+        diff
+    }
+
+    /// Encode the block in a compact binary format, eg. for network transfer
+    ///
+    /// All integers are little-endian. The block is the number of transactions (u32) followed by
+    /// each transaction:
+    /// - EE index (u64)
+    /// - length of the data (u32), followed by the raw data
+    /// - 0 (u8) if there is no condition, or 1 (u8) followed by the condition's EE index (u64),
+    ///   state (32 bytes), and comparison (u8, 0 for `Equal` and 1 for `NotEqual`)
+    /// - 0 (u8) if there is no state key, or 1 (u8) followed by the state key (u32)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.transactions.len() as u32).to_le_bytes());
+        for transaction in self.transactions.iter() {
+            bytes.extend_from_slice(&transaction.ee_index.to_le_bytes());
+            bytes.extend_from_slice(&(transaction.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&transaction.data);
+            match &transaction.condition {
+                None => bytes.push(0),
+                Some(condition) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&condition.ee_index.to_le_bytes());
+                    bytes.extend_from_slice(&condition.state);
+                    bytes.push(match condition.comparison {
+                        Comparison::Equal => 0,
+                        Comparison::NotEqual => 1,
+                    });
+                }
+            }
+            match transaction.state_key {
+                None => bytes.push(0),
+                Some(state_key) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&state_key.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decode a block from the format produced by `to_bytes`
+    /// Errors name the field (and transaction) that couldn't be decoded.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let decode_error = |field: &str, transaction_index: Option<usize>| {
+            let field = field.to_string();
+            move |reason| Error::Decode {
+                field,
+                transaction_index,
+                reason,
+            }
+        };
+
+        let mut reader = ByteReader { bytes };
+        let num_transactions = reader
+            .read_u32()
+            .map_err(decode_error("number of transactions", None))?;
+        let mut transactions = Vec::new();
+        for transaction_index in 0..num_transactions as usize {
+            let field = |field| decode_error(field, Some(transaction_index));
+            let ee_index = reader.read_u64().map_err(field("ee_index"))?;
+            let data_len = reader.read_u32().map_err(field("data length"))? as usize;
+            let data = reader.read(data_len).map_err(field("data"))?.to_vec();
+            let condition = match reader.read_u8().map_err(field("condition flag"))? {
+                0 => None,
+                1 => {
+                    let ee_index = reader.read_u64().map_err(field("condition ee_index"))?;
+                    let state = reader
+                        .read(32)
+                        .map_err(field("condition state"))?
+                        .to_vec()
+                        .to_bytes32()?;
+                    let comparison =
+                        match reader.read_u8().map_err(field("condition comparison"))? {
+                            0 => Comparison::Equal,
+                            1 => Comparison::NotEqual,
+                            c => {
+                                return Err(field("condition comparison")(format!(
+                                    "invalid comparison: {}",
+                                    c
+                                )))
+                            }
+                        };
+                    Some(Condition {
+                        ee_index,
+                        state,
+                        comparison,
+                    })
+                }
+                flag => {
+                    return Err(field("condition flag")(format!(
+                        "invalid condition flag: {}",
+                        flag
+                    )))
+                }
+            };
+            let state_key = match reader.read_u8().map_err(field("state key flag"))? {
+                0 => None,
+                1 => Some(reader.read_u32().map_err(field("state key"))?),
+                flag => {
+                    return Err(field("state key flag")(format!(
+                        "invalid state key flag: {}",
+                        flag
+                    )))
+                }
+            };
+            transactions.push(ShardTransaction {
+                data,
+                ee_index,
+                condition,
+                state_key,
+            });
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(decode_error("end of block", None)(format!(
+                "{} trailing bytes",
+                reader.bytes.len()
+            )));
+        }
+        Ok(Self {
+            transactions,
+            block_hash: None,
+            parent_hash: None,
+        })
+    }
+}
+
+/// Reads values from the front of a byte slice, for decoding `ShardBlock::to_bytes`
+/// Errors are the reason reading failed, for the caller to wrap with the field being read.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err(format!(
+                "expected {} more bytes, found {}",
+                len,
+                self.bytes.len()
+            ));
+        }
+        let (read, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(read)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.read(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.read(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+/// Metadata about a single shard chain
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ShardChainInfo {
+    /// The number of blocks added to the shard, including any evicted to stay within the memory
+    /// budget
+    pub num_blocks: u64,
+    pub num_ee_states: u64,
+    /// Hex SHA-256 root of the shard's EE states, in EE index order, as of its latest block
+    pub state_root: String,
+}
+
+/// An overview of the whole simulation
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SimulationState {
+    /// The number of EEs, not counting deleted ones
+    pub num_execution_environments: u64,
+    pub num_shard_chains: u64,
+    /// The number of blocks added to each shard (including evicted ones), by shard index
+    pub shard_block_counts: Vec<u64>,
+    /// The current beacon chain slot
+    pub slot: u64,
+    /// The number of blocks on each shard that have been finalized, by shard index
+    /// These are always the oldest blocks on the shard.
+    pub finalized_block_counts: Vec<u64>,
+}
+
+/// Running totals of the work done by the simulation since it started
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MetricsSnapshot {
+    pub execution_environments_created: u64,
+    pub shard_blocks_created: u64,
+    /// Transactions in created shard blocks, not counting skipped ones
+    pub transactions_executed: u64,
+    /// Args (EEs or shard blocks) that couldn't be decoded
+    pub decode_errors: u64,
+}
+
+/// What was discarded by resetting the simulation
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ResetSummary {
+    /// The number of EEs, not counting deleted ones
+    pub num_execution_environments: u64,
+    /// The number of shard blocks on all shards, including any evicted ones
+    pub num_shard_blocks: u64,
+}
+
+/// A whole-simulation state root, and the sequence number of the operation that produced it
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StateRootAt {
+    pub seq: u64,
+
+    #[serde(with = "base64_fields::state_root")]
+    pub state_root: [u8; 32],
+}
+
+/// A change to the state of one EE on one shard, made by a new shard block
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StateChange {
+    pub shard_index: u64,
+    pub ee_index: u64,
+    /// The key of the EE's state slot that changed, or `None` for its main state
+    #[serde(default)]
+    pub state_key: Option<u32>,
+
+    /// The state of the EE before the block
+    #[serde(with = "base64_fields::old_root")]
+    pub old_root: [u8; 32],
+
+    /// The state of the EE after the block
+    #[serde(with = "base64_fields::new_root")]
+    pub new_root: [u8; 32],
+}
+
+/// The outcome of executing a single transaction
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TransactionReceipt {
+    pub ee_index: u64,
+    /// The key of the EE's state slot the transaction ran against, or `None` for its main state
+    #[serde(default)]
+    pub state_key: Option<u32>,
+
+    #[serde(with = "base64_fields::pre_state")]
+    pub pre_state: [u8; 32],
+
+    #[serde(with = "base64_fields::post_state")]
+    pub post_state: [u8; 32],
+
+    /// True if the transaction's condition didn't hold, so it wasn't executed
+    pub skipped: bool,
+}
+
+/// The outcome of creating a shard block
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ShardBlockResult {
+    /// The slot of the new block on its shard
+    pub block_index: u64,
+    /// What each transaction in the block did, in block order
+    pub transaction_results: Vec<TransactionReceipt>,
+}
+
+/// The kinds of change to the set of EEs that are recorded in the EE audit log
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum EeAuditAction {
+    Create,
+    Freeze,
+    Unfreeze,
+    Delete,
+    Update,
+}
+
+/// One entry in the EE audit log
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct EeAuditEntry {
+    /// Position of the entry in the log, starting at 0
+    pub sequence: u64,
+    pub ee_index: u64,
+    pub action: EeAuditAction,
+
+    #[serde(with = "base64_fields::code_hash")]
+    pub code_hash: [u8; 32],
+}
+
+/// The imports and exports declared by an EE's WASM module
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModuleInfo {
+    pub imports: Vec<ModuleImport>,
+    pub exports: Vec<ModuleExport>,
+}
+
+/// The kinds of item a WASM module can import or export
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ModuleItemKind {
+    Function,
+    Table,
+    Memory,
+    Global,
+}
+
+/// The parameter and result types of a WASM function, eg. `["i32", "i32"]`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FunctionSignature {
+    pub params: Vec<String>,
+    pub results: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModuleImport {
+    pub module: String,
+    pub name: String,
+    pub kind: ModuleItemKind,
+    /// Only set for functions
+    pub signature: Option<FunctionSignature>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModuleExport {
+    pub name: String,
+    pub kind: ModuleItemKind,
+    /// Only set for functions
+    pub signature: Option<FunctionSignature>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ShardState {
-    #[serde(with = "vec_base64_arrs")]
+    #[serde(with = "base64_fields::execution_environment_states")]
     pub execution_environment_states: Vec<[u8; 32]>,
+    /// Only the keyed states that have been written, sorted by EE index and then key
+    #[serde(default)]
+    pub keyed_execution_environment_states: Vec<KeyedEeState>,
+}
+
+/// The state an EE keeps in the slot with `state_key`, separate from its main state
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct KeyedEeState {
+    pub ee_index: u64,
+    pub state_key: u32,
+    #[serde(with = "base64_fields::state")]
+    pub state: [u8; 32],
+}
+
+/// An operation that changed the simulation, with the args it was called with
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum LoggedOperation {
+    AdvanceSlot(AdvanceSlot),
+    CopyEeState(CopyEeState),
+    CreateExecutionEnvironment(CreateExecutionEnvironment),
+    CreateExecutionEnvironments(CreateExecutionEnvironments),
+    CreateShardBlock(CreateShardBlock),
+    DeleteExecutionEnvironment(DeleteExecutionEnvironment),
+    Reset(Reset),
+    UpdateExecutionEnvironment(UpdateExecutionEnvironment),
+}
+
+/// The successful operations that changed a simulation, oldest first
+/// Replaying the log against a new simulation reproduces the recorded one, as long as any EE
+/// code read from a `wasm_path` hasn't changed since.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OperationLog {
+    pub operations: Vec<LoggedOperation>,
 }
 
 // Conversions to/from interface structs <--> internal structs
@@ -133,6 +748,7 @@ impl<T: internal_types::EthSpec> From<internal_types::ExecutionEnvironment<T>>
         Self {
             initial_state,
             wasm_code,
+            index: None,
         }
     }
 }
@@ -155,7 +771,13 @@ impl From<internal_types::ShardTransaction> for ShardTransaction {
     fn from(value: internal_types::ShardTransaction) -> Self {
         let data: Vec<u8> = value.data.into();
         let ee_index: u64 = value.ee_index.into();
-        Self { data, ee_index }
+        let condition = value.condition.map(Condition::from);
+        Self {
+            data,
+            ee_index,
+            condition,
+            state_key: value.state_key,
+        }
     }
 }
 impl TryFrom<ShardTransaction> for internal_types::ShardTransaction {
@@ -163,7 +785,37 @@ impl TryFrom<ShardTransaction> for internal_types::ShardTransaction {
     fn try_from(value: ShardTransaction) -> Result<Self, Self::Error> {
         let ee_index = value.ee_index.into();
         let data = internal_types::VariableList::new(value.data).context(SszTypesError)?;
-        Ok(Self { data, ee_index })
+        let condition = value.condition.map(internal_types::Condition::from);
+        Ok(Self {
+            data,
+            ee_index,
+            condition,
+            state_key: value.state_key,
+        })
+    }
+}
+
+impl From<internal_types::Condition> for Condition {
+    fn from(value: internal_types::Condition) -> Self {
+        let comparison = if value.equal {
+            Comparison::Equal
+        } else {
+            Comparison::NotEqual
+        };
+        Self {
+            ee_index: value.ee_index.into(),
+            state: value.state.into(),
+            comparison,
+        }
+    }
+}
+impl From<Condition> for internal_types::Condition {
+    fn from(value: Condition) -> Self {
+        Self {
+            ee_index: value.ee_index.into(),
+            state: internal_types::Root::from(value.state),
+            equal: value.comparison == Comparison::Equal,
+        }
     }
 }
 
@@ -174,7 +826,11 @@ impl<T: internal_types::EthSpec> From<internal_types::ShardBlock<T>> for ShardBl
             .into_iter()
             .map(|t| -> ShardTransaction { t.clone().into() })
             .collect();
-        Self { transactions }
+        Self {
+            transactions,
+            block_hash: None,
+            parent_hash: None,
+        }
     }
 }
 impl<T: internal_types::EthSpec> TryFrom<ShardBlock> for internal_types::ShardBlock<T> {
@@ -186,7 +842,11 @@ impl<T: internal_types::EthSpec> TryFrom<ShardBlock> for internal_types::ShardBl
             transactions.push(transaction);
         }
         let transactions = internal_types::VariableList::new(transactions).context(SszTypesError)?;
-        Ok(Self { transactions })
+        // The simulation links the block to its parent when the block is added to a shard
+        Ok(Self {
+            transactions,
+            parent_hash: internal_types::Root::zero(),
+        })
     }
 }
 
@@ -197,8 +857,18 @@ impl<T: internal_types::EthSpec> From<internal_types::ShardState<T>> for ShardSt
             .into_iter()
             .map(|t| -> [u8; 32] { t.0 })
             .collect();
+        let keyed_execution_environment_states: Vec<KeyedEeState> = value
+            .keyed_execution_environment_states
+            .into_iter()
+            .map(|t| KeyedEeState {
+                ee_index: t.ee_index.into(),
+                state_key: t.state_key,
+                state: t.state.0,
+            })
+            .collect();
         Self {
             execution_environment_states,
+            keyed_execution_environment_states,
         }
     }
 }
@@ -212,8 +882,21 @@ impl<T: internal_types::EthSpec> TryFrom<ShardState> for internal_types::ShardSt
             .collect();
         let execution_environment_states =
             internal_types::VariableList::new(execution_environment_states).context(SszTypesError)?;
+        let keyed_execution_environment_states: Vec<internal_types::KeyedEeState> = value
+            .keyed_execution_environment_states
+            .into_iter()
+            .map(|t| internal_types::KeyedEeState {
+                ee_index: t.ee_index.into(),
+                state_key: t.state_key,
+                state: internal_types::Root::from(t.state),
+            })
+            .collect();
+        let keyed_execution_environment_states =
+            internal_types::VariableList::new(keyed_execution_environment_states)
+                .context(SszTypesError)?;
         Ok(Self {
             execution_environment_states,
+            keyed_execution_environment_states,
         })
     }
 }
@@ -236,11 +919,53 @@ impl ToBytes32 for Vec<u8> {
     }
 }
 
+/// Decode base64 in either the standard or the URL-safe alphabet, since some clients only send
+/// the latter
+fn decode_base64(txt: &str) -> std::result::Result<Vec<u8>, base64::DecodeError> {
+    base64::decode(txt).or_else(|_| base64::decode_config(txt, base64::URL_SAFE))
+}
+
+/// serde `with` modules for every base64 encoded field, which name the field in decoding errors
+/// serde doesn't tell a `deserialize` function which field it's decoding, so each field name gets
+/// its own module.
+mod base64_fields {
+    macro_rules! base64_field {
+        ($field:ident, $helper:ident) => {
+            pub mod $field {
+                pub use super::super::$helper::serialize;
+
+                pub fn deserialize<'de, D>(
+                    deserializer: D,
+                ) -> Result<super::super::$helper::Decoded, D::Error>
+                where
+                    D: serde::Deserializer<'de>,
+                {
+                    super::super::$helper::deserialize_field(deserializer, stringify!($field))
+                }
+            }
+        };
+    }
+
+    base64_field!(code_hash, base64_arr);
+    base64_field!(execution_environment_states, vec_base64_arrs);
+    base64_field!(initial_state, base64_arr);
+    base64_field!(new_root, base64_arr);
+    base64_field!(old_root, base64_arr);
+    base64_field!(post_state, base64_arr);
+    base64_field!(pre_state, base64_arr);
+    base64_field!(root, base64_arr);
+    base64_field!(state, base64_arr);
+    base64_field!(state_root, base64_arr);
+    base64_field!(wasm_code, base64_vec);
+}
+
 mod vec_base64_arrs {
     use super::ToBytes32;
     use serde::de::{Deserialize, Deserializer, Error, Unexpected};
     use serde::ser::{SerializeSeq, Serializer};
 
+    pub type Decoded = Vec<[u8; 32]>;
+
     pub fn serialize<S>(vec: &Vec<[u8; 32]>, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -253,7 +978,7 @@ mod vec_base64_arrs {
         seq.end()
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error>
+    pub fn deserialize_field<'de, D>(deserializer: D, field: &str) -> Result<Decoded, D::Error>
     where
         D: Deserializer<'de>,
     {
@@ -266,15 +991,14 @@ mod vec_base64_arrs {
             .map(|s| -> Result<[u8; 32], D::Error> {
                 // TODO: Some duplicated code between this deserialize and the deserialize methods below
                 // There's probably a better way to do this without repeating that logic.
-                let vec_u8 = base64::decode(s).map_err(|_| {
-                    D::Error::invalid_value(Unexpected::Str(s), &"base64 encoded bytes")
+                let vec_u8 = super::decode_base64(s).map_err(|_| {
+                    let expected = format!("base64 encoded bytes for `{}`", field);
+                    D::Error::invalid_value(Unexpected::Str(s), &expected.as_str())
                 })?;
 
                 vec_u8.to_bytes32().map_err(|_| {
-                    D::Error::invalid_value(
-                        Unexpected::Bytes(&vec_u8),
-                        &"exactly 32 base64 encoded bytes",
-                    )
+                    let expected = format!("exactly 32 base64 encoded bytes for `{}`", field);
+                    D::Error::invalid_value(Unexpected::Bytes(&vec_u8), &expected.as_str())
                 })
             })
             .collect();
@@ -287,6 +1011,8 @@ mod base64_vec {
     use serde::de::{Deserialize, Deserializer, Error as _, Unexpected};
     use serde::Serializer;
 
+    pub type Decoded = Vec<u8>;
+
     pub fn serialize<T, S>(bytes: T, serializer: S) -> Result<S::Ok, S::Error>
     where
         T: AsRef<[u8]>,
@@ -296,14 +1022,16 @@ mod base64_vec {
         serializer.serialize_str(&txt)
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    pub fn deserialize_field<'de, D>(deserializer: D, field: &str) -> Result<Decoded, D::Error>
     where
         D: Deserializer<'de>,
     {
         let txt = String::deserialize(deserializer)?;
 
-        base64::decode(&txt)
-            .map_err(|_| D::Error::invalid_value(Unexpected::Str(&txt), &"base64 encoded bytes"))
+        super::decode_base64(&txt).map_err(|_| {
+            let expected = format!("base64 encoded bytes for `{}`", field);
+            D::Error::invalid_value(Unexpected::Str(&txt), &expected.as_str())
+        })
     }
 }
 
@@ -314,14 +1042,310 @@ mod base64_arr {
 
     pub use super::base64_vec::serialize;
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+    pub type Decoded = [u8; 32];
+
+    pub fn deserialize_field<'de, D>(deserializer: D, field: &str) -> Result<Decoded, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let vec = super::base64_vec::deserialize(deserializer)?;
+        let vec = super::base64_vec::deserialize_field(deserializer, field)?;
 
         vec.to_bytes32().map_err(|_| {
-            D::Error::invalid_value(Unexpected::Bytes(&vec), &"exactly 32 base64 encoded bytes")
+            let expected = format!("exactly 32 base64 encoded bytes for `{}`", field);
+            D::Error::invalid_value(Unexpected::Bytes(&vec), &expected.as_str())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(data: u8) -> ShardTransaction {
+        ShardTransaction {
+            data: vec![data],
+            ee_index: 0,
+            condition: None,
+            state_key: None,
+        }
+    }
+
+    #[test]
+    fn shard_block_json_round_trip() {
+        let block = ShardBlock {
+            transactions: vec![
+                transaction(1),
+                ShardTransaction {
+                    data: vec![2, 3],
+                    ee_index: 1,
+                    condition: Some(Condition {
+                        ee_index: 0,
+                        state: [4; 32],
+                        comparison: Comparison::Equal,
+                    }),
+                    state_key: None,
+                },
+            ],
+            block_hash: None,
+            parent_hash: None,
+        };
+        let json = serde_json::to_string(&block).unwrap();
+        let decoded: ShardBlock = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.transactions, block.transactions);
+    }
+
+    #[test]
+    fn batch_entries_json_round_trip() {
+        let entries = vec![
+            BatchEntry::Ok(7u64),
+            BatchEntry::Err {
+                code: 3,
+                message: "no shard block exists at index: 9".to_string(),
+            },
+        ];
+        let json = serde_json::to_string(&entries).unwrap();
+        let decoded: Vec<BatchEntry<u64>> = serde_json::from_str(&json).unwrap();
+        match &decoded[1] {
+            BatchEntry::Err { code, message } => {
+                assert_eq!(*code, 3);
+                assert_eq!(message, "no shard block exists at index: 9");
+            }
+            entry => panic!("unexpected entry: {:?}", entry),
+        }
+        let values: Vec<Option<u64>> = decoded.into_iter().map(BatchEntry::ok).collect();
+        assert_eq!(values, vec![Some(7), None]);
+    }
+
+    #[test]
+    fn error_response_json_is_code_and_message() {
+        let json = r#"{"code":5,"message":"shard index out of bounds"}"#;
+        let error: ErrorResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(error.code, 5);
+        assert_eq!(error.message, "shard index out of bounds");
+        assert_eq!(serde_json::to_string(&error).unwrap(), json);
+    }
+
+    #[test]
+    fn constructed_args_json_round_trip() {
+        let wasm_code = [0, 0x61, 0x73, 0x6d, 1, 0, 0, 0];
+        let ee = ExecutionEnvironment::from_wasm_bytes(&wasm_code);
+        let json = serde_json::to_string(&ee).unwrap();
+        let decoded: ExecutionEnvironment = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.wasm_code, wasm_code);
+        assert_eq!(decoded.initial_state, [0; 32]);
+        assert_eq!(decoded, ee);
+
+        let data = [0xff, 0, 7];
+        let transaction = ShardTransaction::from_data(3, &data);
+        let json = serde_json::to_string(&transaction).unwrap();
+        let decoded: ShardTransaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.data, data);
+        assert_eq!(decoded.ee_index, 3);
+        assert_eq!(decoded, transaction);
+    }
+
+    #[test]
+    fn compact_block_bytes_round_trip() {
+        let block = ShardBlock {
+            transactions: vec![
+                transaction(1),
+                ShardTransaction {
+                    data: vec![],
+                    ee_index: 7,
+                    condition: Some(Condition {
+                        ee_index: 3,
+                        state: [9; 32],
+                        comparison: Comparison::NotEqual,
+                    }),
+                    state_key: None,
+                },
+            ],
+            block_hash: None,
+            parent_hash: None,
+        };
+        let bytes = block.to_bytes();
+        assert_eq!(
+            ShardBlock::from_bytes(&bytes).unwrap().transactions,
+            block.transactions
+        );
+
+        let empty_block = ShardBlock {
+            transactions: vec![],
+            block_hash: None,
+            parent_hash: None,
+        };
+        assert!(ShardBlock::from_bytes(&empty_block.to_bytes())
+            .unwrap()
+            .transactions
+            .is_empty());
+    }
+
+    #[test]
+    fn malformed_block_bytes_are_rejected() {
+        let bytes = ShardBlock {
+            transactions: vec![transaction(1)],
+            block_hash: None,
+            parent_hash: None,
+        }
+        .to_bytes();
+
+        // Truncated, trailing garbage, and a bad state key flag
+        let mut with_trailing_bytes = bytes.clone();
+        with_trailing_bytes.push(0);
+        let mut with_bad_flag = bytes.clone();
+        *with_bad_flag.last_mut().unwrap() = 2;
+        let malformed_bytes: Vec<&[u8]> = vec![
+            &bytes[..bytes.len() - 1],
+            &with_trailing_bytes,
+            &with_bad_flag,
+        ];
+        for malformed in malformed_bytes {
+            match ShardBlock::from_bytes(malformed) {
+                Err(Error::Decode { .. }) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn block_decode_errors_identify_the_field() {
+        let bytes = ShardBlock {
+            transactions: vec![transaction(1), transaction(2)],
+            block_hash: None,
+            parent_hash: None,
+        }
+        .to_bytes();
+
+        // Cut the block off in the middle of the second transaction's data
+        let transaction_len = 8 + 4 + 1 + 1 + 1;
+        let truncated = &bytes[..4 + transaction_len + 8 + 4];
+        match ShardBlock::from_bytes(truncated) {
+            Err(Error::Decode {
+                field,
+                transaction_index: Some(1),
+                ..
+            }) => assert_eq!(field, "data"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // A block without even the transaction count
+        match ShardBlock::from_bytes(&bytes[..2]) {
+            Err(Error::Decode {
+                field,
+                transaction_index: None,
+                ..
+            }) => assert_eq!(field, "number of transactions"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let error = ShardBlock::from_bytes(truncated).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "malformed block bytes in data of transaction 1: expected 1 more bytes, found 0"
+        );
+    }
+
+    #[test]
+    fn can_decode_url_safe_base64() {
+        // These bytes encode to `+` and `/` in the standard alphabet, `-` and `_` when URL-safe
+        let wasm_code = [0xfb, 0xff, 0xfe];
+        let initial_state = [0xfb; 32];
+        let json = format!(
+            r#"{{"initial_state": "{}", "wasm_code": "{}"}}"#,
+            base64::encode_config(&initial_state, base64::URL_SAFE),
+            base64::encode_config(&wasm_code, base64::URL_SAFE),
+        );
+        assert!(json.contains('-') && json.contains('_'));
+        let decoded: ExecutionEnvironment = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.initial_state, initial_state);
+        assert_eq!(decoded.wasm_code, wasm_code);
+
+        let json = format!(
+            r#"{{"execution_environment_states": ["{}"]}}"#,
+            base64::encode_config(&initial_state, base64::URL_SAFE),
+        );
+        let decoded: ShardState = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.execution_environment_states, vec![initial_state]);
+
+        // Anything else is still rejected
+        let json = r#"{"initial_state": "not base64!", "wasm_code": ""}"#;
+        assert!(serde_json::from_str::<ExecutionEnvironment>(json).is_err());
+    }
+
+    #[test]
+    fn base64_decode_errors_name_the_field() {
+        let json = format!(
+            r#"{{"initial_state": "{}", "wasm_code": "not base64!"}}"#,
+            base64::encode(&[0; 32]),
+        );
+        let error = serde_json::from_str::<ExecutionEnvironment>(&json).unwrap_err();
+        assert!(error.to_string().contains("`wasm_code`"), "{}", error);
+
+        // Valid base64, but not 32 bytes long
+        let json = r#"{"initial_state": "AAAA", "wasm_code": ""}"#;
+        let error = serde_json::from_str::<ExecutionEnvironment>(json).unwrap_err();
+        assert!(error.to_string().contains("`initial_state`"), "{}", error);
+
+        let json = r#"{"execution_environment_states": ["not base64!"]}"#;
+        let error = serde_json::from_str::<ShardState>(json).unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.contains("`execution_environment_states`"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn bad_transaction_data_is_rejected() {
+        // Transaction data is a plain array of bytes, not base64
+        let json = r#"{"data": "AAAA", "ee_index": 0}"#;
+        assert!(serde_json::from_str::<ShardTransaction>(json).is_err());
+
+        let json = r#"{"data": [1, 256], "ee_index": 0}"#;
+        assert!(serde_json::from_str::<ShardTransaction>(json).is_err());
+
+        let json = r#"{"data": [1, -1], "ee_index": 0}"#;
+        assert!(serde_json::from_str::<ShardTransaction>(json).is_err());
+
+        let json = r#"{"data": [1, 255], "ee_index": 0}"#;
+        let transaction: ShardTransaction = serde_json::from_str(json).unwrap();
+        assert_eq!(transaction.data, vec![1, 255]);
+    }
+
+    #[test]
+    fn can_diff_shard_blocks() {
+        let block = ShardBlock {
+            transactions: vec![
+                transaction(1),
+                transaction(2),
+                transaction(3),
+                transaction(3),
+            ],
+            block_hash: None,
+            parent_hash: None,
+        };
+        let other = ShardBlock {
+            transactions: vec![transaction(4), transaction(3), transaction(2)],
+            block_hash: None,
+            parent_hash: None,
+        };
+
+        assert_eq!(
+            block.diff(&other),
+            BlockDiff {
+                only_in_self: vec![transaction(1), transaction(3)],
+                only_in_other: vec![transaction(4)],
+                in_both: vec![transaction(2), transaction(3)],
+            }
+        );
+        assert_eq!(
+            other.diff(&block),
+            BlockDiff {
+                only_in_self: vec![transaction(4)],
+                only_in_other: vec![transaction(1), transaction(3)],
+                in_both: vec![transaction(3), transaction(2)],
+            }
+        );
+    }
+}
@@ -21,7 +21,7 @@ where
 {
     // Versioning
     //    genesis_time: u64,
-    slot: Slot,
+    pub slot: Slot,
     //    fork: Fork,
 
     // History
@@ -65,7 +65,9 @@ where
     //    exposed_derived_secrets: FixedVector<VariableList<ValidatorIndex, T::MaxEarlyDerivedSecretRevealsPerEpoch>, T::EarlyDerivedSecretPenaltyMaxFutureEpochs>,
 
     // Unspecced fields
-    pub execution_environments: VariableList<ExecutionEnvironment<T>, T::MaxExecutionEnvironments>,
+    // Deleted EEs are replaced by `None` rather than removed, so later EEs keep their indices
+    pub execution_environments:
+        VariableList<Option<ExecutionEnvironment<T>>, T::MaxExecutionEnvironments>,
 }
 
 impl<T: EthSpec> BeaconState<T> {
@@ -1,5 +1,6 @@
 use crate::eth_spec::EthSpec;
 use crate::shard_transaction::ShardTransaction;
+use crate::slot_epoch_root::Root;
 use serde::{Deserialize, Serialize};
 use ssz_derive::{Decode as DeriveDecode, Encode as DeriveEncode};
 use ssz_types::VariableList;
@@ -10,4 +11,6 @@ where
     T: EthSpec,
 {
     pub transactions: VariableList<ShardTransaction, T::MaxTransactionsPerBlock>,
+    // Hash of the transactions of the previous block on the same shard (all zero for the first)
+    pub parent_hash: Root,
 }
@@ -1,8 +1,19 @@
 use crate::eth_spec::EthSpec;
-use crate::slot_epoch_root::Root;
+use crate::slot_epoch_root::{EeIndex, Root};
 use serde::{Deserialize, Serialize};
 use ssz_derive::{Decode as DeriveDecode, Encode as DeriveEncode};
 use ssz_types::VariableList;
+// TODO: Replace this with a limit from the spec, if keyed EE states are ever specced
+// Currently this is arbitrarily set to 65536 keyed states per shard
+use typenum::U65536;
+
+/// The state an EE keeps under a key, separate from its main state
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize, DeriveDecode, DeriveEncode)]
+pub struct KeyedEeState {
+    pub ee_index: EeIndex,
+    pub state_key: u32,
+    pub state: Root,
+}
 
 #[derive(Default, Debug, PartialEq, Clone, Deserialize, Serialize, DeriveDecode, DeriveEncode)]
 pub struct ShardState<T>
@@ -16,12 +27,15 @@ where
 
     // Unspecced fields
     pub execution_environment_states: VariableList<Root, T::MaxExecutionEnvironments>,
+    // Only holds the keys that have been written, sorted by EE index and then key
+    pub keyed_execution_environment_states: VariableList<KeyedEeState, U65536>,
 }
 
 impl<T: EthSpec> ShardState<T> {
     pub fn new() -> Self {
         Self {
             execution_environment_states: VariableList::empty(),
+            keyed_execution_environment_states: VariableList::empty(),
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::slot_epoch_root::EeIndex;
+use crate::slot_epoch_root::{EeIndex, Root};
 use serde::{Deserialize, Serialize};
 use ssz_derive::{Decode as DeriveDecode, Encode as DeriveEncode};
 use ssz_types::VariableList;
@@ -10,4 +10,18 @@ use typenum::U262144;
 pub struct ShardTransaction {
     pub data: VariableList<u8, U262144>,
     pub ee_index: EeIndex,
+    // If present, the transaction is skipped unless the condition holds when it is reached
+    pub condition: Option<Condition>,
+    // If present, the transaction runs against (and updates) the EE's state slot with this key
+    // instead of its main state
+    pub state_key: Option<u32>,
+}
+
+/// A predicate on the state of an EE, evaluated on the shard the transaction is executed on
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize, DeriveDecode, DeriveEncode)]
+pub struct Condition {
+    pub ee_index: EeIndex,
+    pub state: Root,
+    // If true, the condition holds when the EE state equals `state`, otherwise when it differs
+    pub equal: bool,
 }
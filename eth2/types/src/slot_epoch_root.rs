@@ -134,3 +134,31 @@ impl Encode for Root {
         buf.extend_from_slice(self.as_bytes());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn ee_index_keyed_map_survives_serialization() {
+        let mut ee_states: HashMap<EeIndex, Root> = HashMap::new();
+        for i in 0..10 {
+            ee_states.insert(EeIndex::new(i), Root::repeat_byte(i as u8));
+        }
+
+        let serialized = serde_json::to_string(&ee_states).unwrap();
+        let deserialized: HashMap<EeIndex, Root> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, ee_states);
+
+        // Lookups by freshly constructed indices must find the restored entries
+        for i in 0..10 {
+            assert_eq!(deserialized[&EeIndex::new(i)], Root::repeat_byte(i as u8));
+            assert_eq!(
+                deserialized[&EeIndex::from(i as usize)],
+                ee_states[&EeIndex::new(i)]
+            );
+        }
+        assert!(deserialized.get(&EeIndex::new(10)).is_none());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{Parse, Reqwest, Result};
-use reqwest::Client as HttpClient;
+use crate::{Error, Parse, Reqwest, Result};
+use reqwest::{Client as HttpClient, Response};
+use serde::de::DeserializeOwned;
 use snafu::ResultExt;
 use url::Url;
 
@@ -18,6 +19,36 @@ impl SimulationClient {
         }
     }
 
+    pub async fn advance_slot(&self, a: simulation_args::AdvanceSlot) -> Result<u64> {
+        let url = self.base_url.join("/advance-slot").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<u64>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn copy_ee_state(&self, a: simulation_args::CopyEeState) -> Result<()> {
+        let url = self.base_url.join("/copy-ee-state").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        decode::<()>(response).await?;
+
+        Ok(())
+    }
+
     pub async fn create_execution_environment(
         &self,
         a: simulation_args::CreateExecutionEnvironment,
@@ -27,35 +58,114 @@ impl SimulationClient {
             .join("/create-execution-environment")
             .context(Parse)?;
 
-        let res = self
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<u64>()
+            .context(Reqwest)?;
+        let res = decode::<u64>(response).await?;
+
+        Ok(res)
+    }
+    pub async fn create_execution_environments(
+        &self,
+        a: simulation_args::CreateExecutionEnvironments,
+    ) -> Result<Vec<u64>> {
+        let url = self
+            .base_url
+            .join("/create-execution-environments")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
             .await
             .context(Reqwest)?;
+        let res = decode::<Vec<u64>>(response).await?;
 
         Ok(res)
     }
-    pub async fn create_shard_block(&self, a: simulation_args::CreateShardBlock) -> Result<u64> {
+
+    pub async fn create_shard_block(
+        &self,
+        a: simulation_args::CreateShardBlock,
+    ) -> Result<simulation_args::ShardBlockResult> {
         let url = self.base_url.join("/create-shard-block").context(Parse)?;
 
-        let res = self
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<u64>()
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ShardBlockResult>(response).await?;
+
+        Ok(res)
+    }
+    pub async fn delete_execution_environment(
+        &self,
+        a: simulation_args::DeleteExecutionEnvironment,
+    ) -> Result<()> {
+        let url = self
+            .base_url
+            .join("/delete-execution-environment")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<()>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn execution_environment_exists(
+        &self,
+        a: simulation_args::ExecutionEnvironmentExists,
+    ) -> Result<bool> {
+        let url = self
+            .base_url
+            .join("/execution-environment-exists")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
             .await
             .context(Reqwest)?;
+        let res = decode::<bool>(response).await?;
 
         Ok(res)
     }
+    pub async fn get_ee_audit_log(
+        &self,
+        a: simulation_args::GetEeAuditLog,
+    ) -> Result<Vec<simulation_args::EeAuditEntry>> {
+        let url = self.base_url.join("/get-ee-audit-log").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<Vec<simulation_args::EeAuditEntry>>(response).await?;
+
+        Ok(res)
+    }
+
     pub async fn get_execution_environment(
         &self,
         a: simulation_args::GetExecutionEnvironment,
@@ -65,19 +175,60 @@ impl SimulationClient {
             .join("/get-execution-environment")
             .context(Parse)?;
 
-        let res = self
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<simulation_args::ExecutionEnvironment>()
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ExecutionEnvironment>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_execution_environment_hash(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentHash,
+    ) -> Result<String> {
+        let url = self
+            .base_url
+            .join("/get-execution-environment-hash")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<String>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_execution_environment_module_info(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentModuleInfo,
+    ) -> Result<simulation_args::ModuleInfo> {
+        let url = self
+            .base_url
+            .join("/get-execution-environment-module-info")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
             .await
             .context(Reqwest)?;
+        let res = decode::<simulation_args::ModuleInfo>(response).await?;
 
         Ok(res)
     }
+
     pub async fn get_execution_environment_state(
         &self,
         a: simulation_args::GetExecutionEnvironmentState,
@@ -87,56 +238,352 @@ impl SimulationClient {
             .join("/get-execution-environment-state")
             .context(Parse)?;
 
-        let res = self
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<simulation_args::CustomSerializedReturnTypes>()
-            .await
             .context(Reqwest)?;
+        let res = decode::<simulation_args::CustomSerializedReturnTypes>(response).await?;
 
         let simulation_args::CustomSerializedReturnTypes::Base64EncodedRoot(root) = res;
         Ok(root)
     }
+    pub async fn metrics(
+        &self,
+        a: simulation_args::GetMetrics,
+    ) -> Result<simulation_args::MetricsSnapshot> {
+        let url = self.base_url.join("/metrics").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::MetricsSnapshot>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_operation_log(
+        &self,
+        a: simulation_args::GetOperationLog,
+    ) -> Result<Option<simulation_args::OperationLog>> {
+        let url = self.base_url.join("/get-operation-log").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<Option<simulation_args::OperationLog>>(response).await?;
+
+        Ok(res)
+    }
+
     pub async fn get_shard_block(
         &self,
         a: simulation_args::GetShardBlock,
     ) -> Result<simulation_args::ShardBlock> {
         let url = self.base_url.join("/get-shard-block").context(Parse)?;
 
-        let res = self
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<simulation_args::ShardBlock>()
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ShardBlock>(response).await?;
+
+        Ok(res)
+    }
+    pub async fn get_shard_block_range(
+        &self,
+        a: simulation_args::GetShardBlockRange,
+    ) -> Result<Vec<simulation_args::ShardBlock>> {
+        let url = self
+            .base_url
+            .join("/get-shard-block-range")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
             .await
             .context(Reqwest)?;
+        let res = decode::<Vec<simulation_args::ShardBlock>>(response).await?;
 
         Ok(res)
     }
+
+    /// Get several shard blocks at once, with an entry for each in the same order as `a`
+    /// A block that can't be found only fails its own entry.
+    pub async fn get_shard_blocks(
+        &self,
+        a: Vec<simulation_args::GetShardBlock>,
+    ) -> Result<Vec<simulation_args::BatchEntry<simulation_args::ShardBlock>>> {
+        let url = self.base_url.join("/get-shard-blocks").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<Vec<simulation_args::BatchEntry<simulation_args::ShardBlock>>>(response)
+            .await?;
+
+        Ok(res)
+    }
+    pub async fn get_shard_chain(
+        &self,
+        a: simulation_args::GetShardChain,
+    ) -> Result<simulation_args::ShardChainInfo> {
+        let url = self.base_url.join("/get-shard-chain").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ShardChainInfo>(response).await?;
+
+        Ok(res)
+    }
+
     pub async fn get_shard_state(
         &self,
         a: simulation_args::GetShardState,
     ) -> Result<simulation_args::ShardState> {
         let url = self.base_url.join("/get-shard-state").context(Parse)?;
 
-        let res = self
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ShardState>(response).await?;
+
+        Ok(res)
+    }
+    pub async fn simulation_state(
+        &self,
+        a: simulation_args::GetSimulationState,
+    ) -> Result<simulation_args::SimulationState> {
+        let url = self.base_url.join("/get-simulation-state").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::SimulationState>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_state_root_at(
+        &self,
+        a: simulation_args::GetStateRootAt,
+    ) -> Result<simulation_args::StateRootAt> {
+        let url = self.base_url.join("/get-state-root-at").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::StateRootAt>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_transaction(
+        &self,
+        a: simulation_args::GetTransaction,
+    ) -> Result<simulation_args::ShardTransaction> {
+        let url = self.base_url.join("/get-transaction").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ShardTransaction>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn list_execution_environment_states(
+        &self,
+        a: simulation_args::ListExecutionEnvironmentStates,
+    ) -> Result<Vec<u64>> {
+        let url = self
+            .base_url
+            .join("/list-execution-environment-states")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<Vec<u64>>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn list_execution_environments(
+        &self,
+        a: simulation_args::ListExecutionEnvironments,
+    ) -> Result<Vec<simulation_args::ExecutionEnvironment>> {
+        let url = self
+            .base_url
+            .join("/list-execution-environments")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<Vec<simulation_args::ExecutionEnvironment>>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn reset(&self, a: simulation_args::Reset) -> Result<simulation_args::ResetSummary> {
+        let url = self.base_url.join("/reset").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::ResetSummary>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn shard_exists(&self, a: simulation_args::ShardExists) -> Result<bool> {
+        let url = self.base_url.join("/shard-exists").context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<bool>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn simulate_transaction(
+        &self,
+        a: simulation_args::SimulateTransaction,
+    ) -> Result<simulation_args::TransactionReceipt> {
+        let url = self.base_url.join("/simulate-transaction").context(Parse)?;
+
+        let response = self
             .http_client
             .post(url)
             .json(&a)
             .send()
             .await
-            .context(Reqwest)?
-            .json::<simulation_args::ShardState>()
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::TransactionReceipt>(response).await?;
+
+        Ok(res)
+    }
+
+    pub async fn update_execution_environment(
+        &self,
+        a: simulation_args::UpdateExecutionEnvironment,
+    ) -> Result<()> {
+        let url = self
+            .base_url
+            .join("/update-execution-environment")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
             .await
             .context(Reqwest)?;
+        let res = decode::<()>(response).await?;
 
         Ok(res)
     }
+
+    /// Wait until the state of the EE on the shard changes, then return the new state
+    /// The server gives up waiting after a while and returns the unchanged state instead, so
+    /// compare the result with the state you already have and call again if it's the same.
+    pub async fn watch_execution_environment_state(
+        &self,
+        a: simulation_args::GetExecutionEnvironmentState,
+    ) -> Result<[u8; 32]> {
+        let url = self
+            .base_url
+            .join("/watch-execution-environment-state")
+            .context(Parse)?;
+
+        let response = self
+            .http_client
+            .post(url)
+            .json(&a)
+            .send()
+            .await
+            .context(Reqwest)?;
+        let res = decode::<simulation_args::CustomSerializedReturnTypes>(response).await?;
+
+        let simulation_args::CustomSerializedReturnTypes::Base64EncodedRoot(root) = res;
+        Ok(root)
+    }
+}
+
+/// Decode the return value of a successful response, or the error the server returned instead
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let status = response.status();
+    if status.is_success() {
+        return response.json::<T>().await.context(Reqwest);
+    }
+
+    // Errors raised outside of operations, such as an unknown route, don't carry a code
+    match response.json::<simulation_args::ErrorResponse>().await {
+        Ok(simulation_args::ErrorResponse { code, message }) => {
+            Err(Error::Server { code, message })
+        }
+        Err(_) => Err(Error::Http {
+            status: status.as_u16(),
+        }),
+    }
 }
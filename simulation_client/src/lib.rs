@@ -20,8 +20,14 @@ pub enum Error {
         backtrace: Backtrace,
         source: ReqwestError,
     },
-    // #[snafu(display("error with HTTP request"))]
-    // HTTP,
+
+    /// The server failed the operation, see `code` for which error it was
+    #[snafu(display("server returned error {}: {}", code, message))]
+    Server { code: u32, message: String },
+
+    /// The server failed the request without saying why
+    #[snafu(display("server responded with HTTP status {}", status))]
+    Http { status: u16 },
 }
 
 /// Shorthand for result types returned by this library
@@ -68,8 +68,13 @@ async fn run_block_and_verify_state(
     let ee = simulation_args::ExecutionEnvironment {
         initial_state,
         wasm_code: wasm_code.to_vec(),
+        index: None,
+    };
+    let create_ee_args = simulation_args::CreateExecutionEnvironment {
+        ee,
+        wasm_path: None,
+        idempotency_key: None,
     };
-    let create_ee_args = simulation_args::CreateExecutionEnvironment { ee };
     let ee_index = simulation_client
         .create_execution_environment(create_ee_args)
         .await?;
@@ -77,20 +82,24 @@ async fn run_block_and_verify_state(
     println!("created new ee, it has index: {}", ee_index);
 
     // Set up a shard transaction with the specified data
-    let shard_transaction = simulation_args::ShardTransaction { data, ee_index };
+    let shard_transaction = simulation_args::ShardTransaction::from_data(ee_index, &data);
 
     // Create a shard block with the one transaction in it
     let shard_block = simulation_args::ShardBlock {
         transactions: vec![shard_transaction],
+        block_hash: None,
+        parent_hash: None,
     };
     let create_shard_block_args = simulation_args::CreateShardBlock {
         shard_index,
         shard_block,
+        idempotency_key: None,
     };
     // This creates the block and runs all the transactions inside it
     let shard_block_index = simulation_client
         .create_shard_block(create_shard_block_args)
-        .await?;
+        .await?
+        .block_index;
 
     println!("create a new shard block, it has index: {}", shard_block_index);
 
@@ -98,6 +107,7 @@ async fn run_block_and_verify_state(
     let get_ee_state_args = simulation_args::GetExecutionEnvironmentState {
         ee_index,
         shard_index,
+        state_key: None,
     };
     let ee_post_state = simulation_client
         .get_execution_environment_state(get_ee_state_args)
@@ -1,9 +1,13 @@
-use crate::dispatch::{simulation_args, Handle, Result as DispatchResult};
+use crate::dispatch::{simulation_args, Error as DispatchError, Handle, Result as DispatchResult};
 use crate::{SimulationServer};
 use rocket::config;
-use rocket::{post, routes, State};
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::{post, routes, Request, State};
 use rocket_contrib::json::Json;
 use snafu::{ResultExt, Snafu};
+use std::time::Duration;
+use tokio::time;
 use types::eth_spec::EthSpec;
 
 /// Shorthand for result types returned from the API server.
@@ -15,6 +19,40 @@ pub enum Error {
     Config { source: config::ConfigError },
 }
 
+impl<'r> Responder<'r> for DispatchError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let status = match self {
+            DispatchError::Sim { .. } => Status::BadRequest,
+            DispatchError::Send | DispatchError::Terminated | DispatchError::Panicked => {
+                Status::InternalServerError
+            }
+            DispatchError::Timeout => Status::ServiceUnavailable,
+        };
+        let body = simulation_args::ErrorResponse {
+            code: self.code(),
+            message: self.to_string(),
+        };
+
+        let mut response = Json(body).respond_to(request)?;
+        response.set_status(status);
+        Ok(response)
+    }
+}
+
+/// How long a watch request waits for the EE state to change before returning it unchanged
+const WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Convert the result of one entry of a batch operation into what's returned for it
+fn batch_entry<V>(result: DispatchResult<V>) -> simulation_args::BatchEntry<V> {
+    match result {
+        Ok(value) => simulation_args::BatchEntry::Ok(value),
+        Err(e) => simulation_args::BatchEntry::Err {
+            code: e.code(),
+            message: e.to_string(),
+        },
+    }
+}
+
 pub fn run<T: EthSpec>(simulation_server: &SimulationServer<T>, handle: Handle) -> Result<()> {
     let config = config::Config::build(config::Environment::Development)
         .address(format!("{}", simulation_server.bind.ip()))
@@ -26,12 +64,35 @@ pub fn run<T: EthSpec>(simulation_server: &SimulationServer<T>, handle: Handle)
         .mount(
             "/",
             routes![
+                advance_slot,
+                copy_ee_state,
                 create_execution_environment,
+                create_execution_environments,
                 create_shard_block,
+                delete_execution_environment,
+                execution_environment_exists,
+                get_ee_audit_log,
                 get_execution_environment,
+                get_execution_environment_hash,
+                get_execution_environment_module_info,
                 get_execution_environment_state,
+                get_operation_log,
                 get_shard_block,
+                get_shard_block_range,
+                get_shard_blocks,
+                get_shard_chain,
                 get_shard_state,
+                get_state_root_at,
+                get_transaction,
+                list_execution_environment_states,
+                list_execution_environments,
+                metrics,
+                reset,
+                shard_exists,
+                simulate_transaction,
+                simulation_state,
+                update_execution_environment,
+                watch_execution_environment_state,
             ],
         )
         .manage(handle)
@@ -40,6 +101,28 @@ pub fn run<T: EthSpec>(simulation_server: &SimulationServer<T>, handle: Handle)
     Ok(())
 }
 
+#[tokio::main]
+#[post("/advance-slot", data = "<args>")]
+async fn advance_slot(
+    args: Json<simulation_args::AdvanceSlot>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<u64>> {
+    let args = args.into_inner();
+    let slot = handle.clone().advance_slot(args).await?;
+    Ok(Json(slot))
+}
+
+#[tokio::main]
+#[post("/copy-ee-state", data = "<args>")]
+async fn copy_ee_state(
+    args: Json<simulation_args::CopyEeState>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<()>> {
+    let args = args.into_inner();
+    handle.clone().copy_ee_state(args).await?;
+    Ok(Json(()))
+}
+
 #[tokio::main]
 #[post("/create-execution-environment", data = "<args>")]
 async fn create_execution_environment(
@@ -53,15 +136,59 @@ async fn create_execution_environment(
     Ok(Json(ee_index))
 }
 
+#[tokio::main]
+#[post("/create-execution-environments", data = "<args>")]
+async fn create_execution_environments(
+    args: Json<simulation_args::CreateExecutionEnvironments>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<u64>>> {
+    let args = args.into_inner();
+    let ee_indices = handle.clone().create_execution_environments(args).await?;
+    Ok(Json(ee_indices))
+}
+
 #[tokio::main]
 #[post("/create-shard-block", data = "<args>")]
 async fn create_shard_block(
     args: Json<simulation_args::CreateShardBlock>,
     handle: State<Handle>,
-) -> DispatchResult<Json<u64>> {
+) -> DispatchResult<Json<simulation_args::ShardBlockResult>> {
+    let args = args.into_inner();
+    let shard_block_result = handle.clone().create_shard_block(args).await?;
+    Ok(Json(shard_block_result))
+}
+
+#[tokio::main]
+#[post("/delete-execution-environment", data = "<args>")]
+async fn delete_execution_environment(
+    args: Json<simulation_args::DeleteExecutionEnvironment>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<()>> {
+    let args = args.into_inner();
+    handle.clone().delete_execution_environment(args).await?;
+    Ok(Json(()))
+}
+
+#[tokio::main]
+#[post("/execution-environment-exists", data = "<args>")]
+async fn execution_environment_exists(
+    args: Json<simulation_args::ExecutionEnvironmentExists>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<bool>> {
     let args = args.into_inner();
-    let shard_block_index = handle.clone().create_shard_block(args).await?;
-    Ok(Json(shard_block_index))
+    let exists = handle.clone().execution_environment_exists(args).await?;
+    Ok(Json(exists))
+}
+
+#[tokio::main]
+#[post("/get-ee-audit-log", data = "<args>")]
+async fn get_ee_audit_log(
+    args: Json<simulation_args::GetEeAuditLog>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<simulation_args::EeAuditEntry>>> {
+    let args = args.into_inner();
+    let audit_log = handle.clone().get_ee_audit_log(args).await?;
+    Ok(Json(audit_log))
 }
 
 #[tokio::main]
@@ -75,6 +202,31 @@ async fn get_execution_environment(
     Ok(Json(ee))
 }
 
+#[tokio::main]
+#[post("/get-execution-environment-hash", data = "<args>")]
+async fn get_execution_environment_hash(
+    args: Json<simulation_args::GetExecutionEnvironmentHash>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<String>> {
+    let args = args.into_inner();
+    let code_hash = handle.clone().get_execution_environment_hash(args).await?;
+    Ok(Json(code_hash))
+}
+
+#[tokio::main]
+#[post("/get-execution-environment-module-info", data = "<args>")]
+async fn get_execution_environment_module_info(
+    args: Json<simulation_args::GetExecutionEnvironmentModuleInfo>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::ModuleInfo>> {
+    let args = args.into_inner();
+    let module_info = handle
+        .clone()
+        .get_execution_environment_module_info(args)
+        .await?;
+    Ok(Json(module_info))
+}
+
 #[tokio::main]
 #[post("/get-execution-environment-state", data = "<args>")]
 async fn get_execution_environment_state(
@@ -88,6 +240,17 @@ async fn get_execution_environment_state(
     Ok(Json(encodeable_ee_state_root))
 }
 
+#[tokio::main]
+#[post("/get-operation-log", data = "<args>")]
+async fn get_operation_log(
+    args: Json<simulation_args::GetOperationLog>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Option<simulation_args::OperationLog>>> {
+    let args = args.into_inner();
+    let operation_log = handle.clone().get_operation_log(args).await?;
+    Ok(Json(operation_log))
+}
+
 #[tokio::main]
 #[post("/get-shard-block", data = "<args>")]
 async fn get_shard_block(
@@ -99,6 +262,47 @@ async fn get_shard_block(
     Ok(Json(shard_block))
 }
 
+#[tokio::main]
+#[post("/get-shard-block-range", data = "<args>")]
+async fn get_shard_block_range(
+    args: Json<simulation_args::GetShardBlockRange>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<simulation_args::ShardBlock>>> {
+    let args = args.into_inner();
+    let shard_blocks = handle.clone().get_shard_block_range(args).await?;
+    Ok(Json(shard_blocks))
+}
+
+/// Returns an entry for each requested block, in request order, failing only the entries of
+/// blocks that can't be found
+#[tokio::main]
+#[post("/get-shard-blocks", data = "<args>")]
+async fn get_shard_blocks(
+    args: Json<Vec<simulation_args::GetShardBlock>>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<simulation_args::BatchEntry<simulation_args::ShardBlock>>>> {
+    let args = args.into_inner();
+    let shard_blocks = handle
+        .clone()
+        .get_shard_blocks(args)
+        .await?
+        .into_iter()
+        .map(batch_entry)
+        .collect();
+    Ok(Json(shard_blocks))
+}
+
+#[tokio::main]
+#[post("/get-shard-chain", data = "<args>")]
+async fn get_shard_chain(
+    args: Json<simulation_args::GetShardChain>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::ShardChainInfo>> {
+    let args = args.into_inner();
+    let shard_chain_info = handle.clone().get_shard_chain(args).await?;
+    Ok(Json(shard_chain_info))
+}
+
 #[tokio::main]
 #[post("/get-shard-state", data = "<args>")]
 async fn get_shard_state(
@@ -109,3 +313,142 @@ async fn get_shard_state(
     let shard_state = handle.clone().get_shard_state(args).await?;
     Ok(Json(shard_state))
 }
+
+#[tokio::main]
+#[post("/get-state-root-at", data = "<args>")]
+async fn get_state_root_at(
+    args: Json<simulation_args::GetStateRootAt>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::StateRootAt>> {
+    let args = args.into_inner();
+    let state_root_at = handle.clone().get_state_root_at(args).await?;
+    Ok(Json(state_root_at))
+}
+
+#[tokio::main]
+#[post("/get-transaction", data = "<args>")]
+async fn get_transaction(
+    args: Json<simulation_args::GetTransaction>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::ShardTransaction>> {
+    let args = args.into_inner();
+    let transaction = handle.clone().get_transaction(args).await?;
+    Ok(Json(transaction))
+}
+
+#[tokio::main]
+#[post("/list-execution-environment-states", data = "<args>")]
+async fn list_execution_environment_states(
+    args: Json<simulation_args::ListExecutionEnvironmentStates>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<u64>>> {
+    let args = args.into_inner();
+    let ee_indices = handle
+        .clone()
+        .list_execution_environment_states(args)
+        .await?;
+    Ok(Json(ee_indices))
+}
+
+#[tokio::main]
+#[post("/list-execution-environments", data = "<args>")]
+async fn list_execution_environments(
+    args: Json<simulation_args::ListExecutionEnvironments>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<Vec<simulation_args::ExecutionEnvironment>>> {
+    let args = args.into_inner();
+    let ees = handle.clone().list_execution_environments(args).await?;
+    Ok(Json(ees))
+}
+
+#[tokio::main]
+#[post("/metrics", data = "<args>")]
+async fn metrics(
+    args: Json<simulation_args::GetMetrics>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::MetricsSnapshot>> {
+    let args = args.into_inner();
+    let metrics_snapshot = handle.clone().metrics(args).await?;
+    Ok(Json(metrics_snapshot))
+}
+
+#[tokio::main]
+#[post("/reset", data = "<args>")]
+async fn reset(
+    args: Json<simulation_args::Reset>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::ResetSummary>> {
+    let args = args.into_inner();
+    let reset_summary = handle.clone().reset(args).await?;
+    Ok(Json(reset_summary))
+}
+
+#[tokio::main]
+#[post("/shard-exists", data = "<args>")]
+async fn shard_exists(
+    args: Json<simulation_args::ShardExists>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<bool>> {
+    let args = args.into_inner();
+    let exists = handle.clone().shard_exists(args).await?;
+    Ok(Json(exists))
+}
+
+#[tokio::main]
+#[post("/simulate-transaction", data = "<args>")]
+async fn simulate_transaction(
+    args: Json<simulation_args::SimulateTransaction>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::TransactionReceipt>> {
+    let args = args.into_inner();
+    let receipt = handle.clone().simulate_transaction(args).await?;
+    Ok(Json(receipt))
+}
+
+#[tokio::main]
+#[post("/get-simulation-state", data = "<args>")]
+async fn simulation_state(
+    args: Json<simulation_args::GetSimulationState>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::SimulationState>> {
+    let args = args.into_inner();
+    let simulation_state = handle.clone().simulation_state(args).await?;
+    Ok(Json(simulation_state))
+}
+
+#[tokio::main]
+#[post("/update-execution-environment", data = "<args>")]
+async fn update_execution_environment(
+    args: Json<simulation_args::UpdateExecutionEnvironment>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<()>> {
+    let args = args.into_inner();
+    handle.clone().update_execution_environment(args).await?;
+    Ok(Json(()))
+}
+
+/// Long-polls until the EE state on the shard changes, then returns the new state
+/// Returns the unchanged state if it doesn't change within `WATCH_TIMEOUT`, so that a request
+/// doesn't hold a server worker forever.
+#[tokio::main]
+#[post("/watch-execution-environment-state", data = "<args>")]
+async fn watch_execution_environment_state(
+    args: Json<simulation_args::GetExecutionEnvironmentState>,
+    handle: State<Handle>,
+) -> DispatchResult<Json<simulation_args::CustomSerializedReturnTypes>> {
+    let args = args.into_inner();
+    let mut receiver = handle
+        .clone()
+        .watch_execution_environment_state(args)
+        .await?;
+
+    // The first value received is the state at subscription time, so wait for the one after it
+    let current_ee_state_root = receiver.recv().await.ok_or(DispatchError::Terminated)?;
+    let ee_state_root = match time::timeout(WATCH_TIMEOUT, receiver.recv()).await {
+        Ok(ee_state_root) => ee_state_root.ok_or(DispatchError::Terminated)?,
+        Err(_) => current_ee_state_root,
+    };
+    let encodeable_ee_state_root =
+        simulation_args::CustomSerializedReturnTypes::Base64EncodedRoot(ee_state_root);
+    Ok(Json(encodeable_ee_state_root))
+}
@@ -1,8 +1,16 @@
 use eth2_types::eth_spec::EthSpec;
+use futures_util::future::{self, Either, FutureExt};
 pub use simulation::{Error as SimulationError, Simulation};
 pub use simulation_args;
 use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{broadcast, oneshot, watch, Semaphore};
+use tokio::{task, time};
 use types as eth2_types;
 
 /// Shorthand for result types returned from Dispatch.
@@ -11,42 +19,481 @@ pub type Result<V, E = Error> = std::result::Result<V, E>;
 #[derive(Debug, Snafu)]
 pub enum Error {
     /// Error calling "send" on Operation enum value
-    // TODO: Handle these errors a bit more elegantly than is happening currently
-    // (eg. wrap the underlying `std::sync::mpsc::SendError` -- this is a bit of a pain given
-    //  that it uses generics)
+    // No longer returned: an operation that can't be sent means the simulation task has stopped,
+    // which is reported as `Terminated`. Kept so that its code isn't reused.
+    #[snafu(display("unable to send operation to the simulation"))]
     Send,
 
     /// Simulation error
     // Called "Sim" instead of "Simulation" to prevent name collision because Snafu auto-generates
     // context selectors with the same name as the enum values
+    #[snafu(display("{}", source))]
     Sim { source: SimulationError },
     /// Operation was cancelled because the simulation is shutting down.
+    #[snafu(display("operation cancelled: simulation is shutting down"))]
     Terminated,
+    /// The simulation didn't answer the operation within the handle's timeout.
+    #[snafu(display("operation timed out waiting for the simulation"))]
+    Timeout,
+    /// An operation changing the simulation panicked, which stopped the simulation.
+    #[snafu(display("simulation stopped: an operation changing it panicked"))]
+    Panicked,
+}
+
+impl Error {
+    /// A stable numeric code identifying the kind of error
+    ///
+    /// Simulation errors keep the code from `SimulationError::code`, which are all below 100.
+    /// Errors from the dispatch machinery itself are numbered from 100: `Send` is 100,
+    /// `Terminated` is 101, `Timeout` is 102, and `Panicked` is 103.
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::Send => 100,
+            Error::Sim { source } => source.code(),
+            Error::Terminated => 101,
+            Error::Timeout => 102,
+            Error::Panicked => 103,
+        }
+    }
 }
 
 #[derive(Debug)]
 enum Operation {
+    AdvanceSlot(simulation_args::AdvanceSlot, Sender<u64>),
+    CopyEeState(simulation_args::CopyEeState, Sender<Result<()>>),
     CreateExecutionEnvironment(
         simulation_args::CreateExecutionEnvironment,
         Sender<Result<u64>>,
     ),
-    CreateShardBlock(simulation_args::CreateShardBlock, Sender<Result<u64>>),
+    CreateExecutionEnvironments(
+        simulation_args::CreateExecutionEnvironments,
+        Sender<Result<Vec<u64>>>,
+    ),
+    CreateShardBlock(
+        simulation_args::CreateShardBlock,
+        Sender<Result<simulation_args::ShardBlockResult>>,
+    ),
+    DeleteExecutionEnvironment(
+        simulation_args::DeleteExecutionEnvironment,
+        Sender<Result<()>>,
+    ),
+    ExecutionEnvironmentExists(simulation_args::ExecutionEnvironmentExists, Sender<bool>),
+    GetEeAuditLog(
+        simulation_args::GetEeAuditLog,
+        Sender<Vec<simulation_args::EeAuditEntry>>,
+    ),
     GetExecutionEnvironment(
         simulation_args::GetExecutionEnvironment,
         Sender<Result<simulation_args::ExecutionEnvironment>>,
     ),
+    GetExecutionEnvironmentHash(
+        simulation_args::GetExecutionEnvironmentHash,
+        Sender<Result<String>>,
+    ),
+    GetExecutionEnvironmentModuleInfo(
+        simulation_args::GetExecutionEnvironmentModuleInfo,
+        Sender<Result<simulation_args::ModuleInfo>>,
+    ),
     GetExecutionEnvironmentState(
         simulation_args::GetExecutionEnvironmentState,
         Sender<Result<[u8; 32]>>,
     ),
+    GetMetrics(
+        simulation_args::GetMetrics,
+        Sender<simulation_args::MetricsSnapshot>,
+    ),
+    GetOperationLog(
+        simulation_args::GetOperationLog,
+        Sender<Option<simulation_args::OperationLog>>,
+    ),
     GetShardBlock(
         simulation_args::GetShardBlock,
         Sender<Result<simulation_args::ShardBlock>>,
     ),
+    GetShardBlockRange(
+        simulation_args::GetShardBlockRange,
+        Sender<Result<Vec<simulation_args::ShardBlock>>>,
+    ),
+    GetShardBlocks(
+        Vec<simulation_args::GetShardBlock>,
+        Sender<Vec<Result<simulation_args::ShardBlock>>>,
+    ),
+    GetShardChain(
+        simulation_args::GetShardChain,
+        Sender<Result<simulation_args::ShardChainInfo>>,
+    ),
     GetShardState(
         simulation_args::GetShardState,
         Sender<Result<simulation_args::ShardState>>,
     ),
+    GetSimulationState(
+        simulation_args::GetSimulationState,
+        Sender<simulation_args::SimulationState>,
+    ),
+    GetStateRootAt(
+        simulation_args::GetStateRootAt,
+        Sender<simulation_args::StateRootAt>,
+    ),
+    GetTransaction(
+        simulation_args::GetTransaction,
+        Sender<Result<simulation_args::ShardTransaction>>,
+    ),
+    ListExecutionEnvironmentStates(
+        simulation_args::ListExecutionEnvironmentStates,
+        Sender<Result<Vec<u64>>>,
+    ),
+    ListExecutionEnvironments(
+        simulation_args::ListExecutionEnvironments,
+        Sender<Vec<simulation_args::ExecutionEnvironment>>,
+    ),
+    Reset(
+        simulation_args::Reset,
+        Sender<simulation_args::ResetSummary>,
+    ),
+    ShardExists(simulation_args::ShardExists, Sender<bool>),
+    SimulateTransaction(
+        simulation_args::SimulateTransaction,
+        Sender<Result<simulation_args::TransactionReceipt>>,
+    ),
+    UpdateExecutionEnvironment(
+        simulation_args::UpdateExecutionEnvironment,
+        Sender<Result<()>>,
+    ),
+    WatchExecutionEnvironmentState(
+        simulation_args::GetExecutionEnvironmentState,
+        Sender<Result<watch::Receiver<[u8; 32]>>>,
+    ),
+}
+
+impl Operation {
+    /// Copy the operation for an operation log, if it changes the simulation
+    fn to_logged(&self) -> Option<simulation_args::LoggedOperation> {
+        use simulation_args::LoggedOperation;
+
+        match self {
+            Operation::AdvanceSlot(args, _) => Some(LoggedOperation::AdvanceSlot(args.clone())),
+            Operation::CopyEeState(args, _) => Some(LoggedOperation::CopyEeState(args.clone())),
+            Operation::CreateExecutionEnvironment(args, _) => {
+                Some(LoggedOperation::CreateExecutionEnvironment(args.clone()))
+            }
+            Operation::CreateExecutionEnvironments(args, _) => {
+                Some(LoggedOperation::CreateExecutionEnvironments(args.clone()))
+            }
+            Operation::CreateShardBlock(args, _) => {
+                Some(LoggedOperation::CreateShardBlock(args.clone()))
+            }
+            Operation::DeleteExecutionEnvironment(args, _) => {
+                Some(LoggedOperation::DeleteExecutionEnvironment(args.clone()))
+            }
+            Operation::Reset(args, _) => Some(LoggedOperation::Reset(args.clone())),
+            Operation::UpdateExecutionEnvironment(args, _) => {
+                Some(LoggedOperation::UpdateExecutionEnvironment(args.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the operation only reads the simulation, so it can run alongside other reads
+    fn is_read(&self) -> bool {
+        match self {
+            Operation::ExecutionEnvironmentExists(..)
+            | Operation::GetEeAuditLog(..)
+            | Operation::GetExecutionEnvironment(..)
+            | Operation::GetExecutionEnvironmentHash(..)
+            | Operation::GetExecutionEnvironmentModuleInfo(..)
+            | Operation::GetExecutionEnvironmentState(..)
+            | Operation::GetMetrics(..)
+            | Operation::GetShardBlock(..)
+            | Operation::GetShardBlockRange(..)
+            | Operation::GetShardBlocks(..)
+            | Operation::GetShardChain(..)
+            | Operation::GetShardState(..)
+            | Operation::GetSimulationState(..)
+            | Operation::GetStateRootAt(..)
+            | Operation::GetTransaction(..)
+            | Operation::ListExecutionEnvironmentStates(..)
+            | Operation::ListExecutionEnvironments(..)
+            | Operation::ShardExists(..)
+            | Operation::SimulateTransaction(..) => true,
+            // Includes `GetOperationLog` and `WatchExecutionEnvironmentState`, which don't change
+            // the simulation but use the operation log and EE state watchers owned by the writer
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Operation {
+    /// The name of the operation, for tracing
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::AdvanceSlot(..) => "advance_slot",
+            Operation::CopyEeState(..) => "copy_ee_state",
+            Operation::CreateExecutionEnvironment(..) => "create_execution_environment",
+            Operation::CreateExecutionEnvironments(..) => "create_execution_environments",
+            Operation::CreateShardBlock(..) => "create_shard_block",
+            Operation::DeleteExecutionEnvironment(..) => "delete_execution_environment",
+            Operation::ExecutionEnvironmentExists(..) => "execution_environment_exists",
+            Operation::GetEeAuditLog(..) => "get_ee_audit_log",
+            Operation::GetExecutionEnvironment(..) => "get_execution_environment",
+            Operation::GetExecutionEnvironmentHash(..) => "get_execution_environment_hash",
+            Operation::GetExecutionEnvironmentModuleInfo(..) => {
+                "get_execution_environment_module_info"
+            }
+            Operation::GetExecutionEnvironmentState(..) => "get_execution_environment_state",
+            Operation::GetMetrics(..) => "get_metrics",
+            Operation::GetOperationLog(..) => "get_operation_log",
+            Operation::GetShardBlock(..) => "get_shard_block",
+            Operation::GetShardBlockRange(..) => "get_shard_block_range",
+            Operation::GetShardBlocks(..) => "get_shard_blocks",
+            Operation::GetShardChain(..) => "get_shard_chain",
+            Operation::GetShardState(..) => "get_shard_state",
+            Operation::GetSimulationState(..) => "get_simulation_state",
+            Operation::GetStateRootAt(..) => "get_state_root_at",
+            Operation::GetTransaction(..) => "get_transaction",
+            Operation::ListExecutionEnvironmentStates(..) => "list_execution_environment_states",
+            Operation::ListExecutionEnvironments(..) => "list_execution_environments",
+            Operation::Reset(..) => "reset",
+            Operation::ShardExists(..) => "shard_exists",
+            Operation::SimulateTransaction(..) => "simulate_transaction",
+            Operation::UpdateExecutionEnvironment(..) => "update_execution_environment",
+            Operation::WatchExecutionEnvironmentState(..) => "watch_execution_environment_state",
+        }
+    }
+
+    /// The (shard index, EE index) the operation is about, for tracing
+    fn indices(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            Operation::CopyEeState(args, _) => (Some(args.source_shard_index), Some(args.ee_index)),
+            Operation::CreateShardBlock(args, _) => (Some(args.shard_index), None),
+            Operation::DeleteExecutionEnvironment(args, _) => {
+                (None, Some(args.execution_environment_index))
+            }
+            Operation::ExecutionEnvironmentExists(args, _) => (None, Some(args.ee_index)),
+            Operation::GetExecutionEnvironment(args, _) => (None, Some(args.ee_index)),
+            Operation::GetExecutionEnvironmentHash(args, _) => {
+                (None, Some(args.execution_environment_index))
+            }
+            Operation::GetExecutionEnvironmentModuleInfo(args, _) => {
+                (None, Some(args.execution_environment_index))
+            }
+            Operation::GetExecutionEnvironmentState(args, _)
+            | Operation::WatchExecutionEnvironmentState(args, _) => {
+                (Some(args.shard_index), Some(args.ee_index))
+            }
+            Operation::GetShardBlock(args, _) => (Some(args.shard_index), None),
+            Operation::GetShardBlockRange(args, _) => (Some(args.shard_index), None),
+            Operation::GetShardChain(args, _) => (Some(args.shard_index), None),
+            Operation::GetShardState(args, _) => (Some(args.shard_index), None),
+            Operation::GetTransaction(args, _) => (Some(args.shard_index), None),
+            Operation::ListExecutionEnvironmentStates(args, _) => (Some(args.shard_index), None),
+            Operation::ShardExists(args, _) => (Some(args.shard_index), None),
+            Operation::SimulateTransaction(args, _) => {
+                (Some(args.shard_index), Some(args.transaction.ee_index))
+            }
+            Operation::UpdateExecutionEnvironment(args, _) => {
+                (None, Some(args.execution_environment_index))
+            }
+            _ => (None, None),
+        }
+    }
+}
+
+/// Send a reply to the caller of an operation
+///
+/// Every reply channel holds exactly one reply, so sending only fails when the caller stopped
+/// waiting (eg. because it timed out) and dropped its receiver. That only affects the caller, so
+/// it's logged rather than stopping the simulation.
+fn send_reply<V>(reply: &mut Sender<V>, value: V) {
+    if reply.try_send(value).is_err() {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("dropped reply: the caller is no longer waiting for it");
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("Dropped reply: the caller is no longer waiting for it");
+    }
+}
+
+/// Send the result of a fallible operation to its caller, tracing it if it failed
+fn send_result<V>(reply: &mut Sender<Result<V>>, result: Result<V>) {
+    #[cfg(feature = "tracing")]
+    {
+        if let Err(e) = &result {
+            tracing::warn!(code = e.code(), error = %e, "operation failed");
+        }
+    }
+    send_reply(reply, result);
+}
+
+/// Announce that the simulation has started handling operations
+fn announce_running() {
+    #[cfg(feature = "tracing")]
+    tracing::info!(thread = ?std::thread::current().id(), "simulation running");
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("Simulation Running: {:?}", std::thread::current().id());
+}
+
+/// Run a future on its own task
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn spawn_in_current_span<F>(future: F) -> task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future)
+}
+
+/// Run a future on its own task, within the span the caller is in
+/// A bare `tokio::spawn` would run it outside of any span.
+#[cfg(feature = "tracing")]
+pub(crate) fn spawn_in_current_span<F>(future: F) -> task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    use tracing::Instrument;
+    tokio::spawn(future.instrument(tracing::Span::current()))
+}
+
+/// Run an operation on a thread where it can block without holding up other operations
+#[cfg(not(feature = "tracing"))]
+fn spawn_operation<F, R>(op: Operation, run: F) -> task::JoinHandle<R>
+where
+    F: FnOnce(Operation) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    task::spawn_blocking(move || run(op))
+}
+
+/// Run an operation on a thread where it can block without holding up other operations, within a
+/// span naming the operation and the shard and EE it is about
+#[cfg(feature = "tracing")]
+fn spawn_operation<F, R>(op: Operation, run: F) -> task::JoinHandle<R>
+where
+    F: FnOnce(Operation) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    // The span is created here rather than on the blocking thread, so that it's a child of the
+    // span the dispatch loop runs in. The writer is spawned within that span too.
+    let (shard_index, ee_index) = op.indices();
+    let span = tracing::info_span!("operation", name = op.name(), ?shard_index, ?ee_index);
+    task::spawn_blocking(move || {
+        let _enter = span.enter();
+        let result = run(op);
+        tracing::info!("operation handled");
+        result
+    })
+}
+
+/// Run an operation that only reads the simulation and send its reply
+///
+/// Operations that change the simulation, or need more than the simulation itself, are run by the
+/// `Writer` instead, and must not be passed here.
+fn run_read_operation<T: EthSpec>(simulation: &Simulation<T>, op: Operation) {
+    match op {
+        Operation::ExecutionEnvironmentExists(args, mut reply) => {
+            let res = simulation.execution_environment_exists(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::GetEeAuditLog(args, mut reply) => {
+            let res = simulation.get_ee_audit_log(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::GetExecutionEnvironment(args, mut reply) => {
+            let res = simulation.get_execution_environment(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetExecutionEnvironmentHash(args, mut reply) => {
+            let res = simulation.get_execution_environment_hash(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetExecutionEnvironmentModuleInfo(args, mut reply) => {
+            let res = simulation
+                .get_execution_environment_module_info(args)
+                .context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetExecutionEnvironmentState(args, mut reply) => {
+            let res = simulation
+                .get_execution_environment_state(args)
+                .context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetMetrics(args, mut reply) => {
+            let res = simulation.metrics(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::GetShardBlock(args, mut reply) => {
+            let res = simulation.get_shard_block(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetShardBlockRange(args, mut reply) => {
+            let res = simulation.get_shard_block_range(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetShardBlocks(args, mut reply) => {
+            let res = args
+                .into_iter()
+                .map(|a| simulation.get_shard_block(a).context(Sim))
+                .collect();
+            send_reply(&mut reply, res);
+        }
+        Operation::GetShardChain(args, mut reply) => {
+            let res = simulation.get_shard_chain(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetShardState(args, mut reply) => {
+            let res = simulation.get_shard_state(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::GetSimulationState(args, mut reply) => {
+            let res = simulation.simulation_state(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::GetStateRootAt(args, mut reply) => {
+            let res = simulation.get_state_root_at(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::GetTransaction(args, mut reply) => {
+            let res = simulation.get_transaction(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::ListExecutionEnvironmentStates(args, mut reply) => {
+            let res = simulation
+                .list_execution_environment_states(args)
+                .context(Sim);
+            send_result(&mut reply, res);
+        }
+        Operation::ListExecutionEnvironments(args, mut reply) => {
+            let res = simulation.list_execution_environments(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::ShardExists(args, mut reply) => {
+            let res = simulation.shard_exists(args);
+            send_reply(&mut reply, res);
+        }
+        Operation::SimulateTransaction(args, mut reply) => {
+            let res = simulation.simulate_transaction(args).context(Sim);
+            send_result(&mut reply, res);
+        }
+        op => unreachable!("not a read operation: {:?}", op),
+    }
+}
+
+/// Publishes the latest state of one EE on one shard to its subscribers
+#[derive(Debug)]
+struct ExecutionEnvironmentStateWatcher {
+    ee_index: u64,
+    shard_index: u64,
+    state_key: Option<u32>,
+    last_state: [u8; 32],
+    sender: watch::Sender<[u8; 32]>,
+}
+
+impl ExecutionEnvironmentStateWatcher {
+    /// Whether every receiver of the watcher has been dropped
+    fn is_closed(&mut self) -> bool {
+        self.sender.closed().now_or_never().is_some()
+    }
 }
 
 #[derive(Debug)]
@@ -54,151 +501,1512 @@ pub struct Dispatch<T>
 where
     T: EthSpec,
 {
-    simulation: Simulation<T>,
+    simulation: Arc<RwLock<Simulation<T>>>,
     receiver: Receiver<Operation>,
+    writer: Writer<T>,
+    // How many operations that change the simulation can wait for the writer
+    capacity: usize,
+}
+
+/// Runs the operations that change the simulation, one at a time and in the order they arrive
+/// It also owns everything else those operations update: the EE state watchers, the simulation
+/// state and state change subscribers and the operation log.
+#[derive(Debug)]
+struct Writer<T>
+where
+    T: EthSpec,
+{
+    simulation: Arc<RwLock<Simulation<T>>>,
+    ee_state_watchers: Vec<ExecutionEnvironmentStateWatcher>,
+    simulation_state_sender: broadcast::Sender<simulation_args::SimulationState>,
+    state_change_sender: broadcast::Sender<simulation_args::StateChange>,
+    // Every successful operation that changed the simulation (not recorded if None)
+    operation_log: Option<simulation_args::OperationLog>,
 }
 
+/// How many operations can be queued for the simulation before senders have to wait, by default
+const DEFAULT_CAPACITY: usize = 16;
+
+/// How many simulation state changes a subscriber can fall behind before it misses some
+const SIMULATION_STATE_CAPACITY: usize = 16;
+
+/// How many EE state changes a subscriber can fall behind before it misses some
+/// A single block can change the state of many EEs, so this is larger than the simulation state
+/// capacity.
+const STATE_CHANGE_CAPACITY: usize = 256;
+
+/// The simulation lock is only poisoned if an operation panicked while changing the simulation,
+/// which leaves it in an unknown state
+const POISONED: &str = "simulation lock poisoned by a panicking operation";
+
 impl<T: EthSpec> Dispatch<T> {
     pub fn new(simulation: Simulation<T>) -> (Self, Handle) {
-        let (sender, receiver) = channel(1);
-        let handle = Handle { sender };
+        Self::with_capacity(simulation, DEFAULT_CAPACITY)
+    }
+
+    /// Like `new`, but queueing up to `capacity` operations before senders have to wait
+    /// Up to `capacity` more operations that change the simulation can be waiting for the ones
+    /// ahead of them to finish, and up to `capacity` reads can run at once.
+    pub fn with_capacity(simulation: Simulation<T>, capacity: usize) -> (Self, Handle) {
+        let (sender, receiver) = channel(capacity);
+        let (simulation_state_sender, _) = broadcast::channel(SIMULATION_STATE_CAPACITY);
+        let (state_change_sender, _) = broadcast::channel(STATE_CHANGE_CAPACITY);
+        let handle = Handle {
+            sender,
+            simulation_state_sender: simulation_state_sender.clone(),
+            state_change_sender: state_change_sender.clone(),
+            timeout: None,
+        };
 
+        let simulation = Arc::new(RwLock::new(simulation));
+        let writer = Writer {
+            simulation: Arc::clone(&simulation),
+            ee_state_watchers: Vec::new(),
+            simulation_state_sender,
+            state_change_sender,
+            operation_log: None,
+        };
         let me: Dispatch<T> = Dispatch {
             simulation,
             receiver,
+            writer,
+            capacity,
         };
 
         (me, handle)
     }
 
-    pub async fn run(mut self) -> Result<()> {
-        eprintln!("Simulation Running: {:?}", std::thread::current().id());
-        while let Some(op) = self.receiver.recv().await {
-            match op {
-                Operation::CreateExecutionEnvironment(args, mut reply) => {
-                    let res = self
-                        .simulation
-                        .create_execution_environment(args)
-                        .context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+    /// Start recording every successful operation that changes the simulation
+    /// The log can be fetched with `Handle::get_operation_log` and replayed against a new
+    /// simulation with `Simulation::replay` to reproduce this one.
+    pub fn record_operations(&mut self) {
+        self.writer
+            .operation_log
+            .get_or_insert_with(simulation_args::OperationLog::default);
+    }
+
+    /// Handle operations until every handle has been dropped
+    ///
+    /// Operations that only read the simulation run as soon as they arrive, each on its own
+    /// blocking thread, so they don't wait behind queued operations that change the simulation
+    /// and can run at the same time as each other. They still wait for an operation that is
+    /// changing the simulation to finish. Operations that change the simulation run one at a time,
+    /// in the order they arrive. A read can therefore overtake a change sent earlier by another
+    /// caller, but never one whose reply its caller already has. Once too many operations that
+    /// change the simulation are waiting, or too many reads are running, no more operations are
+    /// received until one finishes, so callers wait to send theirs.
+    ///
+    /// Returns `Error::Panicked` if an operation that changes the simulation panicked.
+    pub async fn run(self) -> Result<()> {
+        self.run_until(future::pending::<()>()).await
+    }
+
+    /// Like `run`, but also stops as soon as `shutdown` receives a signal (or its sender is
+    /// dropped), even while handles are still alive
+    /// Operations being handled when the signal arrives finish and get their reply. Operations
+    /// still queued are dropped, so their callers get `Error::Terminated`, as does every later one.
+    pub async fn run_with_shutdown(self, shutdown: oneshot::Receiver<()>) -> Result<()> {
+        self.run_until(shutdown).await
+    }
+
+    async fn run_until<F: Future + Unpin>(self, mut shutdown: F) -> Result<()> {
+        announce_running();
+        let Dispatch {
+            simulation,
+            mut receiver,
+            writer,
+            capacity,
+        } = self;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let (mut writes, queued_writes) = channel(capacity);
+        let writer_run = spawn_in_current_span(writer.run(queued_writes, Arc::clone(&stopping)));
+        let reads = Arc::new(Semaphore::new(capacity));
+
+        loop {
+            let op = match future::select(Box::pin(receiver.recv()), &mut shutdown).await {
+                Either::Left((Some(op), _)) => op,
+                Either::Left((None, _)) => break,
+                Either::Right(_) => {
+                    stopping.store(true, Ordering::SeqCst);
+                    break;
                 }
-                Operation::CreateShardBlock(args, mut reply) => {
-                    let res = self.simulation.create_shard_block(args).context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+            };
+            if op.is_read() {
+                // Once too many reads are running, wait for one to finish, so that they can't
+                // take up every blocking thread (which the writer needs too). Shutting down while
+                // waiting drops the read.
+                let read_permit =
+                    match future::select(Box::pin(reads.acquire()), &mut shutdown).await {
+                        Either::Left((permit, _)) => {
+                            permit.forget();
+                            ReadPermit(Arc::clone(&reads))
+                        }
+                        Either::Right(_) => {
+                            stopping.store(true, Ordering::SeqCst);
+                            break;
+                        }
+                    };
+                let simulation = Arc::clone(&simulation);
+                spawn_operation(op, move |op| {
+                    run_read_operation(&simulation.read().expect(POISONED), op);
+                    drop(read_permit);
+                });
+            } else {
+                // The writer only stops early if an operation panicked, in which case the
+                // operation is dropped and its caller gets `Error::Terminated`. Shutting down while
+                // waiting for room in the writer's queue drops the operation too.
+                match future::select(Box::pin(writes.send(op)), &mut shutdown).await {
+                    Either::Left(_) => {}
+                    Either::Right(_) => {
+                        stopping.store(true, Ordering::SeqCst);
+                        break;
+                    }
                 }
-                Operation::GetExecutionEnvironment(args, mut reply) => {
-                    let res = self.simulation.get_execution_environment(args).context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+            }
+        }
+
+        drop(writes);
+        // The writer only fails if an operation panicked, which its own task has already reported
+        writer_run.await.map_err(|_| Error::Panicked)?;
+
+        Ok(())
+    }
+}
+
+/// Allows one read to run, handing its place back to the dispatch loop when dropped
+/// Permits are handed back on drop rather than when the read returns, so that a read that panics
+/// doesn't use up its place for good.
+struct ReadPermit(Arc<Semaphore>);
+
+impl Drop for ReadPermit {
+    fn drop(&mut self) {
+        self.0.add_permits(1);
+    }
+}
+
+impl<T: EthSpec> Writer<T> {
+    /// Run queued operations until the dispatch loop stops, dropping any still queued if it was
+    /// shut down
+    async fn run(mut self, mut operations: Receiver<Operation>, stopping: Arc<AtomicBool>) {
+        while let Some(op) = operations.recv().await {
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            self = spawn_operation(op, move |op| {
+                self.run_operation(op);
+                self
+            })
+            .await
+            .expect("operation changing the simulation panicked");
+        }
+    }
+
+    fn run_operation(&mut self, op: Operation) {
+        // Only copy the args of operations that may need to be logged
+        let logged_operation = self.operation_log.as_ref().and_then(|_| op.to_logged());
+        let lock = Arc::clone(&self.simulation);
+        let mut simulation = lock.write().expect(POISONED);
+        match op {
+            Operation::AdvanceSlot(args, mut reply) => {
+                let res = simulation.advance_slot(args);
+                self.log_operation(logged_operation);
+                self.notify_simulation_state_subscribers(&simulation);
+                send_reply(&mut reply, res);
+            }
+            Operation::CopyEeState(args, mut reply) => {
+                let res = simulation.copy_ee_state(args).context(Sim);
+                if res.is_ok() {
+                    self.notify_ee_state_watchers(&simulation);
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
+                }
+                send_result(&mut reply, res);
+            }
+            Operation::CreateExecutionEnvironment(args, mut reply) => {
+                let res = simulation.create_execution_environment(args).context(Sim);
+                if res.is_ok() {
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
+                }
+                send_result(&mut reply, res);
+            }
+            Operation::CreateExecutionEnvironments(args, mut reply) => {
+                let res = simulation.create_execution_environments(args).context(Sim);
+                if res.is_ok() {
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
                 }
-                Operation::GetExecutionEnvironmentState(args, mut reply) => {
-                    let res = self
-                        .simulation
-                        .get_execution_environment_state(args)
-                        .context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+                send_result(&mut reply, res);
+            }
+            Operation::CreateShardBlock(args, mut reply) => {
+                let shard_index = args.shard_index;
+                let res = simulation.create_shard_block(args).context(Sim);
+                if let Ok(shard_block_result) = &res {
+                    self.notify_ee_state_watchers(&simulation);
+                    self.notify_state_change_subscribers(shard_index, shard_block_result);
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
                 }
-                Operation::GetShardBlock(args, mut reply) => {
-                    let res = self.simulation.get_shard_block(args).context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+                send_result(&mut reply, res);
+            }
+            Operation::DeleteExecutionEnvironment(args, mut reply) => {
+                let res = simulation.delete_execution_environment(args).context(Sim);
+                if res.is_ok() {
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
                 }
-                Operation::GetShardState(args, mut reply) => {
-                    let res = self.simulation.get_shard_state(args).context(Sim);
-                    reply.send(res).await.map_err(|_| Error::Send)?;
+                send_result(&mut reply, res);
+            }
+            Operation::GetOperationLog(_, mut reply) => {
+                let res = self.operation_log.clone();
+                send_reply(&mut reply, res);
+            }
+            Operation::Reset(args, mut reply) => {
+                let res = simulation.reset(args);
+                self.notify_ee_state_watchers(&simulation);
+                self.log_operation(logged_operation);
+                self.notify_simulation_state_subscribers(&simulation);
+                send_reply(&mut reply, res);
+            }
+            Operation::UpdateExecutionEnvironment(args, mut reply) => {
+                let res = simulation.update_execution_environment(args).context(Sim);
+                if res.is_ok() {
+                    self.log_operation(logged_operation);
+                    self.notify_simulation_state_subscribers(&simulation);
                 }
+                send_result(&mut reply, res);
+            }
+            Operation::WatchExecutionEnvironmentState(args, mut reply) => {
+                let res = self.watch_ee_state(&simulation, args);
+                send_result(&mut reply, res);
             }
+            op => run_read_operation(&simulation, op),
         }
+    }
 
-        Ok(())
+    /// Register a new watcher, failing if the shard or EE doesn't exist
+    fn watch_ee_state(
+        &mut self,
+        simulation: &Simulation<T>,
+        args: simulation_args::GetExecutionEnvironmentState,
+    ) -> Result<watch::Receiver<[u8; 32]>> {
+        let ee_index = args.ee_index;
+        let shard_index = args.shard_index;
+        let state_key = args.state_key;
+        let state = simulation
+            .get_execution_environment_state(args)
+            .context(Sim)?;
+
+        let (sender, receiver) = watch::channel(state);
+        self.prune_ee_state_watchers();
+        self.ee_state_watchers
+            .push(ExecutionEnvironmentStateWatcher {
+                ee_index,
+                shard_index,
+                state_key,
+                last_state: state,
+                sender,
+            });
+
+        Ok(receiver)
+    }
+
+    /// Drop the watchers that no longer have any receivers
+    /// Watchers are otherwise only dropped once their EE state changes, so without this a caller
+    /// that keeps watching an EE whose state doesn't change would add a watcher every time.
+    fn prune_ee_state_watchers(&mut self) {
+        let watchers = std::mem::replace(&mut self.ee_state_watchers, Vec::new());
+        self.ee_state_watchers = watchers
+            .into_iter()
+            .filter_map(|mut watcher| {
+                if watcher.is_closed() {
+                    None
+                } else {
+                    Some(watcher)
+                }
+            })
+            .collect();
+    }
+
+    /// Publish the latest EE state to every watcher whose EE state changed, dropping watchers
+    /// that no longer have any receivers (whether or not their EE state changed)
+    fn notify_ee_state_watchers(&mut self, simulation: &Simulation<T>) {
+        let watchers = std::mem::replace(&mut self.ee_state_watchers, Vec::new());
+        self.ee_state_watchers = watchers
+            .into_iter()
+            .filter_map(|mut watcher| {
+                if watcher.is_closed() {
+                    return None;
+                }
+                let args = simulation_args::GetExecutionEnvironmentState {
+                    ee_index: watcher.ee_index,
+                    shard_index: watcher.shard_index,
+                    state_key: watcher.state_key,
+                };
+                let state = simulation.get_execution_environment_state(args).ok()?;
+                if state != watcher.last_state {
+                    watcher.sender.broadcast(state).ok()?;
+                    watcher.last_state = state;
+                }
+                Some(watcher)
+            })
+            .collect();
+    }
+
+    /// Append an operation that changed the simulation to the operation log, if recording
+    fn log_operation(&mut self, logged_operation: Option<simulation_args::LoggedOperation>) {
+        if let (Some(operation_log), Some(logged_operation)) =
+            (&mut self.operation_log, logged_operation)
+        {
+            operation_log.operations.push(logged_operation);
+        }
+    }
+
+    /// Publish the current simulation state to every subscriber
+    fn notify_simulation_state_subscribers(&self, simulation: &Simulation<T>) {
+        let state = simulation.simulation_state(simulation_args::GetSimulationState {});
+        // Sending only fails when there are no subscribers, which is fine
+        let _ = self.simulation_state_sender.send(state);
+    }
+
+    /// Publish a `StateChange` to every subscriber for each EE state (main state or keyed state
+    /// slot) that was changed by a new block on the shard, in EE index and then state key order
+    fn notify_state_change_subscribers(
+        &self,
+        shard_index: u64,
+        shard_block_result: &simulation_args::ShardBlockResult,
+    ) {
+        // The state of an EE slot before the block is the pre state of its first transaction, and
+        // its state after the block is the post state of its last
+        let mut roots: BTreeMap<(u64, Option<u32>), ([u8; 32], [u8; 32])> = BTreeMap::new();
+        for receipt in &shard_block_result.transaction_results {
+            roots
+                .entry((receipt.ee_index, receipt.state_key))
+                .or_insert((receipt.pre_state, receipt.post_state))
+                .1 = receipt.post_state;
+        }
+        for ((ee_index, state_key), (old_root, new_root)) in roots {
+            if old_root != new_root {
+                let state_change = simulation_args::StateChange {
+                    shard_index,
+                    ee_index,
+                    state_key,
+                    old_root,
+                    new_root,
+                };
+                // Sending only fails when there are no subscribers, which is fine
+                let _ = self.state_change_sender.send(state_change);
+            }
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Handle {
     sender: Sender<Operation>,
+    simulation_state_sender: broadcast::Sender<simulation_args::SimulationState>,
+    state_change_sender: broadcast::Sender<simulation_args::StateChange>,
+    // How long to wait for each operation before giving up (forever if None)
+    timeout: Option<Duration>,
 }
 
 impl Handle {
+    /// Limit how long each operation may take, or wait forever with `None`
+    /// An operation that isn't sent to the simulation and answered in time fails with
+    /// `Error::Timeout`, eg. because the simulation is stuck or isn't running. The timeout only
+    /// stops the caller from waiting: an operation that was already sent still runs.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Send an operation to the simulation and wait for its reply on `receiver`
+    async fn request<V>(&mut self, operation: Operation, mut receiver: Receiver<V>) -> Result<V> {
+        let timeout = self.timeout;
+        let sender = &mut self.sender;
+        let exchange = async move {
+            sender
+                .send(operation)
+                .await
+                .map_err(|_| Error::Terminated)?;
+            receiver.recv().await.context(Terminated)
+        };
+        match timeout {
+            Some(timeout) => time::timeout(timeout, exchange)
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => exchange.await,
+        }
+    }
+
+    /// Receive the new simulation state after every operation that changes the simulation
+    /// Only changes made after subscribing are received. A subscriber that falls too far behind
+    /// gets `RecvError::Lagged` and then continues from the oldest state still kept.
+    pub fn subscribe(&self) -> broadcast::Receiver<simulation_args::SimulationState> {
+        self.simulation_state_sender.subscribe()
+    }
+
+    /// Receive a `StateChange` for every EE whose state is changed by a new shard block
+    /// Only EEs whose state after the block differs from their state before it are reported.
+    /// Changes made in other ways (eg. by `copy_ee_state` or `reset`) aren't. As with `subscribe`,
+    /// only changes made after subscribing are received, and a subscriber that falls too far
+    /// behind gets `RecvError::Lagged`.
+    pub fn subscribe_state_changes(&self) -> broadcast::Receiver<simulation_args::StateChange> {
+        self.state_change_sender.subscribe()
+    }
+
+    pub async fn advance_slot(&mut self, arg: simulation_args::AdvanceSlot) -> Result<u64> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::AdvanceSlot(arg, sender), receiver)
+            .await
+    }
+
+    pub async fn copy_ee_state(&mut self, arg: simulation_args::CopyEeState) -> Result<()> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::CopyEeState(arg, sender), receiver)
+            .await?
+    }
+
     pub async fn create_execution_environment(
         &mut self,
         arg: simulation_args::CreateExecutionEnvironment,
     ) -> Result<u64> {
-        let (sender, mut receiver) = channel(1);
-
-        self.sender
-            .send(Operation::CreateExecutionEnvironment(arg, sender))
-            .await
-            .map_err(|_| Error::Send)?;
+        let (sender, receiver) = channel(1);
+        self.request(Operation::CreateExecutionEnvironment(arg, sender), receiver)
+            .await?
+    }
 
-        receiver.recv().await.context(Terminated)?
+    pub async fn create_execution_environments(
+        &mut self,
+        arg: simulation_args::CreateExecutionEnvironments,
+    ) -> Result<Vec<u64>> {
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::CreateExecutionEnvironments(arg, sender),
+            receiver,
+        )
+        .await?
     }
 
     pub async fn create_shard_block(
         &mut self,
         arg: simulation_args::CreateShardBlock,
-    ) -> Result<u64> {
-        let (sender, mut receiver) = channel(1);
+    ) -> Result<simulation_args::ShardBlockResult> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::CreateShardBlock(arg, sender), receiver)
+            .await?
+    }
 
-        self.sender
-            .send(Operation::CreateShardBlock(arg, sender))
+    pub async fn delete_execution_environment(
+        &mut self,
+        arg: simulation_args::DeleteExecutionEnvironment,
+    ) -> Result<()> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::DeleteExecutionEnvironment(arg, sender), receiver)
+            .await?
+    }
+
+    pub async fn execution_environment_exists(
+        &mut self,
+        arg: simulation_args::ExecutionEnvironmentExists,
+    ) -> Result<bool> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::ExecutionEnvironmentExists(arg, sender), receiver)
             .await
-            .map_err(|_| Error::Send)?;
+    }
 
-        receiver.recv().await.context(Terminated)?
+    pub async fn get_ee_audit_log(
+        &mut self,
+        arg: simulation_args::GetEeAuditLog,
+    ) -> Result<Vec<simulation_args::EeAuditEntry>> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetEeAuditLog(arg, sender), receiver)
+            .await
     }
 
     pub async fn get_execution_environment(
         &mut self,
         arg: simulation_args::GetExecutionEnvironment,
     ) -> Result<simulation_args::ExecutionEnvironment> {
-        let (sender, mut receiver) = channel(1);
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetExecutionEnvironment(arg, sender), receiver)
+            .await?
+    }
 
-        self.sender
-            .send(Operation::GetExecutionEnvironment(arg, sender))
-            .await
-            .map_err(|_| Error::Send)?;
+    pub async fn get_execution_environment_hash(
+        &mut self,
+        arg: simulation_args::GetExecutionEnvironmentHash,
+    ) -> Result<String> {
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::GetExecutionEnvironmentHash(arg, sender),
+            receiver,
+        )
+        .await?
+    }
 
-        receiver.recv().await.context(Terminated)?
+    pub async fn get_execution_environment_module_info(
+        &mut self,
+        arg: simulation_args::GetExecutionEnvironmentModuleInfo,
+    ) -> Result<simulation_args::ModuleInfo> {
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::GetExecutionEnvironmentModuleInfo(arg, sender),
+            receiver,
+        )
+        .await?
     }
 
     pub async fn get_execution_environment_state(
         &mut self,
         arg: simulation_args::GetExecutionEnvironmentState,
     ) -> Result<[u8; 32]> {
-        let (sender, mut receiver) = channel(1);
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::GetExecutionEnvironmentState(arg, sender),
+            receiver,
+        )
+        .await?
+    }
 
-        self.sender
-            .send(Operation::GetExecutionEnvironmentState(arg, sender))
+    pub async fn metrics(
+        &mut self,
+        arg: simulation_args::GetMetrics,
+    ) -> Result<simulation_args::MetricsSnapshot> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetMetrics(arg, sender), receiver)
             .await
-            .map_err(|_| Error::Send)?;
+    }
 
-        receiver.recv().await.context(Terminated)?
+    pub async fn get_operation_log(
+        &mut self,
+        arg: simulation_args::GetOperationLog,
+    ) -> Result<Option<simulation_args::OperationLog>> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetOperationLog(arg, sender), receiver)
+            .await
     }
 
     pub async fn get_shard_block(
         &mut self,
         arg: simulation_args::GetShardBlock,
     ) -> Result<simulation_args::ShardBlock> {
-        let (sender, mut receiver) = channel(1);
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetShardBlock(arg, sender), receiver)
+            .await?
+    }
 
-        self.sender
-            .send(Operation::GetShardBlock(arg, sender))
+    pub async fn get_shard_block_range(
+        &mut self,
+        arg: simulation_args::GetShardBlockRange,
+    ) -> Result<Vec<simulation_args::ShardBlock>> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetShardBlockRange(arg, sender), receiver)
+            .await?
+    }
+
+    /// Get several shard blocks at once, with results in the same order as `args`
+    ///
+    /// The reads are batched into a single `Operation`, so fetching many blocks costs one round
+    /// trip through the dispatch channel instead of one per block. Each block is looked up
+    /// independently, so a bad index only fails its own entry.
+    pub async fn get_shard_blocks(
+        &mut self,
+        args: Vec<simulation_args::GetShardBlock>,
+    ) -> Result<Vec<Result<simulation_args::ShardBlock>>> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetShardBlocks(args, sender), receiver)
             .await
-            .map_err(|_| Error::Send)?;
+    }
 
-        receiver.recv().await.context(Terminated)?
+    pub async fn get_shard_chain(
+        &mut self,
+        arg: simulation_args::GetShardChain,
+    ) -> Result<simulation_args::ShardChainInfo> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetShardChain(arg, sender), receiver)
+            .await?
     }
 
     pub async fn get_shard_state(
         &mut self,
         arg: simulation_args::GetShardState,
     ) -> Result<simulation_args::ShardState> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetShardState(arg, sender), receiver)
+            .await?
+    }
+
+    pub async fn simulation_state(
+        &mut self,
+        arg: simulation_args::GetSimulationState,
+    ) -> Result<simulation_args::SimulationState> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetSimulationState(arg, sender), receiver)
+            .await
+    }
+
+    pub async fn get_state_root_at(
+        &mut self,
+        arg: simulation_args::GetStateRootAt,
+    ) -> Result<simulation_args::StateRootAt> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetStateRootAt(arg, sender), receiver)
+            .await
+    }
+
+    pub async fn get_transaction(
+        &mut self,
+        arg: simulation_args::GetTransaction,
+    ) -> Result<simulation_args::ShardTransaction> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::GetTransaction(arg, sender), receiver)
+            .await?
+    }
+
+    pub async fn list_execution_environment_states(
+        &mut self,
+        arg: simulation_args::ListExecutionEnvironmentStates,
+    ) -> Result<Vec<u64>> {
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::ListExecutionEnvironmentStates(arg, sender),
+            receiver,
+        )
+        .await?
+    }
+
+    pub async fn list_execution_environments(
+        &mut self,
+        arg: simulation_args::ListExecutionEnvironments,
+    ) -> Result<Vec<simulation_args::ExecutionEnvironment>> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::ListExecutionEnvironments(arg, sender), receiver)
+            .await
+    }
+
+    pub async fn reset(
+        &mut self,
+        arg: simulation_args::Reset,
+    ) -> Result<simulation_args::ResetSummary> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::Reset(arg, sender), receiver).await
+    }
+
+    pub async fn shard_exists(&mut self, arg: simulation_args::ShardExists) -> Result<bool> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::ShardExists(arg, sender), receiver)
+            .await
+    }
+
+    pub async fn simulate_transaction(
+        &mut self,
+        arg: simulation_args::SimulateTransaction,
+    ) -> Result<simulation_args::TransactionReceipt> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::SimulateTransaction(arg, sender), receiver)
+            .await?
+    }
+
+    pub async fn update_execution_environment(
+        &mut self,
+        arg: simulation_args::UpdateExecutionEnvironment,
+    ) -> Result<()> {
+        let (sender, receiver) = channel(1);
+        self.request(Operation::UpdateExecutionEnvironment(arg, sender), receiver)
+            .await?
+    }
+
+    /// Subscribe to the state of an EE on a shard
+    ///
+    /// The receiver starts out holding the current state and is updated whenever executing a shard
+    /// block changes it. Rapid changes are coalesced, so a slow reader only sees the latest state.
+    pub async fn watch_execution_environment_state(
+        &mut self,
+        arg: simulation_args::GetExecutionEnvironmentState,
+    ) -> Result<watch::Receiver<[u8; 32]>> {
+        let (sender, receiver) = channel(1);
+        self.request(
+            Operation::WatchExecutionEnvironmentState(arg, sender),
+            receiver,
+        )
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+    use types::eth_spec::MainnetEthSpec;
+
+    #[tokio::test]
+    async fn concurrent_operations_do_not_deadlock() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, handle) = Dispatch::with_capacity(simulation, 2);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        // Queue more operations than the channel can hold at once
+        let creates = (0..8).map(|_| {
+            let mut handle = handle.clone();
+            async move {
+                let ee = simulation_args::ExecutionEnvironment {
+                    initial_state: [0; 32],
+                    wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+                    index: None,
+                };
+                handle
+                    .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                        ee,
+                        wasm_path: None,
+                        idempotency_key: None,
+                    })
+                    .await
+            }
+        });
+        let mut ee_indices = future::join_all(creates)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<u64>>>()
+            .unwrap();
+        ee_indices.sort();
+        assert_eq!(ee_indices, (0..8).collect::<Vec<u64>>());
+
+        // Dropping the last handle stops the dispatch loop
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    /// Takes a while to accept each transaction, making every shard block a slow write
+    #[derive(Debug)]
+    struct SlowValidator;
+
+    impl simulation::TransactionValidator for SlowValidator {
+        fn validate(&self, _ee_index: u64, _data: &[u8]) -> simulation::Result<()> {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_do_not_wait_for_queued_writes() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_validator(Some(Box::new(SlowValidator)));
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        // Queue several slow writes...
+        let writes = (0..3).map(|_| {
+            let mut handle = handle.clone();
+            let shard_block = simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction {
+                    data: vec![],
+                    ee_index,
+                    condition: None,
+                    state_key: None,
+                }],
+                block_hash: None,
+                parent_hash: None,
+            };
+            tokio::spawn(async move {
+                handle
+                    .create_shard_block(simulation_args::CreateShardBlock {
+                        shard_index: 0,
+                        shard_block,
+                        idempotency_key: None,
+                    })
+                    .await
+            })
+        });
+        let writes: Vec<_> = writes.collect();
+        time::delay_for(Duration::from_millis(20)).await;
+
+        // ...then read while they're still running: the read only waits for the write in
+        // progress, not for the ones queued behind it
+        let simulation_state = handle
+            .simulation_state(simulation_args::GetSimulationState {})
+            .await
+            .unwrap();
+        assert!(simulation_state.shard_block_counts[0] < 3);
+
+        for write in future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+        let simulation_state = handle
+            .simulation_state(simulation_args::GetSimulationState {})
+            .await
+            .unwrap();
+        assert_eq!(simulation_state.shard_block_counts[0], 3);
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_full_write_queue_makes_callers_wait() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_validator(Some(Box::new(SlowValidator)));
+        let (dispatch, mut handle) = Dispatch::with_capacity(simulation, 1);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        // One slow write runs, one waits for the writer, one waits to be handed to the writer
+        // and one waits in the handle's channel, leaving the rest waiting to be sent
+        let writes = (0..5).map(|_| {
+            let mut handle = handle.clone();
+            let shard_block = simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction::from_data(ee_index, &[])],
+                block_hash: None,
+                parent_hash: None,
+            };
+            tokio::spawn(async move {
+                handle
+                    .create_shard_block(simulation_args::CreateShardBlock {
+                        shard_index: 0,
+                        shard_block,
+                        idempotency_key: None,
+                    })
+                    .await
+            })
+        });
+        let writes: Vec<_> = writes.collect();
+        time::delay_for(Duration::from_millis(50)).await;
+
+        // A new operation can't be sent until the queued writes make room for it
+        let (sender, _receiver) = channel(1);
+        let operation =
+            Operation::GetSimulationState(simulation_args::GetSimulationState {}, sender);
+        assert!(handle.sender.try_send(operation).is_err());
+        let mut waiting_handle = handle.clone();
+        waiting_handle.set_timeout(Some(Duration::from_millis(20)));
+        match waiting_handle
+            .simulation_state(simulation_args::GetSimulationState {})
+            .await
+        {
+            Err(Error::Timeout) => {}
+            other => panic!("expected Timeout, got {:?}", other),
+        }
+
+        for write in future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+        let simulation_state = handle
+            .simulation_state(simulation_args::GetSimulationState {})
+            .await
+            .unwrap();
+        assert_eq!(simulation_state.shard_block_counts[0], 5);
+
+        drop(waiting_handle);
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_beyond_the_capacity_make_callers_wait() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_validator(Some(Box::new(SlowValidator)));
+        let (dispatch, mut handle) = Dispatch::with_capacity(simulation, 1);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        // A slow write holds up the reads sent after it
+        let mut write_handle = handle.clone();
+        let write = tokio::spawn(async move {
+            let shard_block = simulation_args::ShardBlock {
+                transactions: vec![simulation_args::ShardTransaction::from_data(ee_index, &[])],
+                block_hash: None,
+                parent_hash: None,
+            };
+            write_handle
+                .create_shard_block(simulation_args::CreateShardBlock {
+                    shard_index: 0,
+                    shard_block,
+                    idempotency_key: None,
+                })
+                .await
+        });
+        time::delay_for(Duration::from_millis(20)).await;
+        let reads: Vec<_> = (0..2)
+            .map(|_| {
+                let mut handle = handle.clone();
+                tokio::spawn(async move {
+                    handle
+                        .simulation_state(simulation_args::GetSimulationState {})
+                        .await
+                })
+            })
+            .collect();
+        time::delay_for(Duration::from_millis(20)).await;
+
+        // One read runs, the other waits for it to finish before being handed to a thread, and
+        // the handle's channel only has room for one more operation
         let (sender, mut receiver) = channel(1);
+        let operation =
+            Operation::GetSimulationState(simulation_args::GetSimulationState {}, sender);
+        assert!(handle.sender.try_send(operation).is_ok());
+        let (sender, _) = channel(1);
+        let operation =
+            Operation::GetSimulationState(simulation_args::GetSimulationState {}, sender);
+        assert!(handle.sender.try_send(operation).is_err());
+
+        write.await.unwrap().unwrap();
+        for read in future::join_all(reads).await {
+            assert_eq!(read.unwrap().unwrap().shard_block_counts[0], 1);
+        }
+        assert!(receiver.recv().await.is_some());
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    /// Panics when asked to accept a transaction
+    #[derive(Debug)]
+    struct PanickingValidator;
+
+    impl simulation::TransactionValidator for PanickingValidator {
+        fn validate(&self, _ee_index: u64, _data: &[u8]) -> simulation::Result<()> {
+            panic!("validator bug");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_write_stops_the_simulation_with_an_error() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        simulation.set_transaction_validator(Some(Box::new(PanickingValidator)));
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![simulation_args::ShardTransaction::from_data(ee_index, &[])],
+            block_hash: None,
+            parent_hash: None,
+        };
+        match handle
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block,
+                idempotency_key: None,
+            })
+            .await
+        {
+            Err(Error::Terminated) => {}
+            other => panic!("expected Terminated, got {:?}", other),
+        }
+
+        // The dispatch loop still stops cleanly, reporting the panic
+        drop(handle);
+        match dispatch_run.await.unwrap() {
+            Err(Error::Panicked) => {}
+            other => panic!("expected Panicked, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_replies_do_not_stop_the_simulation() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        // A caller that gives up before its reply arrives
+        let (sender, receiver) = channel(1);
+        drop(receiver);
+        handle
+            .sender
+            .send(Operation::ShardExists(
+                simulation_args::ShardExists { shard_index: 0 },
+                sender,
+            ))
+            .await
+            .unwrap();
+
+        let shard_exists = handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+            .unwrap();
+        assert!(shard_exists);
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn operations_on_a_stopped_simulation_are_terminated() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        drop(dispatch);
+
+        match handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+        {
+            Err(Error::Terminated) => {}
+            other => panic!("expected Terminated, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_simulation_state_changes() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+        let mut subscriber = handle.subscribe();
+
+        // Reading the simulation doesn't publish anything...
+        handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+            .unwrap();
+
+        // ...but changing it does
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+        let simulation_state = subscriber.recv().await.unwrap();
+        assert_eq!(simulation_state.num_execution_environments, 1);
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+
+        // The subscription ends along with the simulation
+        assert!(subscriber.recv().await.is_err());
+    }
+
+    // Returns the (initial state, transaction data, expected post state) of the scout bazaar example
+    fn bazaar_test_vector() -> ([u8; 32], Vec<u8>, [u8; 32]) {
+        let initial_state = hex::FromHex::from_hex(
+            "22ea9b045f8792170b45ec629c98e1b92bc6a19cd8d0e9f37baaadf2564142f4",
+        )
+        .unwrap();
+        let expected_post_state = hex::FromHex::from_hex(
+            "29505fd952857b5766c759bcb4af58eb8df5a91043540c1398dd987a503127fc",
+        )
+        .unwrap();
+        let data = hex::decode("5c0000005000000001000000000000000000000000000000000000000000000000000000000000000000000000000000020000000000000001010101010101010101010101010101010101010101010101010101010101010400000000000000").unwrap();
+        (initial_state, data, expected_post_state)
+    }
+
+    #[tokio::test]
+    async fn blocks_publish_ee_state_changes() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+        let mut subscriber = handle.subscribe_state_changes();
+
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+
+        let bazaar = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let bazaar_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: bazaar,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+        let do_nothing = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let do_nothing_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: do_nothing,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        // Only the EE states that change are reported, with each keyed state slot separately
+        let keyed_transaction = simulation_args::ShardTransaction {
+            state_key: Some(7),
+            ..simulation_args::ShardTransaction::from_data(bazaar_index, &data)
+        };
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![
+                simulation_args::ShardTransaction::from_data(do_nothing_index, &[]),
+                keyed_transaction,
+                simulation_args::ShardTransaction::from_data(bazaar_index, &data),
+            ],
+            block_hash: None,
+            parent_hash: None,
+        };
+        handle
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 2,
+                shard_block,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            simulation_args::StateChange {
+                shard_index: 2,
+                ee_index: bazaar_index,
+                state_key: None,
+                old_root: initial_state,
+                new_root: expected_post_state,
+            }
+        );
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            simulation_args::StateChange {
+                shard_index: 2,
+                ee_index: bazaar_index,
+                state_key: Some(7),
+                old_root: initial_state,
+                new_root: expected_post_state,
+            }
+        );
+        assert!(subscriber.try_recv().is_err());
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
 
-        self.sender
-            .send(Operation::GetShardState(arg, sender))
+    #[tokio::test]
+    async fn watchers_see_new_ee_states() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let (initial_state, data, expected_post_state) = bazaar_test_vector();
+
+        let bazaar = simulation_args::ExecutionEnvironment {
+            initial_state,
+            wasm_code: include_bytes!("../../tests/phase2_bazaar.wasm").to_vec(),
+            index: None,
+        };
+        let bazaar_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: bazaar,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        let mut receiver = handle
+            .watch_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index: bazaar_index,
+                shard_index: 2,
+                state_key: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(receiver.recv().await, Some(initial_state));
+
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![simulation_args::ShardTransaction::from_data(
+                bazaar_index,
+                &data,
+            )],
+            block_hash: None,
+            parent_hash: None,
+        };
+        handle
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 2,
+                shard_block,
+                idempotency_key: None,
+            })
             .await
-            .map_err(|_| Error::Send)?;
+            .unwrap();
+        assert_eq!(receiver.recv().await, Some(expected_post_state));
+
+        // Watchers are dropped with the simulation, ending their receivers
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[test]
+    fn dropped_ee_state_watchers_are_removed() {
+        let mut simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let ee_index = simulation
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee: simulation_args::ExecutionEnvironment {
+                    initial_state: [0; 32],
+                    wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+                    index: None,
+                },
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .unwrap();
+        let (mut dispatch, _handle) = Dispatch::new(simulation);
+        let lock = Arc::clone(&dispatch.simulation);
+        let simulation = lock.read().unwrap();
+        let args = || simulation_args::GetExecutionEnvironmentState {
+            ee_index,
+            shard_index: 0,
+            state_key: None,
+        };
+
+        let receiver = dispatch.writer.watch_ee_state(&simulation, args()).unwrap();
+        assert_eq!(dispatch.writer.ee_state_watchers.len(), 1);
+
+        // Registering a new watcher removes the one whose receiver was dropped...
+        drop(receiver);
+        let receiver = dispatch.writer.watch_ee_state(&simulation, args()).unwrap();
+        assert_eq!(dispatch.writer.ee_state_watchers.len(), 1);
+
+        // ...as does any notification, even though the EE's state didn't change
+        drop(receiver);
+        dispatch.writer.notify_ee_state_watchers(&simulation);
+        assert!(dispatch.writer.ee_state_watchers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn watching_a_missing_ee_fails() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let res = handle
+            .watch_execution_environment_state(simulation_args::GetExecutionEnvironmentState {
+                ee_index: 0,
+                shard_index: 0,
+                state_key: None,
+            })
+            .await;
+        match res {
+            Err(Error::Sim { .. }) => {}
+            other => panic!("expected Sim, got {:?}", other.map(|_| ())),
+        }
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn operations_are_traced() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        // Only logs within the test's span are checked, so this also checks that the operations
+        // of both reads and writes are traced within the span the dispatch loop runs in
+        let dispatch_run = spawn_in_current_span(dispatch.run());
+
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![],
+            block_hash: None,
+            parent_hash: None,
+        };
+        handle
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 0,
+                shard_block,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+        handle
+            .get_shard_state(simulation_args::GetShardState { shard_index: 1000 })
+            .await
+            .unwrap_err();
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+
+        assert!(logs_contain("create_shard_block"));
+        assert!(logs_contain("get_shard_state"));
+        assert!(logs_contain("operation handled"));
+        assert!(logs_contain("operation failed"));
+    }
 
-        receiver.recv().await.context(Terminated)?
+    #[test]
+    fn error_messages_describe_the_error() {
+        let sim_error = Error::Sim {
+            source: SimulationError::Frozen { ee_index: 3 },
+        };
+        assert_eq!(sim_error.to_string(), "execution environment 3 is frozen");
+        assert!(Error::Send.to_string().contains("unable to send"));
+        assert_eq!(
+            Error::Terminated.to_string(),
+            "operation cancelled: simulation is shutting down"
+        );
+        assert!(Error::Timeout.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn recorded_operations_can_be_replayed() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (mut dispatch, mut handle) = Dispatch::new(simulation);
+        dispatch.record_operations();
+        let dispatch_run = tokio::spawn(dispatch.run());
+
+        let ee = simulation_args::ExecutionEnvironment {
+            initial_state: [0; 32],
+            wasm_code: include_bytes!("../../tests/do_nothing.wasm").to_vec(),
+            index: None,
+        };
+        let ee_index = handle
+            .create_execution_environment(simulation_args::CreateExecutionEnvironment {
+                ee,
+                wasm_path: None,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+        let shard_block = simulation_args::ShardBlock {
+            transactions: vec![simulation_args::ShardTransaction {
+                data: vec![],
+                ee_index,
+                condition: None,
+                state_key: None,
+            }],
+            block_hash: None,
+            parent_hash: None,
+        };
+        handle
+            .create_shard_block(simulation_args::CreateShardBlock {
+                shard_index: 1,
+                shard_block,
+                idempotency_key: None,
+            })
+            .await
+            .unwrap();
+
+        // Failed and read-only operations aren't recorded
+        handle
+            .get_execution_environment(simulation_args::GetExecutionEnvironment { ee_index: 5 })
+            .await
+            .unwrap_err();
+
+        let operation_log = handle
+            .get_operation_log(simulation_args::GetOperationLog {})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(operation_log.operations.len(), 2);
+
+        let mut replayed: Simulation<MainnetEthSpec> = Simulation::new();
+        replayed.replay(&operation_log).unwrap();
+        let simulation_state = handle
+            .simulation_state(simulation_args::GetSimulationState {})
+            .await
+            .unwrap();
+        assert_eq!(
+            replayed.simulation_state(simulation_args::GetSimulationState {}),
+            simulation_state
+        );
+        assert_eq!(simulation_state.shard_block_counts[1], 1);
+
+        drop(handle);
+        dispatch_run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn operations_time_out_when_the_simulation_is_not_running() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        // The simulation stays alive, but never runs, so operations are never answered
+        let (_dispatch, mut handle) = Dispatch::new(simulation);
+        handle.set_timeout(Some(Duration::from_millis(10)));
+
+        match handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+        {
+            Err(Error::Timeout) => {}
+            other => panic!("expected Timeout, got {:?}", other),
+        }
+        assert_eq!(Error::Timeout.code(), 102);
+        assert_eq!(Error::Panicked.code(), 103);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_the_simulation_while_handles_are_alive() {
+        let simulation: Simulation<MainnetEthSpec> = Simulation::new();
+        let (dispatch, mut handle) = Dispatch::new(simulation);
+        let (shutdown_sender, shutdown) = oneshot::channel();
+        let dispatch_run = tokio::spawn(dispatch.run_with_shutdown(shutdown));
+
+        // Operations are handled as usual until the shutdown signal
+        let shard_exists = handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+            .unwrap();
+        assert!(shard_exists);
+
+        shutdown_sender.send(()).unwrap();
+        dispatch_run.await.unwrap().unwrap();
+
+        match handle
+            .shard_exists(simulation_args::ShardExists { shard_index: 0 })
+            .await
+        {
+            Err(Error::Terminated) => {}
+            other => panic!("expected Terminated, got {:?}", other),
+        }
     }
 }
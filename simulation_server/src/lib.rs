@@ -115,7 +115,8 @@ impl<T: EthSpec> SimulationServer<T> {
         let simulation: Simulation<T> = Simulation::new();
         let (dispatch, handle) = dispatch::Dispatch::new(simulation);
 
-        let eth_run = tokio::spawn(dispatch.run().map(|x| x.context(error::Dispatch)));
+        let eth_run =
+            dispatch::spawn_in_current_span(dispatch.run().map(|x| x.context(error::Dispatch)));
         let api_run =
             tokio::task::spawn_blocking(move || api::run(&self, handle).context(error::Api));
 